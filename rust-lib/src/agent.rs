@@ -0,0 +1,360 @@
+// Server-side agent loop: execute MCP tool calls and re-prompt the provider until it answers
+use crate::ai::{
+    ChatCompletionRequest, ChatCompletionResponse, ChatMessage, FinishReason, FunctionCall, Role,
+    Tool, ToolCall, ToolChoice, ToolChoiceMode, Usage,
+};
+use crate::mcp::McpRegistry;
+use crate::provider::{ChatProvider, ProviderError};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const DEFAULT_MAX_TOOL_ITERATIONS: u32 = 5;
+
+// Who runs the tool calls the model asks for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolExecution {
+    // Return `finish_reason: "tool_calls"` and let the client run the tools (OpenAI default)
+    #[default]
+    Client,
+    // Run the tools through the MCP registry and keep prompting until a final answer
+    Server,
+}
+
+// One round of the loop: the assistant turn that requested tools and the tool results fed back
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AgentStep {
+    pub iteration: u32,
+    pub assistant: ChatMessage,
+    pub tool_results: Vec<ChatMessage>,
+}
+
+// Calls to MCP tools are executed and fed back until the model answers. Calls to
+// `client_tools` (which shadow MCP tools of the same name) end the loop: the response carries
+// them with `finish_reason: "tool_calls"`, and MCP calls of the same turn are answered in the
+// trace only.
+pub async fn run_agent_loop(
+    provider: &dyn ChatProvider,
+    registry: &McpRegistry,
+    mut request: ChatCompletionRequest,
+    client_tools: &[String],
+    max_iterations: u32,
+) -> Result<ChatCompletionResponse, ProviderError> {
+    let mut trace = Vec::new();
+    let mut usage = Usage::default();
+
+    loop {
+        let mut response = crate::structured::complete_structured(provider, &request).await?;
+        usage.prompt_tokens += response.usage.prompt_tokens;
+        usage.completion_tokens += response.usage.completion_tokens;
+        usage.total_tokens += response.usage.total_tokens;
+
+        let assistant = response
+            .choices
+            .first()
+            .map(|choice| choice.message.clone())
+            .filter(|message| message.tool_calls.as_ref().is_some_and(|c| !c.is_empty()));
+
+        let Some(assistant) = assistant else {
+            return Ok(finish(response, usage, trace));
+        };
+        if trace.len() as u32 >= max_iterations {
+            log::warn!(
+                "⚠️ Agent loop stopped after {} tool iterations; returning pending tool calls",
+                max_iterations
+            );
+            return Ok(finish(response, usage, trace));
+        }
+        let iteration = trace.len() as u32 + 1;
+
+        let tool_calls = assistant.tool_calls.clone().unwrap_or_default();
+        let (server_calls, client_calls): (Vec<ToolCall>, Vec<ToolCall>) =
+            tool_calls.into_iter().partition(|call| {
+                registry.has_tool(&call.function.name)
+                    && !client_tools.contains(&call.function.name)
+            });
+        let results = registry
+            .execute_tool_calls(&server_calls, request.allows_parallel_tool_calls())
+            .await;
+        let tool_results: Vec<ChatMessage> = server_calls
+            .iter()
+            .zip(results)
+            .map(|(tool_call, result)| tool_result_message(tool_call, result))
+            .collect();
+
+        log::info!(
+            "🔁 Agent iteration {} executed {} tool call(s)",
+            iteration,
+            tool_results.len()
+        );
+
+        if !client_calls.is_empty() {
+            log::info!(
+                "📤 Agent loop returning {} client tool call(s)",
+                client_calls.len()
+            );
+            if !tool_results.is_empty() {
+                trace.push(AgentStep {
+                    iteration,
+                    assistant,
+                    tool_results,
+                });
+            }
+            if let Some(choice) = response.choices.first_mut() {
+                choice.message.tool_calls = Some(client_calls);
+                choice.finish_reason = FinishReason::ToolCalls;
+            }
+            return Ok(finish(response, usage, trace));
+        }
+
+        request.messages.push(assistant.clone());
+        request.messages.extend(tool_results.iter().cloned());
+        // A forced tool_choice only applies to the first turn, otherwise the model could
+        // never produce a final answer
+        if matches!(
+            request.tool_choice_mode(),
+            ToolChoiceMode::Required | ToolChoiceMode::Function(_)
+        ) {
+            request.tool_choice = Some(ToolChoice::Auto("auto".to_string()));
+        }
+        trace.push(AgentStep {
+            iteration,
+            assistant,
+            tool_results,
+        });
+    }
+}
+
+// Failed tools still answer the call, so the model can see the error and react to it
+fn tool_result_message(tool_call: &ToolCall, result: Result<String, String>) -> ChatMessage {
+    let content = result.unwrap_or_else(|e| {
+        log::warn!("⚠️ Tool '{}' failed: {}", tool_call.function.name, e);
+        format!("Error: {}", e)
+    });
+    ChatMessage {
+        role: Role::Tool,
+        content: Some(content.into()),
+        tool_calls: None,
+        tool_call_id: Some(tool_call.id.clone()),
+        name: Some(tool_call.function.name.clone()),
+    }
+}
+
+fn finish(
+    mut response: ChatCompletionResponse,
+    usage: Usage,
+    trace: Vec<AgentStep>,
+) -> ChatCompletionResponse {
+    response.usage = usage;
+    response.agent_trace = Some(trace);
+    response
+}
+
+// Tools for a streamed run: the client's own tools plus every MCP tool they do not shadow
+pub(crate) fn with_mcp_tools(client_tools: Vec<Tool>, registry: &McpRegistry) -> Option<Vec<Tool>> {
+    let mut tools = client_tools;
+    let mcp_tools: Vec<Tool> = registry
+        .get_available_tools()
+        .into_iter()
+        .filter(|mcp| !tools.iter().any(|t| t.function.name == mcp.function.name))
+        .collect();
+    tools.extend(mcp_tools);
+    (!tools.is_empty()).then_some(tools)
+}
+
+// Progress of a streamed agent run, rendered by the event protocols (AG-UI, AI SDK data stream)
+#[derive(Debug, Clone)]
+pub enum AgentEvent {
+    // A provider turn begins; steps count from 0
+    StepStarted {
+        step: u32,
+    },
+    TextDelta(String),
+    ToolCallStarted {
+        id: String,
+        name: String,
+    },
+    ToolCallArgs {
+        id: String,
+        delta: String,
+    },
+    // All arguments of the call have been streamed
+    ToolCallReady(ToolCall),
+    ToolResult {
+        call: ToolCall,
+        content: String,
+    },
+    // A provider turn ended; `continued` when its tool results are fed into another turn.
+    // `served_by` names the upstream a fallback chain picked for the turn.
+    StepFinished {
+        step: u32,
+        finish_reason: FinishReason,
+        usage: Usage,
+        continued: bool,
+        served_by: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentOutcome {
+    // The model answered, or the run stopped at the iteration limit
+    Finished,
+    // The model called tools only the client can run; it continues with a new request
+    AwaitingClientTools,
+}
+
+// Streamed counterpart of `run_agent_loop`: every provider turn is streamed through `emit`,
+// MCP tool calls are executed and fed back, and calls to `client_tools` (which shadow MCP tools
+// of the same name) end the run. `emit` returns false once the client is gone.
+pub async fn stream_agent_loop(
+    provider: &dyn ChatProvider,
+    registry: &McpRegistry,
+    request: &mut ChatCompletionRequest,
+    client_tools: &[String],
+    max_iterations: u32,
+    emit: &mut (dyn FnMut(AgentEvent) -> bool + Send),
+) -> Result<AgentOutcome, ProviderError> {
+    let mut step = 0;
+    loop {
+        emit(AgentEvent::StepStarted { step });
+        let (assistant, finish_reason, usage, served_by) =
+            stream_turn(provider, request, emit).await?;
+
+        let tool_calls = assistant.tool_calls.clone().unwrap_or_default();
+        let (server_calls, client_calls): (Vec<ToolCall>, Vec<ToolCall>) =
+            tool_calls.into_iter().partition(|call| {
+                registry.has_tool(&call.function.name)
+                    && !client_tools.contains(&call.function.name)
+            });
+        let continued =
+            client_calls.is_empty() && !server_calls.is_empty() && step < max_iterations;
+
+        if step < max_iterations {
+            let results = registry
+                .execute_tool_calls(&server_calls, request.allows_parallel_tool_calls())
+                .await;
+            request.messages.push(assistant);
+            for (call, result) in server_calls.iter().zip(results) {
+                let message = tool_result_message(call, result);
+                let content = message
+                    .content
+                    .as_ref()
+                    .map(|c| c.to_text())
+                    .unwrap_or_default();
+                emit(AgentEvent::ToolResult {
+                    call: call.clone(),
+                    content,
+                });
+                request.messages.push(message);
+            }
+        } else if !server_calls.is_empty() {
+            log::warn!(
+                "⚠️ Agent run stopped after {} tool iterations; returning pending tool calls",
+                max_iterations
+            );
+        }
+
+        let alive = emit(AgentEvent::StepFinished {
+            step,
+            finish_reason,
+            usage,
+            continued,
+            served_by,
+        });
+        if !client_calls.is_empty() {
+            return Ok(AgentOutcome::AwaitingClientTools);
+        }
+        if !continued {
+            return Ok(AgentOutcome::Finished);
+        }
+        if !alive {
+            log::info!("🔌 Client disconnected; stopping the agent run");
+            return Ok(AgentOutcome::Finished);
+        }
+        if matches!(
+            request.tool_choice_mode(),
+            ToolChoiceMode::Required | ToolChoiceMode::Function(_)
+        ) {
+            request.tool_choice = Some(ToolChoice::Auto("auto".to_string()));
+        }
+        step += 1;
+    }
+}
+
+// One provider turn: forward its deltas as events and assemble the assistant message
+async fn stream_turn(
+    provider: &dyn ChatProvider,
+    request: &ChatCompletionRequest,
+    emit: &mut (dyn FnMut(AgentEvent) -> bool + Send),
+) -> Result<(ChatMessage, FinishReason, Usage, Option<String>), ProviderError> {
+    let mut chunks = provider.complete_stream(request).await?;
+    let mut text: Option<String> = None;
+    let mut tool_calls: BTreeMap<u32, ToolCall> = BTreeMap::new();
+    let mut finish_reason = FinishReason::Stop;
+    let mut usage = Usage::default();
+    let mut served_by = None;
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        if chunk.served_by.is_some() {
+            served_by = chunk.served_by.clone();
+        }
+        if let Some(chunk_usage) = chunk.usage {
+            usage = chunk_usage;
+        }
+        let Some(choice) = chunk.choices.into_iter().find(|c| c.index == 0) else {
+            continue;
+        };
+        if let Some(reason) = choice.finish_reason {
+            finish_reason = reason;
+        }
+
+        if let Some(delta) = choice.delta.content.filter(|d| !d.is_empty()) {
+            text.get_or_insert_with(String::new).push_str(&delta);
+            emit(AgentEvent::TextDelta(delta));
+        }
+        for delta in choice.delta.tool_calls.into_iter().flatten() {
+            let function = delta.function.unwrap_or_default();
+            // Deltas of parallel calls may interleave or arrive out of order, so key them by index
+            let call = tool_calls.entry(delta.index).or_insert_with(|| {
+                let id = delta
+                    .id
+                    .unwrap_or_else(|| format!("call_{}_{}", chunk.id, delta.index));
+                let name = function.name.clone().unwrap_or_default();
+                emit(AgentEvent::ToolCallStarted {
+                    id: id.clone(),
+                    name: name.clone(),
+                });
+                ToolCall {
+                    id,
+                    r#type: "function".to_string(),
+                    function: FunctionCall {
+                        name,
+                        arguments: String::new(),
+                    },
+                }
+            });
+            if let Some(arguments) = function.arguments.filter(|a| !a.is_empty()) {
+                call.function.arguments.push_str(&arguments);
+                emit(AgentEvent::ToolCallArgs {
+                    id: call.id.clone(),
+                    delta: arguments,
+                });
+            }
+        }
+    }
+
+    let tool_calls: Vec<ToolCall> = tool_calls.into_values().collect();
+    for call in &tool_calls {
+        emit(AgentEvent::ToolCallReady(call.clone()));
+    }
+    let assistant = ChatMessage {
+        role: Role::Assistant,
+        content: text.map(Into::into),
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        tool_call_id: None,
+        name: None,
+    };
+    Ok((assistant, finish_reason, usage, served_by))
+}
@@ -0,0 +1,447 @@
+// AG-UI protocol endpoint (https://docs.ag-ui.com): a run receives the thread's messages,
+// frontend tools and shared state, and streams typed events over SSE. Runs share the request
+// preparation, provider and MCP registry with /chat/completions. MCP tools are executed here;
+// a call to a frontend tool ends the run so the client can execute it and start the next one.
+// The upstream that served the last turn is reported as `agent.served_by` when the run ends.
+use crate::agent::{stream_agent_loop, with_mcp_tools, AgentEvent, AgentOutcome};
+use crate::ai::{
+    prepare_chat_request, ChatCompletionRequest, ChatMessage, FunctionDefinition, MessageContent,
+    Role, Tool, ToolCall,
+};
+use crate::context::CONTEXT_STRATEGY_HEADER;
+use crate::error::{AiError, ApiJson};
+use crate::mcp::McpRegistry;
+use crate::state::AppState;
+use axum::extract::State;
+use axum::http::HeaderValue;
+use axum::response::{
+    sse::{Event, Sse},
+    IntoResponse, Response,
+};
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// Model used when the client does not pick one through `forwardedProps.model`
+pub const DEFAULT_AGUI_MODEL: &str = "default";
+
+// Key of the shared state this server reports run progress under
+pub const AGENT_STATE_KEY: &str = "agent";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunAgentInput {
+    pub thread_id: String,
+    pub run_id: String,
+    #[serde(default)]
+    pub state: Value,
+    pub messages: Vec<AguiMessage>,
+    #[serde(default)]
+    pub tools: Vec<AguiTool>,
+    #[serde(default)]
+    pub context: Vec<AguiContext>,
+    // Free-form client options; `model` and `context_strategy` are honored
+    #[serde(default)]
+    pub forwarded_props: Value,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AguiMessage {
+    #[serde(default)]
+    pub id: String,
+    pub role: Role,
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AguiTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub parameters: Value,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AguiContext {
+    pub description: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    rename_all_fields = "camelCase"
+)]
+pub enum AguiEvent {
+    RunStarted {
+        thread_id: String,
+        run_id: String,
+    },
+    RunFinished {
+        thread_id: String,
+        run_id: String,
+    },
+    RunError {
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<String>,
+    },
+    TextMessageStart {
+        message_id: String,
+        role: Role,
+    },
+    TextMessageContent {
+        message_id: String,
+        delta: String,
+    },
+    TextMessageEnd {
+        message_id: String,
+    },
+    ToolCallStart {
+        tool_call_id: String,
+        tool_call_name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent_message_id: Option<String>,
+    },
+    ToolCallArgs {
+        tool_call_id: String,
+        delta: String,
+    },
+    ToolCallEnd {
+        tool_call_id: String,
+    },
+    ToolCallResult {
+        message_id: String,
+        tool_call_id: String,
+        content: String,
+        role: Role,
+    },
+    StateSnapshot {
+        snapshot: Value,
+    },
+    // RFC 6902 JSON Patch operations against the last snapshot
+    StateDelta {
+        delta: Vec<Value>,
+    },
+}
+
+impl From<AguiMessage> for ChatMessage {
+    fn from(message: AguiMessage) -> Self {
+        ChatMessage {
+            role: message.role,
+            content: message.content,
+            tool_calls: message.tool_calls,
+            tool_call_id: message.tool_call_id,
+            name: message.name,
+        }
+    }
+}
+
+impl From<AguiTool> for Tool {
+    fn from(tool: AguiTool) -> Self {
+        Tool {
+            r#type: "function".to_string(),
+            function: FunctionDefinition {
+                name: tool.name,
+                description: tool.description,
+                parameters: tool.parameters,
+            },
+        }
+    }
+}
+
+impl RunAgentInput {
+    // The equivalent chat completion request: frontend tools next to the MCP ones, and the
+    // client context as a system message after the leading system/developer messages
+    fn to_chat_request(&self, registry: &McpRegistry) -> Result<ChatCompletionRequest, AiError> {
+        let model = self.forwarded_props["model"]
+            .as_str()
+            .unwrap_or(DEFAULT_AGUI_MODEL);
+        let mut request: ChatCompletionRequest = serde_json::from_value(json!({
+            "model": model,
+            "messages": [],
+            "context_strategy": self.forwarded_props.get("context_strategy"),
+        }))
+        .map_err(|e| {
+            AiError::invalid_request(
+                format!("invalid forwardedProps: {}", e),
+                Some("forwardedProps"),
+            )
+        })?;
+
+        request.messages = self.messages.iter().cloned().map(Into::into).collect();
+        if !self.context.is_empty() {
+            let context: Vec<String> = self
+                .context
+                .iter()
+                .map(|entry| format!("- {}: {}", entry.description, entry.value))
+                .collect();
+            let position = request
+                .messages
+                .iter()
+                .take_while(|m| matches!(m.role, Role::System | Role::Developer))
+                .count();
+            request.messages.insert(
+                position,
+                ChatMessage {
+                    role: Role::System,
+                    content: Some(
+                        format!("Context from the client:\n{}", context.join("\n")).into(),
+                    ),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: Some("agui_context".to_string()),
+                },
+            );
+        }
+
+        request.tools = with_mcp_tools(
+            self.tools.iter().cloned().map(Into::into).collect(),
+            registry,
+        );
+        request.stream = Some(true);
+        Ok(request)
+    }
+}
+
+pub async fn run_agent_handler(
+    State(state): State<AppState>,
+    ApiJson(input): ApiJson<RunAgentInput>,
+) -> Result<Response, AiError> {
+    log::info!(
+        "🧭 AG-UI run: thread={}, run={}, messages={}, frontend tools={}",
+        input.thread_id,
+        input.run_id,
+        input.messages.len(),
+        input.tools.len()
+    );
+
+    if !(input.state.is_object() || input.state.is_null()) {
+        return Err(AiError::invalid_request(
+            "'state' must be an object",
+            Some("state"),
+        ));
+    }
+    let registry = state.mcp_snapshot().await;
+    let mut request = input.to_chat_request(&registry)?;
+    // Malformed input fails the HTTP request; anything after this point becomes RUN_ERROR
+    let report = prepare_chat_request(&state, &mut request).await?;
+
+    let (events, receiver) = mpsc::unbounded();
+    tokio::spawn(async move {
+        let mut run = Run {
+            input,
+            events,
+            next_id: 0,
+            served_by: None,
+        };
+        run.execute(&state, &registry, request).await;
+    });
+
+    let stream = receiver.map(|event: AguiEvent| Event::default().json_data(&event));
+    let mut response = Sse::new(stream).into_response();
+    if let Ok(value) = HeaderValue::from_str(&report.to_string()) {
+        response
+            .headers_mut()
+            .insert(CONTEXT_STRATEGY_HEADER, value);
+    }
+    Ok(response)
+}
+
+// How a run ended, reported as `agent.status` in the shared state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunOutcome {
+    Finished,
+    AwaitingTools,
+    Failed,
+}
+
+struct Run {
+    input: RunAgentInput,
+    events: mpsc::UnboundedSender<AguiEvent>,
+    next_id: u32,
+    served_by: Option<String>,
+}
+
+impl Run {
+    // Sending only fails once the client disconnected; the run then stops at the next step
+    fn emit(&self, event: AguiEvent) -> bool {
+        self.events.unbounded_send(event).is_ok()
+    }
+
+    fn new_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}_{}_{}", prefix, self.input.run_id, self.next_id)
+    }
+
+    fn set_status(&self, outcome: RunOutcome) {
+        let status = match outcome {
+            RunOutcome::Finished => "finished",
+            RunOutcome::AwaitingTools => "awaiting_tools",
+            RunOutcome::Failed => "error",
+        };
+        let mut delta = vec![json!({
+            "op": "replace",
+            "path": format!("/{}/status", AGENT_STATE_KEY),
+            "value": status,
+        })];
+        if let Some(served_by) = &self.served_by {
+            delta.push(json!({
+                "op": "replace",
+                "path": format!("/{}/served_by", AGENT_STATE_KEY),
+                "value": served_by,
+            }));
+        }
+        self.emit(AguiEvent::StateDelta { delta });
+    }
+
+    async fn execute(
+        &mut self,
+        state: &AppState,
+        registry: &McpRegistry,
+        mut request: ChatCompletionRequest,
+    ) {
+        let (thread_id, run_id) = (self.input.thread_id.clone(), self.input.run_id.clone());
+        self.emit(AguiEvent::RunStarted {
+            thread_id: thread_id.clone(),
+            run_id: run_id.clone(),
+        });
+
+        let mut snapshot = match &self.input.state {
+            Value::Object(object) => object.clone(),
+            _ => Default::default(),
+        };
+        snapshot.insert(
+            AGENT_STATE_KEY.to_string(),
+            json!({ "status": "running", "model": request.model, "served_by": null, "steps": [] }),
+        );
+        self.emit(AguiEvent::StateSnapshot {
+            snapshot: Value::Object(snapshot),
+        });
+
+        let outcome = match self.agent_loop(state, registry, &mut request).await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                log::error!("❌ AG-UI run {} failed: {}", run_id, e);
+                self.set_status(RunOutcome::Failed);
+                let body = e.body();
+                self.emit(AguiEvent::RunError {
+                    message: body.message,
+                    code: body.code,
+                });
+                return;
+            }
+        };
+        self.set_status(outcome);
+        self.emit(AguiEvent::RunFinished { thread_id, run_id });
+    }
+
+    async fn agent_loop(
+        &mut self,
+        state: &AppState,
+        registry: &McpRegistry,
+        request: &mut ChatCompletionRequest,
+    ) -> Result<RunOutcome, AiError> {
+        let client_tools: Vec<String> = self.input.tools.iter().map(|t| t.name.clone()).collect();
+        let events = self.events.clone();
+        let emit = |event: AguiEvent| events.unbounded_send(event).is_ok();
+
+        let mut message_id = String::new();
+        let mut text_started = false;
+        let mut step_tools: Vec<String> = Vec::new();
+        let outcome = stream_agent_loop(
+            state.provider.as_ref(),
+            registry,
+            request,
+            &client_tools,
+            state.config.max_tool_iterations,
+            &mut |event| match event {
+                AgentEvent::StepStarted { .. } => {
+                    message_id = self.new_id("msg");
+                    text_started = false;
+                    true
+                }
+                AgentEvent::TextDelta(delta) => {
+                    if !text_started {
+                        text_started = true;
+                        emit(AguiEvent::TextMessageStart {
+                            message_id: message_id.clone(),
+                            role: Role::Assistant,
+                        });
+                    }
+                    emit(AguiEvent::TextMessageContent {
+                        message_id: message_id.clone(),
+                        delta,
+                    })
+                }
+                AgentEvent::ToolCallStarted { id, name } => {
+                    // Text always precedes the calls of a turn
+                    if std::mem::take(&mut text_started) {
+                        emit(AguiEvent::TextMessageEnd {
+                            message_id: message_id.clone(),
+                        });
+                    }
+                    emit(AguiEvent::ToolCallStart {
+                        tool_call_id: id,
+                        tool_call_name: name,
+                        parent_message_id: Some(message_id.clone()),
+                    })
+                }
+                AgentEvent::ToolCallArgs { id, delta } => emit(AguiEvent::ToolCallArgs {
+                    tool_call_id: id,
+                    delta,
+                }),
+                AgentEvent::ToolCallReady(call) => emit(AguiEvent::ToolCallEnd {
+                    tool_call_id: call.id,
+                }),
+                AgentEvent::ToolResult { call, content } => {
+                    step_tools.push(call.function.name);
+                    emit(AguiEvent::ToolCallResult {
+                        message_id: self.new_id("msg"),
+                        tool_call_id: call.id,
+                        content,
+                        role: Role::Tool,
+                    })
+                }
+                AgentEvent::StepFinished {
+                    step, served_by, ..
+                } => {
+                    self.served_by =
+                        Some(served_by.unwrap_or_else(|| state.provider.name().to_string()));
+                    if std::mem::take(&mut text_started) {
+                        emit(AguiEvent::TextMessageEnd {
+                            message_id: message_id.clone(),
+                        });
+                    }
+                    if step_tools.is_empty() {
+                        return true;
+                    }
+                    emit(AguiEvent::StateDelta {
+                        delta: vec![json!({
+                            "op": "add",
+                            "path": format!("/{}/steps/-", AGENT_STATE_KEY),
+                            "value": { "iteration": step + 1, "tools": std::mem::take(&mut step_tools) },
+                        })],
+                    })
+                }
+            },
+        )
+        .await?;
+
+        Ok(match outcome {
+            AgentOutcome::Finished => RunOutcome::Finished,
+            AgentOutcome::AwaitingClientTools => RunOutcome::AwaitingTools,
+        })
+    }
+}
@@ -1,34 +1,161 @@
 // AI/LLM-specific handlers compatible with assistant-ui and ag-ui
-use axum::{extract::Json, http::StatusCode, response::Json as AxumJson, response::Response};
+use crate::agent::{AgentStep, ToolExecution};
+use crate::context::{
+    apply_context_strategy, enforce_context_window, ContextReport, ContextStrategy,
+    CONTEXT_STRATEGY_HEADER,
+};
+use crate::error::{AiError, ApiJson};
+use crate::models::{ModelInfo, ModelList};
+use crate::state::AppState;
+use crate::stream::ChunkStream;
+use crate::tokenizer::Encoding;
+use crate::upstream::SERVED_BY_HEADER;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json as AxumJson, Response},
+};
+use base64::Engine;
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::HashMap;
 
 // OpenAI-compatible request/response types for assistant-ui/ag-ui with tool calling
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct ChatCompletionRequest {
     pub model: String,
     pub messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
+    // Number of choices to generate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<StopSequences>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    // Token id (as a string key) -> bias between -100 and 100
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<HashMap<String, f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+    // End-user identifier for upstream abuse monitoring
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_choice: Option<ToolChoice>,
+    // Whether the model may request several tool calls in one turn (OpenAI default: true)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    // Extensions (never forwarded upstream): server-side tool execution and its iteration guard,
+    // and the context strategy overriding the server default for this request
+    #[serde(default, skip_serializing)]
+    pub tool_execution: ToolExecution,
+    #[serde(default, skip_serializing)]
+    pub max_tool_iterations: Option<u32>,
+    #[serde(default, skip_serializing)]
+    pub context_strategy: Option<ContextStrategy>,
+    // Alias the client asked for ("default", "fast", ...) once `model` has been resolved; picks
+    // the upstream fallback chain
+    #[serde(skip)]
+    pub alias: Option<String>,
+    // Tools the client sent itself, as opposed to MCP tools the server injected; with
+    // server-side tool execution their calls are returned to the client
+    #[serde(skip)]
+    pub client_tools: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// `stop` accepts a single sequence or a list of up to four
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum StopSequences {
+    Single(String),
+    Many(Vec<String>),
+}
+
+impl StopSequences {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            StopSequences::Single(sequence) => std::slice::from_ref(sequence),
+            StopSequences::Many(sequences) => sequences,
+        }
+    }
+}
+
+// JSON mode (`json_object`) and structured outputs (`json_schema`); non-streamed replies that do
+// not conform are repaired or retried by the server, see structured.rs
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    pub fn is_json(&self) -> bool {
+        !matches!(self, ResponseFormat::Text)
+    }
+
+    // The schema replies must satisfy, if any
+    pub fn schema(&self) -> Option<&serde_json::Value> {
+        match self {
+            ResponseFormat::JsonSchema { json_schema } => json_schema.schema.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Tool {
     pub r#type: String, // "function"
     pub function: FunctionDefinition,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FunctionDefinition {
     pub name: String,
     pub description: String,
     pub parameters: serde_json::Value, // JSON schema
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum ToolChoice {
     Auto(String), // "auto", "none", "required"
@@ -38,232 +165,749 @@ pub enum ToolChoice {
     },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FunctionChoice {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// Effective tool_choice once defaults are applied (OpenAI: "auto" with tools, "none" without)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolChoiceMode<'a> {
+    Auto,
+    None,
+    Required,
+    Function(&'a str),
+}
+
+impl ChatCompletionRequest {
+    pub fn allows_parallel_tool_calls(&self) -> bool {
+        self.parallel_tool_calls != Some(false)
+    }
+
+    pub fn tool_choice_mode(&self) -> ToolChoiceMode<'_> {
+        let has_tools = self.tools.as_ref().is_some_and(|tools| !tools.is_empty());
+        match &self.tool_choice {
+            _ if !has_tools => ToolChoiceMode::None,
+            Some(ToolChoice::Auto(mode)) if mode == "none" => ToolChoiceMode::None,
+            Some(ToolChoice::Auto(mode)) if mode == "required" => ToolChoiceMode::Required,
+            Some(ToolChoice::Function { function, .. }) => ToolChoiceMode::Function(&function.name),
+            _ => ToolChoiceMode::Auto,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    Developer,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::Developer => "developer",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChatMessage {
-    pub role: String, // "system", "user", "assistant", "tool"
-    pub content: Option<String>,
+    pub role: Role,
+    pub content: Option<MessageContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>, // For tool messages
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// Message content: a plain string, or typed parts as sent by assistant-ui and the OpenAI
+// vision/file APIs (`[{"type": "text", ...}, {"type": "image_url", ...}]`). Serialized back in
+// the shape it arrived in, so parts are forwarded to providers unchanged.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    File { file: FileInput },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ImageUrl {
+    pub url: String, // https:// URL or data:image/...;base64,...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>, // "auto", "low" or "high"
+}
+
+// A document passed inline (`file_data` as a base64 data URL) or by a previously uploaded id
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FileInput {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<String>,
+}
+
+impl MessageContent {
+    // The content when it is a plain string
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            MessageContent::Text(text) => Some(text),
+            MessageContent::Parts(_) => None,
+        }
+    }
+
+    // All text, with the text parts joined by newlines; images and files are left out
+    pub fn to_text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            MessageContent::Text(text) => text.is_empty(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .all(|part| matches!(part, ContentPart::Text { text } if text.is_empty())),
+        }
+    }
+
+    pub fn parts(&self) -> &[ContentPart] {
+        match self {
+            MessageContent::Text(_) => &[],
+            MessageContent::Parts(parts) => parts,
+        }
+    }
+
+    pub fn has_images(&self) -> bool {
+        self.parts()
+            .iter()
+            .any(|part| matches!(part, ContentPart::ImageUrl { .. }))
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+// Hand-written instead of `#[serde(untagged)]` so a bad part reports its own error and path
+// (e.g. "messages[0].content[1]: unknown variant `audio`") rather than "did not match any variant"
+impl<'de> Deserialize<'de> for MessageContent {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ContentVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ContentVisitor {
+            type Value = MessageContent;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a string or an array of content parts")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Self::Value, E> {
+                Ok(MessageContent::Text(text.to_string()))
+            }
+
+            fn visit_string<E: serde::de::Error>(self, text: String) -> Result<Self::Value, E> {
+                Ok(MessageContent::Text(text))
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut parts = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+                while let Some(part) = seq.next_element()? {
+                    parts.push(part);
+                }
+                Ok(MessageContent::Parts(parts))
+            }
+        }
+
+        deserializer.deserialize_any(ContentVisitor)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ToolCall {
     pub id: String,
     pub r#type: String, // "function"
     pub function: FunctionCall,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FunctionCall {
     pub name: String,
     pub arguments: String, // JSON string
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChatCompletionResponse {
     pub id: String,
     pub object: String,
     pub created: u64,
     pub model: String,
     pub choices: Vec<ChatChoice>,
+    #[serde(default)]
     pub usage: Usage,
+    // Intermediate tool rounds when the server executed tool calls itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_trace: Option<Vec<AgentStep>>,
+    // Upstream that answered when the request went through a fallback chain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub served_by: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChatChoice {
     pub index: u32,
     pub message: ChatMessage,
-    pub finish_reason: String, // "stop", "length", "tool_calls", "content_filter"
+    pub finish_reason: FinishReason,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChoiceLogprobs>,
+}
+
+// Per-token log probabilities, returned when the request sets `logprobs: true`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ChoiceLogprobs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+    // The most likely alternatives at this position, `top_logprobs` of them
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    Stop,
+    Length,
+    ToolCalls,
+    ContentFilter,
+    FunctionCall, // Deprecated upstream, still sent by some OpenAI-compatible servers
+}
+
+impl FinishReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::ContentFilter => "content_filter",
+            FinishReason::FunctionCall => "function_call",
+        }
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
 
+// Streaming (`stream: true`) counterparts sent as `chat.completion.chunk` server-sent events
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChunkChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>, // Only on the final chunk when stream_options.include_usage is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub served_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChunkChoice {
+    pub index: u32,
+    pub delta: ChatDelta,
+    pub finish_reason: Option<FinishReason>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ChatDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolCallDelta {
+    pub index: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FunctionCallDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+// OpenAI-compatible embeddings request/response types
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EmbeddingsRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding_format: Option<EncodingFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl EmbeddingInput {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            EmbeddingInput::Single(input) => vec![input],
+            EmbeddingInput::Batch(inputs) => inputs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    #[default]
+    Float,
+    Base64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EmbeddingsResponse {
+    pub object: String, // "list"
+    pub data: Vec<EmbeddingObject>,
+    pub model: String,
+    pub usage: EmbeddingsUsage,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EmbeddingObject {
+    pub object: String, // "embedding"
+    pub index: u32,
+    pub embedding: EmbeddingVector,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum EmbeddingVector {
+    Float(Vec<f32>),
+    Base64(String), // little-endian f32 bytes
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EmbeddingsUsage {
+    pub prompt_tokens: u32,
+    pub total_tokens: u32,
+}
+
 // OpenAI-compatible chat completions endpoint with tool calling and RAG support
 pub async fn chat_completions_handler(
-    Json(mut request): Json<ChatCompletionRequest>,
-) -> Result<AxumJson<ChatCompletionResponse>, StatusCode> {
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(mut request): ApiJson<ChatCompletionRequest>,
+) -> Result<Response, AiError> {
+    if crate::data_stream::wants_data_stream(&headers) {
+        return crate::data_stream::respond(state, request).await;
+    }
+
+    let streaming = request.stream.unwrap_or(false);
     log::info!(
-        "🤖 OpenAI-compatible chat completion request: model={}, messages={}, tools={}",
+        "🤖 OpenAI-compatible chat completion request: model={}, messages={}, tools={}, stream={}",
         request.model,
         request.messages.len(),
-        request.tools.as_ref().map(|t| t.len()).unwrap_or(0)
+        request.tools.as_ref().map(|t| t.len()).unwrap_or(0),
+        streaming
     );
 
-    // Initialize services
-    crate::mcp::initialize_default_mcp_servers();
-    crate::rag::initialize_rag_service();
+    let report = prepare_chat_request(&state, &mut request).await?;
+    let mut response = dispatch_chat_completion(&state, request, streaming).await?;
+    if let Ok(value) = HeaderValue::from_str(&report.to_string()) {
+        response
+            .headers_mut()
+            .insert(CONTEXT_STRATEGY_HEADER, value);
+    }
+    Ok(response)
+}
+
+// Everything between parsing and the provider call, shared by the OpenAI and AG-UI endpoints:
+// model resolution, MCP tool injection, validation, context strategy, RAG and the context window
+pub(crate) async fn prepare_chat_request(
+    state: &AppState,
+    request: &mut ChatCompletionRequest,
+) -> Result<ContextReport, AiError> {
+    // Resolve aliases ("default", "fast", ...) to a concrete catalog model before dispatch
+    let Some(model) = state.config.models.resolve(&request.model) else {
+        return Err(AiError::ModelNotFound(request.model.clone()));
+    };
+    if model.id != request.model {
+        log::info!(
+            "🔀 Resolved model alias '{}' -> '{}'",
+            request.model,
+            model.id
+        );
+        request.alias = Some(std::mem::replace(&mut request.model, model.id.clone()));
+    }
+
+    // Reject malformed conversations before spending any RAG, tool or provider work. This runs
+    // before MCP tools are injected so server tools are never reported as client `tools[i]`
+    request.validate_fields()?;
+
+    request.client_tools = request
+        .tools
+        .iter()
+        .flatten()
+        .map(|tool| tool.function.name.clone())
+        .collect();
+    // If no tools specified, add available MCP tools
+    if request.tools.is_none() {
+        let mcp_tools = state.mcp.read().await.get_available_tools();
+        if !mcp_tools.is_empty() {
+            request.tools = Some(mcp_tools);
+        }
+    }
+    // A named or required tool_choice may refer to the injected MCP tools
+    request.validate_tool_choice()?;
+    if !model.capabilities.vision
+        && request
+            .messages
+            .iter()
+            .any(|msg| msg.content.as_ref().is_some_and(MessageContent::has_images))
+    {
+        return Err(AiError::invalid_request(
+            format!("Model '{}' does not support image inputs", model.id),
+            Some("messages"),
+        ));
+    }
+    if !model.capabilities.json
+        && request
+            .response_format
+            .as_ref()
+            .is_some_and(ResponseFormat::is_json)
+    {
+        return Err(AiError::invalid_request(
+            format!("Model '{}' does not support JSON response formats", model.id),
+            Some("response_format"),
+        ));
+    }
 
-    let mcp_registry = crate::mcp::get_mcp_registry();
-    let rag_service = crate::rag::get_rag_service();
+    // Shape the history before RAG so retrieved documents are never summarized or dropped
+    let strategy = request
+        .context_strategy
+        .clone()
+        .unwrap_or_else(|| state.config.context_strategy.clone());
+    let report = apply_context_strategy(request, model, &strategy, state.provider.as_ref()).await;
+
+    let rag_service = &state.rag;
 
     // Extract user query for RAG (clone to avoid borrow checker issues)
     let user_query = request
         .messages
         .iter()
-        .filter(|msg| msg.role == "user")
-        .last()
+        .rev()
+        .find(|msg| msg.role == Role::User)
         .and_then(|msg| msg.content.as_ref())
-        .map_or(String::new(), |v| v.clone());
+        .map(MessageContent::to_text)
+        .unwrap_or_default();
 
     // Enhance messages with RAG context if there's a user query
     if !user_query.is_empty() {
-        if let Ok(rag_context) = rag_service.retrieve_context(&user_query, None).await {
+        let user = request.user.as_deref();
+        if let Ok(rag_context) = rag_service.retrieve_context(&user_query, user).await {
             rag_service.enhance_messages_with_context(&mut request.messages, &rag_context);
         }
     }
 
-    // Check if this is a tool call response or if we need to make tool calls
-    let has_tool_call_response = request.messages.iter().any(|msg| msg.role == "tool");
+    // Counted after RAG injection, since retrieved documents take up context too
+    enforce_context_window(request, model, state.config.context_overflow)?;
+    Ok(report)
+}
 
-    // If no tools specified, add available MCP tools
-    let available_tools = if request.tools.is_none() {
-        let mcp_tools = mcp_registry.get_available_tools();
-        if !mcp_tools.is_empty() {
-            request.tools = Some(mcp_tools);
-        }
-        request.tools.as_ref()
+// Run the prepared request: server-side agent loop, upstream stream, or a single completion
+async fn dispatch_chat_completion(
+    state: &AppState,
+    request: ChatCompletionRequest,
+    streaming: bool,
+) -> Result<Response, AiError> {
+    let provider = &state.provider;
+
+    if streaming && request.tool_execution != ToolExecution::Server {
+        let (chunks, served_by) = open_chat_stream(state, &request).await?;
+        let mut response = crate::stream::sse_response(chunks);
+        insert_served_by(&mut response, served_by.as_deref(), provider.name());
+        return Ok(response);
+    }
+
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .is_some_and(|options| options.include_usage);
+    let completion = complete_chat(state, request).await?;
+    let served_by = completion.served_by.clone();
+    let mut response = if streaming {
+        completed_stream_response(completion, include_usage)
     } else {
-        request.tools.as_ref()
+        AxumJson(completion).into_response()
     };
+    insert_served_by(&mut response, served_by.as_deref(), provider.name());
+    Ok(response)
+}
 
-    // Determine if we should make a tool call (mock logic for now)
-    let should_call_tool = available_tools.is_some()
-        && !has_tool_call_response
-        && (user_query.contains("search")
-            || user_query.contains("file")
-            || user_query.contains("read"));
-
-    let response = if should_call_tool && available_tools.is_some() {
-        // Generate a tool call response
-        let tool_calls = vec![ToolCall {
-            id: format!("call_{}", chrono::Utc::now().timestamp()),
-            r#type: "function".to_string(),
-            function: FunctionCall {
-                name: if user_query.contains("search") {
-                    "search_web"
-                } else {
-                    "read_file"
-                }
-                .to_string(),
-                arguments: if user_query.contains("search") {
-                    serde_json::json!({"query": &user_query, "max_results": 3}).to_string()
-                } else {
-                    serde_json::json!({"path": "/example/file.txt"}).to_string()
-                },
-            },
-        }];
-
-        let response_message = ChatMessage {
-            role: "assistant".to_string(),
-            content: None,
-            tool_calls: Some(tool_calls),
-            tool_call_id: None,
-            name: None,
-        };
+// Open the provider's stream with the response_format enforced, along with the upstream the
+// fallback chain picked
+pub(crate) async fn open_chat_stream(
+    state: &AppState,
+    request: &ChatCompletionRequest,
+) -> Result<(ChunkStream, Option<String>), AiError> {
+    let provider = &state.provider;
+    let mut chunks = provider.complete_stream(request).await.map_err(|e| {
+        log::error!("❌ Provider '{}' failed to stream: {}", provider.name(), e);
+        AiError::from(e)
+    })?;
+    // The fallback chain has picked its upstream once the stream is open; its first chunk says
+    // which one
+    let first = chunks.next().await;
+    let served_by = first
+        .as_ref()
+        .and_then(|chunk| chunk.as_ref().ok())
+        .and_then(|chunk| chunk.served_by.clone());
+    let chunks = futures::stream::iter(first).chain(chunks).boxed();
+    let chunks =
+        crate::structured::validate_structured_stream(request.response_format.clone(), chunks);
+    Ok((chunks, served_by))
+}
+
+// Report the upstream that produced the reply: the fallback chain's pick, else the provider
+pub(crate) fn insert_served_by(response: &mut Response, served_by: Option<&str>, provider: &str) {
+    if let Ok(value) = HeaderValue::from_str(served_by.unwrap_or(provider)) {
+        response.headers_mut().insert(SERVED_BY_HEADER, value);
+    }
+}
+
+// The whole reply at once: the server-side agent loop when requested, otherwise one completion
+// with the response_format enforced
+pub(crate) async fn complete_chat(
+    state: &AppState,
+    request: ChatCompletionRequest,
+) -> Result<ChatCompletionResponse, AiError> {
+    let provider = &state.provider;
+
+    if request.tool_execution == ToolExecution::Server {
+        let max_iterations = request
+            .max_tool_iterations
+            .map_or(state.config.max_tool_iterations, |n| {
+                n.min(state.config.max_tool_iterations)
+            });
+        let registry = state.mcp_snapshot().await;
+        let client_tools = request.client_tools.clone();
+        return crate::agent::run_agent_loop(
+            provider.as_ref(),
+            &registry,
+            request,
+            &client_tools,
+            max_iterations,
+        )
+        .await
+        .map_err(|e| {
+            log::error!(
+                "❌ Agent loop with provider '{}' failed: {}",
+                provider.name(),
+                e
+            );
+            AiError::from(e)
+        });
+    }
+
+    crate::structured::complete_structured(provider.as_ref(), &request)
+        .await
+        .map_err(|e| {
+            log::error!("❌ Provider '{}' failed: {}", provider.name(), e);
+            AiError::from(e)
+        })
+}
+
+// Replay a finished completion as an SSE stream for clients that asked for one
+pub(crate) fn completed_stream_response(
+    response: ChatCompletionResponse,
+    include_usage: bool,
+) -> Response {
+    let chunks = crate::stream::response_to_chunks(response, include_usage);
+    crate::stream::sse_response(futures::stream::iter(chunks.into_iter().map(Ok)).boxed())
+}
+
+// OpenAI-compatible embeddings endpoint backed by the RAG service's embedder
+pub async fn embeddings_handler(
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<EmbeddingsRequest>,
+) -> Result<AxumJson<EmbeddingsResponse>, AiError> {
+    let encoding_format = request.encoding_format.unwrap_or_default();
+    let inputs = request.input.into_vec();
+    let embedder = state.rag.embedder();
+    log::info!(
+        "🧮 Embeddings request: model={}, inputs={}, format={:?}, embedder={}",
+        request.model,
+        inputs.len(),
+        encoding_format,
+        embedder.model()
+    );
+
+    if inputs.is_empty() || inputs.iter().any(|input| input.is_empty()) {
+        return Err(AiError::invalid_request(
+            "'input' must be a non-empty string or array of non-empty strings",
+            Some("input"),
+        ));
+    }
+    if request.dimensions == Some(0) {
+        return Err(AiError::invalid_request(
+            "'dimensions' must be greater than 0",
+            Some("dimensions"),
+        ));
+    }
 
-        ChatCompletionResponse {
-            id: format!("chatcmpl-{}", chrono::Utc::now().timestamp()),
-            object: "chat.completion".to_string(),
-            created: chrono::Utc::now().timestamp() as u64,
-            model: request.model.clone(),
-            choices: vec![ChatChoice {
-                index: 0,
-                message: response_message,
-                finish_reason: "tool_calls".to_string(),
-            }],
-            usage: Usage {
-                prompt_tokens: calculate_tokens(&request.messages),
-                completion_tokens: 25,
-                total_tokens: calculate_tokens(&request.messages) + 25,
-            },
+    let vectors = embedder.embed(&inputs).await.map_err(|e| {
+        log::error!("❌ Embedder '{}' failed: {}", embedder.model(), e);
+        AiError::from(e)
+    })?;
+
+    let mut data = Vec::with_capacity(vectors.len());
+    for (index, mut vector) in vectors.into_iter().enumerate() {
+        if let Some(dimensions) = request.dimensions {
+            if dimensions > vector.len() {
+                return Err(AiError::invalid_request(
+                    format!(
+                        "'dimensions' must be at most {} for model '{}'",
+                        vector.len(),
+                        embedder.model()
+                    ),
+                    Some("dimensions"),
+                ));
+            }
+            shorten_embedding(&mut vector, dimensions);
         }
-    } else {
-        // Generate a regular text response
-        let context_info = if request
-            .messages
-            .iter()
-            .any(|msg| msg.name == Some("rag_context".to_string()))
-        {
-            " (Enhanced with RAG context from your documents)"
-        } else {
-            ""
-        };
 
-        let tool_info = if available_tools.is_some() {
-            format!(
-                " {} MCP tools are available.",
-                available_tools.unwrap().len()
-            )
-        } else {
-            String::new()
+        let embedding = match encoding_format {
+            EncodingFormat::Float => EmbeddingVector::Float(vector),
+            EncodingFormat::Base64 => {
+                let bytes: Vec<u8> = vector.iter().flat_map(|x| x.to_le_bytes()).collect();
+                EmbeddingVector::Base64(base64::engine::general_purpose::STANDARD.encode(bytes))
+            }
         };
+        data.push(EmbeddingObject {
+            object: "embedding".to_string(),
+            index: index as u32,
+            embedding,
+        });
+    }
 
-        let response_message = ChatMessage {
-            role: "assistant".to_string(),
-            content: Some(format!(
-                "This is a response from the shared Rust handler with full tool calling and RAG support. \
-                 You sent {} messages to model '{}'.{}{} \
-                 This response is compatible with assistant-ui and ag-ui.",
-                request.messages.len(),
-                request.model,
-                context_info,
-                tool_info
-            )),
-            tool_calls: None,
-            tool_call_id: None,
-            name: None,
-        };
+    let encoding = Encoding::for_model(embedder.model());
+    let prompt_tokens = inputs.iter().map(|input| encoding.count(input)).sum();
+    Ok(AxumJson(EmbeddingsResponse {
+        object: "list".to_string(),
+        data,
+        model: embedder.model().to_string(),
+        usage: EmbeddingsUsage {
+            prompt_tokens,
+            total_tokens: prompt_tokens,
+        },
+    }))
+}
 
-        ChatCompletionResponse {
-            id: format!("chatcmpl-{}", chrono::Utc::now().timestamp()),
-            object: "chat.completion".to_string(),
-            created: chrono::Utc::now().timestamp() as u64,
-            model: request.model.clone(),
-            choices: vec![ChatChoice {
-                index: 0,
-                message: response_message,
-                finish_reason: "stop".to_string(),
-            }],
-            usage: Usage {
-                prompt_tokens: calculate_tokens(&request.messages),
-                completion_tokens: 75,
-                total_tokens: calculate_tokens(&request.messages) + 75,
-            },
-        }
-    };
+// Truncate to the leading `dimensions` components and re-normalize to unit length
+fn shorten_embedding(vector: &mut Vec<f32>, dimensions: usize) {
+    vector.truncate(dimensions);
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
 
-    Ok(AxumJson(response))
+// OpenAI-compatible model listing
+pub async fn list_models_handler(State(state): State<AppState>) -> AxumJson<ModelList> {
+    log::info!("📋 Listing {} models", state.config.models.models.len());
+    AxumJson(state.config.models.list())
 }
 
-// Helper function to calculate tokens (rough estimation)
-fn calculate_tokens(messages: &[ChatMessage]) -> u32 {
-    messages
-        .iter()
-        .map(|msg| {
-            let content_tokens = msg
-                .content
-                .as_ref()
-                .map(|c| c.len() as u32 / 4)
-                .unwrap_or(0);
-            let tool_tokens = msg
-                .tool_calls
-                .as_ref()
-                .map(|calls| calls.len() as u32 * 10)
-                .unwrap_or(0);
-            content_tokens + tool_tokens
-        })
-        .sum()
+// Single model lookup; aliases resolve to the model they point at
+pub async fn retrieve_model_handler(
+    State(state): State<AppState>,
+    Path(model): Path<String>,
+) -> Result<AxumJson<ModelInfo>, AiError> {
+    match state.config.models.resolve(&model) {
+        Some(info) => Ok(AxumJson(info.clone())),
+        None => Err(AiError::ModelNotFound(model)),
+    }
 }
 
 // Legacy AI chat handler (for backward compatibility)
@@ -281,22 +925,16 @@ pub async fn ai_chat_handler() -> Result<AxumJson<Value>, StatusCode> {
     Ok(AxumJson(response))
 }
 
-// AI streaming handler (placeholder for now)
-pub async fn ai_stream_handler() -> Result<Response, StatusCode> {
+// AI streaming handler: same pipeline as /v1/chat/completions with streaming forced on
+pub async fn ai_stream_handler(
+    state: State<AppState>,
+    headers: HeaderMap,
+    ApiJson(mut request): ApiJson<ChatCompletionRequest>,
+) -> Result<Response, AiError> {
     log::info!("🤖 AI stream request received (shared handler)");
 
-    use axum::body::Body;
-
-    let response = Response::builder()
-        .header("content-type", "text/event-stream")
-        .header("cache-control", "no-cache")
-        .header("connection", "keep-alive")
-        .body(Body::from(
-            "data: {\"message\": \"AI streaming from shared handler\"}\n\n",
-        ))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    Ok(response)
+    request.stream = Some(true);
+    chat_completions_handler(state, headers, ApiJson(request)).await
 }
 
 // AI health check
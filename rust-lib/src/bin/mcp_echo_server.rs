@@ -0,0 +1,113 @@
+// Minimal stdio MCP server used for local development and tests of the MCP client.
+// Speaks newline-delimited JSON-RPC 2.0 and advertises three tools: `echo`, `fail` and `sleep`.
+// Requests are answered on their own threads, so concurrent calls may complete out of order.
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = Arc::new(Mutex::new(io::stdout()));
+
+    for line in stdin.lock().lines() {
+        let Ok(line) = line else { break };
+        let Ok(request) = serde_json::from_str::<Value>(&line) else {
+            eprintln!("mcp-echo-server: ignoring invalid JSON: {}", line);
+            continue;
+        };
+
+        // Notifications (no id) never get a response
+        let Some(id) = request.get("id").cloned() else {
+            continue;
+        };
+
+        let stdout = stdout.clone();
+        thread::spawn(move || {
+            let response = match handle(&request) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": message }
+                }),
+            };
+
+            let mut stdout = stdout.lock().unwrap_or_else(|e| e.into_inner());
+            // A closed stdout means the client is gone; the stdin loop ends on its own
+            let _ = writeln!(stdout, "{}", response).and_then(|_| stdout.flush());
+        });
+    }
+}
+
+fn handle(request: &Value) -> Result<Value, (i64, String)> {
+    match request["method"].as_str().unwrap_or_default() {
+        "initialize" => Ok(json!({
+            "protocolVersion": request["params"]["protocolVersion"],
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": "mcp-echo-server", "version": env!("CARGO_PKG_VERSION") }
+        })),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({
+            "tools": [
+                {
+                    "name": "echo",
+                    "description": "Echo back the provided message",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "message": { "type": "string", "description": "Text to echo" }
+                        },
+                        "required": ["message"]
+                    }
+                },
+                {
+                    "name": "fail",
+                    "description": "Always report a tool error",
+                    "inputSchema": { "type": "object", "properties": {} }
+                },
+                {
+                    "name": "sleep",
+                    "description": "Wait for the given number of milliseconds, then reply",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "ms": { "type": "integer", "description": "Delay in milliseconds" }
+                        },
+                        "required": ["ms"]
+                    }
+                }
+            ]
+        })),
+        "tools/call" => {
+            let params = &request["params"];
+            match params["name"].as_str() {
+                Some("echo") => Ok(json!({
+                    "content": [{
+                        "type": "text",
+                        "text": params["arguments"]["message"].as_str().unwrap_or_default()
+                    }],
+                    "isError": false
+                })),
+                Some("fail") => Ok(json!({
+                    "content": [{ "type": "text", "text": "fail tool always fails" }],
+                    "isError": true
+                })),
+                Some("sleep") => {
+                    let ms = params["arguments"]["ms"].as_u64().unwrap_or_default();
+                    thread::sleep(Duration::from_millis(ms));
+                    Ok(json!({
+                        "content": [{ "type": "text", "text": format!("slept {}ms", ms) }],
+                        "isError": false
+                    }))
+                }
+                other => Err((
+                    -32602,
+                    format!("Unknown tool: {}", other.unwrap_or_default()),
+                )),
+            }
+        }
+        method => Err((-32601, format!("Method not found: {}", method))),
+    }
+}
@@ -0,0 +1,584 @@
+// Application configuration: typed loader for config/mcp_servers.yaml with per-environment
+// overlays, the model catalog, plus the environment-driven knobs of the AI pipeline
+use crate::agent::DEFAULT_MAX_TOOL_ITERATIONS;
+use crate::context::{ContextOverflow, ContextStrategy};
+use crate::mcp::{McpRegistry, McpServer, McpServerStatus, McpTool};
+use crate::mcp_client::StdioTransport;
+use crate::models::ModelCatalog;
+use crate::rag::RagConfig;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const MCP_SERVERS_FILE: &str = "mcp_servers.yaml";
+pub const ENVIRONMENTS_DIR: &str = "environments";
+pub const DEFAULT_DATA_DIR: &str = "data";
+pub const RAG_INDEX_FILE: &str = "rag_index.redb";
+pub const THREADS_FILE: &str = "threads.redb";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("invalid configuration in {path} at `{field}`: {message}")]
+    Schema {
+        path: PathBuf,
+        field: String,
+        message: String,
+    },
+    #[error("invalid configuration:\n{}", format_issues(.0))]
+    Validation(Vec<ValidationIssue>),
+}
+
+// A single problem found while validating the merged configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub path: String, // e.g. "servers.filesystem.transport.command"
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+fn format_issues(issues: &[ValidationIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| format!("  - {}", issue))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub mcp: McpConfig,
+    pub rag: RagConfig,
+    pub models: ModelCatalog,
+    // Root for persistent state (vector index, threads) shared by the web server and desktop app
+    pub data_dir: PathBuf,
+    // redb file holding conversation threads; None keeps them in memory only
+    pub threads_path: Option<PathBuf>,
+    // Upper bound for server-side tool rounds; requests may ask for fewer
+    pub max_tool_iterations: u32,
+    // How history is shaped before dispatch; requests may choose their own
+    pub context_strategy: ContextStrategy,
+    // What to do with prompts that exceed the model's context window
+    pub context_overflow: ContextOverflow,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            mcp: McpConfig::default(),
+            rag: RagConfig::default(),
+            models: ModelCatalog::default(),
+            data_dir: PathBuf::from(DEFAULT_DATA_DIR),
+            threads_path: None,
+            max_tool_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+            context_strategy: ContextStrategy::default(),
+            context_overflow: ContextOverflow::default(),
+        }
+    }
+}
+
+impl AppConfig {
+    // MCP servers come from the YAML config (an invalid file is logged and treated as empty);
+    // ONE_DATA_DIR sets the data directory, AI_MAX_TOOL_ITERATIONS the agent loop guard,
+    // AI_CONTEXT_STRATEGY the history strategy ("full", "last_turns:<n>",
+    // "sliding_window[:<tokens>]" or "summarize[:<keep turns>]"),
+    // AI_CONTEXT_OVERFLOW ("reject" or "trim") the handling of over-long prompts and
+    // RAG_RELEVANCE_THRESHOLD the minimum retrieval score (default: the embedder's own)
+    pub fn from_env() -> Self {
+        let data_dir = std::env::var("ONE_DATA_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_DATA_DIR));
+        let mcp = McpConfig::from_env().unwrap_or_else(|e| {
+            log::error!("❌ Failed to load MCP server config: {}", e);
+            McpConfig::default()
+        });
+        let max_tool_iterations = std::env::var("AI_MAX_TOOL_ITERATIONS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_TOOL_ITERATIONS);
+        let context_strategy = match std::env::var("AI_CONTEXT_STRATEGY") {
+            Ok(value) => value.parse().unwrap_or_else(|e| {
+                log::warn!("⚠️ Ignoring AI_CONTEXT_STRATEGY: {}", e);
+                ContextStrategy::default()
+            }),
+            Err(_) => ContextStrategy::default(),
+        };
+        let context_overflow = match std::env::var("AI_CONTEXT_OVERFLOW").as_deref() {
+            Ok("trim") => ContextOverflow::Trim,
+            Ok("reject") | Err(_) => ContextOverflow::Reject,
+            Ok(other) => {
+                log::warn!(
+                    "⚠️ Unknown AI_CONTEXT_OVERFLOW '{}', rejecting over-long prompts",
+                    other
+                );
+                ContextOverflow::Reject
+            }
+        };
+        let mut rag = RagConfig::default();
+        if let Some(threshold) = std::env::var("RAG_RELEVANCE_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            rag.relevance_threshold = Some(threshold);
+        }
+
+        Self {
+            mcp,
+            rag,
+            models: ModelCatalog::from_env(),
+            data_dir,
+            threads_path: None,
+            max_tool_iterations,
+            context_strategy,
+            context_overflow,
+        }
+        .with_data_dir_defaults()
+    }
+
+    // Point persistent stores at `data_dir` unless they were configured explicitly
+    pub fn with_data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = data_dir.into();
+        self.rag.storage_path = None;
+        self.threads_path = None;
+        self.with_data_dir_defaults()
+    }
+
+    fn with_data_dir_defaults(mut self) -> Self {
+        if self.rag.storage_path.is_none() {
+            self.rag.storage_path = Some(self.data_dir.join(RAG_INDEX_FILE));
+        }
+        if self.threads_path.is_none() {
+            self.threads_path = Some(self.data_dir.join(THREADS_FILE));
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct McpConfig {
+    #[serde(default)]
+    pub servers: BTreeMap<String, McpServerConfig>,
+    #[serde(default)]
+    pub settings: McpSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct McpServerConfig {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_version")]
+    pub version: String,
+    pub transport: TransportConfig,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    // Statically declared tools, used when the transport cannot advertise them (e.g. http)
+    #[serde(default)]
+    pub tools: Vec<McpToolConfig>,
+    #[serde(default)]
+    pub security: SecurityConfig,
+    #[serde(default)]
+    pub resources: ResourceConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum TransportConfig {
+    Stdio {
+        command: Option<String>,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+        cwd: Option<PathBuf>,
+    },
+    Http {
+        url: Option<String>,
+        port: Option<u16>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct McpToolConfig {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_schema")]
+    pub schema: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityConfig {
+    #[serde(default)]
+    pub network_enabled: bool,
+    #[serde(default)]
+    pub file_access: Vec<String>,
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceConfig {
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+impl Default for ResourceConfig {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: default_timeout_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct McpSettings {
+    #[serde(default = "default_vm_pool_size")]
+    pub vm_pool_size: u32,
+    #[serde(default = "default_health_check_interval")]
+    pub health_check_interval: u64,
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    #[serde(default)]
+    pub dev_mode: bool,
+    // Coerce tool call arguments to the types their schema expects before validating them
+    #[serde(default)]
+    pub coerce_arguments: bool,
+    // Environment-specific switches (hot_reload, metrics_enabled, ...) kept as-is
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl Default for McpSettings {
+    fn default() -> Self {
+        Self {
+            vm_pool_size: default_vm_pool_size(),
+            health_check_interval: default_health_check_interval(),
+            log_level: default_log_level(),
+            dev_mode: false,
+            coerce_arguments: false,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_version() -> String {
+    "1.0.0".to_string()
+}
+fn default_enabled() -> bool {
+    true
+}
+fn default_schema() -> serde_json::Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+fn default_timeout_seconds() -> u64 {
+    30
+}
+fn default_vm_pool_size() -> u32 {
+    3
+}
+fn default_health_check_interval() -> u64 {
+    30
+}
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+impl McpConfig {
+    // Load `<dir>/mcp_servers.yaml`, merge `<dir>/environments/<env>.yaml` on top and validate
+    pub fn load(config_dir: &Path, environment: Option<&str>) -> Result<Self, ConfigError> {
+        let base_path = config_dir.join(MCP_SERVERS_FILE);
+        let mut merged = read_yaml(&base_path)?;
+
+        if let Some(environment) = environment {
+            let overlay_path = config_dir
+                .join(ENVIRONMENTS_DIR)
+                .join(format!("{}.yaml", environment));
+            if overlay_path.exists() {
+                log::info!(
+                    "⚙️ Applying '{}' overlay from {}",
+                    environment,
+                    overlay_path.display()
+                );
+                merge_yaml(&mut merged, read_yaml(&overlay_path)?);
+            } else {
+                log::info!("⚙️ No overlay for environment '{}'", environment);
+            }
+        }
+
+        expand_env_vars(&mut merged);
+
+        let config: McpConfig =
+            serde_path_to_error::deserialize(merged).map_err(|e| ConfigError::Schema {
+                path: base_path.clone(),
+                field: e.path().to_string(),
+                message: e.into_inner().to_string(),
+            })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Load using ONE_CONFIG_DIR (default: ./config or ../config) and APP_ENV (default: development)
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let environment = std::env::var("APP_ENV").unwrap_or_else(|_| "development".to_string());
+        Self::load(&default_config_dir(), Some(&environment))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut issues = Vec::new();
+        let mut issue = |path: String, message: &str| {
+            issues.push(ValidationIssue {
+                path,
+                message: message.to_string(),
+            })
+        };
+
+        if self.settings.health_check_interval == 0 {
+            issue(
+                "settings.health_check_interval".into(),
+                "must be greater than 0",
+            );
+        }
+
+        let mut tool_owners: HashMap<&str, &str> = HashMap::new();
+        for (name, server) in &self.servers {
+            let path = format!("servers.{}", name);
+
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                issue(
+                    path.clone(),
+                    "server names may only contain letters, digits, '_' and '-'",
+                );
+            }
+            if server.resources.timeout_seconds == 0 {
+                issue(
+                    format!("{}.resources.timeout_seconds", path),
+                    "must be greater than 0",
+                );
+            }
+
+            match &server.transport {
+                TransportConfig::Stdio { command, .. } => {
+                    let missing = command.as_deref().is_none_or(|c| c.trim().is_empty());
+                    if server.enabled && missing {
+                        issue(
+                            format!("{}.transport.command", path),
+                            "is required for enabled stdio servers",
+                        );
+                    }
+                }
+                TransportConfig::Http { url, port } => {
+                    if url.is_none() && port.is_none() {
+                        issue(
+                            format!("{}.transport", path),
+                            "http transport needs a url or a port",
+                        );
+                    }
+                    if *port == Some(0) {
+                        issue(
+                            format!("{}.transport.port", path),
+                            "must be between 1 and 65535",
+                        );
+                    }
+                }
+            }
+
+            for (i, tool) in server.tools.iter().enumerate() {
+                let tool_path = format!("{}.tools[{}]", path, i);
+                if tool.name.trim().is_empty() {
+                    issue(format!("{}.name", tool_path), "must not be empty");
+                }
+                if tool.schema.get("type").and_then(|t| t.as_str()) != Some("object") {
+                    issue(
+                        format!("{}.schema", tool_path),
+                        "must be a JSON schema of type \"object\"",
+                    );
+                }
+                if server.enabled {
+                    if let Some(owner) = tool_owners.insert(&tool.name, name) {
+                        issue(
+                            format!("{}.name", tool_path),
+                            &format!(
+                                "tool '{}' is already provided by server '{}'",
+                                tool.name, owner
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Validation(issues))
+        }
+    }
+
+    // Register every enabled server: stdio servers are spawned and queried for their tools,
+    // others are registered with their statically declared tools
+    pub async fn register_servers(&self, registry: &mut McpRegistry) {
+        registry.set_argument_coercion(self.settings.coerce_arguments);
+        for (name, server) in &self.servers {
+            if !server.enabled {
+                log::info!("⏸️ MCP server '{}' is disabled in config", name);
+                continue;
+            }
+            registry.set_tool_timeout(name, Duration::from_secs(server.resources.timeout_seconds));
+
+            match &server.transport {
+                TransportConfig::Stdio {
+                    command: Some(command),
+                    args,
+                    env,
+                    cwd,
+                } => {
+                    let transport = StdioTransport {
+                        command: command.clone(),
+                        args: args.clone(),
+                        env: env.clone(),
+                        cwd: cwd.clone(),
+                    };
+                    if let Err(e) = registry
+                        .connect_stdio_server(name, &server.description, &transport)
+                        .await
+                    {
+                        log::error!("❌ Failed to start MCP server '{}': {}", name, e);
+                        registry.register_server(McpServer {
+                            status: McpServerStatus::Error(e.to_string()),
+                            tools: Vec::new(),
+                            ..server.declared_server(name)
+                        });
+                    }
+                }
+                _ => {
+                    log::warn!(
+                        "⚠️ MCP server '{}' uses a transport without tool discovery; registering {} declared tool(s)",
+                        name,
+                        server.tools.len()
+                    );
+                    registry.register_server(server.declared_server(name));
+                }
+            }
+        }
+    }
+}
+
+impl McpServerConfig {
+    fn declared_server(&self, name: &str) -> McpServer {
+        McpServer {
+            name: name.to_string(),
+            description: self.description.clone(),
+            version: self.version.clone(),
+            tools: self
+                .tools
+                .iter()
+                .map(|tool| McpTool {
+                    name: tool.name.clone(),
+                    description: tool.description.clone(),
+                    schema: tool.schema.clone(),
+                    server: name.to_string(),
+                })
+                .collect(),
+            status: McpServerStatus::Active,
+        }
+    }
+}
+
+// ./config when run from the repo root (web), ../config when run from src-tauri (desktop)
+pub fn default_config_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("ONE_CONFIG_DIR") {
+        return PathBuf::from(dir);
+    }
+    ["config", "../config"]
+        .iter()
+        .map(PathBuf::from)
+        .find(|dir| dir.join(MCP_SERVERS_FILE).exists())
+        .unwrap_or_else(|| PathBuf::from("config"))
+}
+
+pub(crate) fn read_yaml(path: &Path) -> Result<Value, ConfigError> {
+    let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let value: Value = serde_yaml::from_str(&content).map_err(|source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    // An empty file parses as null; treat it as an empty mapping
+    Ok(if value.is_null() {
+        Value::Mapping(Mapping::new())
+    } else {
+        value
+    })
+}
+
+// Deep-merge mappings; any other overlay value replaces the base value
+fn merge_yaml(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_yaml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// Substitute ${VAR} references in string values; unknown variables are left untouched
+pub(crate) fn expand_env_vars(value: &mut Value) {
+    match value {
+        Value::String(s) => {
+            let mut expanded = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find("${") {
+                let Some(end) = rest[start..].find('}') else {
+                    break;
+                };
+                let name = &rest[start + 2..start + end];
+                expanded.push_str(&rest[..start]);
+                match std::env::var(name) {
+                    Ok(var) => expanded.push_str(&var),
+                    Err(_) => expanded.push_str(&rest[start..=start + end]),
+                }
+                rest = &rest[start + end + 1..];
+            }
+            expanded.push_str(rest);
+            *s = expanded;
+        }
+        Value::Sequence(items) => items.iter_mut().for_each(expand_env_vars),
+        Value::Mapping(map) => map.iter_mut().for_each(|(_, v)| expand_env_vars(v)),
+        _ => {}
+    }
+}
@@ -0,0 +1,384 @@
+// Context management applied before dispatch. A strategy first shapes the history (keep the last
+// N turns, a token-budget sliding window, or summarize older turns through the provider); then
+// the prompt is checked against the model's context window, and requests that would still
+// overflow are rejected with `context_length_exceeded` or trimmed when configured to.
+use crate::ai::{ChatCompletionRequest, ChatMessage, MessageContent, Role};
+use crate::error::AiError;
+use crate::models::ModelInfo;
+use crate::provider::{ChatProvider, ProviderError};
+use crate::tokenizer::Encoding;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Response header naming the strategy that shaped the prompt, e.g. "sliding_window; dropped=6"
+pub const CONTEXT_STRATEGY_HEADER: &str = "x-context-strategy";
+
+pub const DEFAULT_SUMMARY_KEEP_TURNS: usize = 2;
+const SUMMARY_MAX_TOKENS: u32 = 512;
+const SUMMARY_PROMPT: &str = "Summarize the conversation below for the assistant that will \
+    continue it. Keep facts, decisions, open questions and tool results that later turns may \
+    rely on. Reply with the summary only.";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextOverflow {
+    // Fail like the OpenAI API does
+    #[default]
+    Reject,
+    // Drop the oldest non-system messages until the prompt fits
+    Trim,
+}
+
+// A turn starts at a user message and runs until the next one. System and developer messages
+// are always kept, and the current turn is never dropped.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContextStrategy {
+    // Send the whole history
+    #[default]
+    Full,
+    // Keep only the last `turns` turns
+    LastTurns {
+        turns: usize,
+    },
+    // Keep the newest history that fits in `max_tokens` (default: the model's context window)
+    SlidingWindow {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_tokens: Option<u32>,
+    },
+    // Replace everything before the last `keep_turns` turns with a summary from the provider
+    Summarize {
+        #[serde(default = "default_keep_turns")]
+        keep_turns: usize,
+    },
+}
+
+fn default_keep_turns() -> usize {
+    DEFAULT_SUMMARY_KEEP_TURNS
+}
+
+impl ContextStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContextStrategy::Full => "full",
+            ContextStrategy::LastTurns { .. } => "last_turns",
+            ContextStrategy::SlidingWindow { .. } => "sliding_window",
+            ContextStrategy::Summarize { .. } => "summarize",
+        }
+    }
+}
+
+// "full", "last_turns:<n>", "sliding_window[:<tokens>]" or "summarize[:<keep turns>]"
+impl FromStr for ContextStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name.trim(), Some(arg.trim())),
+            None => (s.trim(), None),
+        };
+        let number = |arg: &str| {
+            arg.parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| format!("'{}' needs a positive number, got '{}'", name, arg))
+        };
+
+        match (name, arg) {
+            ("full", None) => Ok(ContextStrategy::Full),
+            ("last_turns", Some(turns)) => Ok(ContextStrategy::LastTurns {
+                turns: number(turns)?,
+            }),
+            ("last_turns", None) => {
+                Err("'last_turns' needs a turn count, e.g. last_turns:10".into())
+            }
+            ("sliding_window", arg) => Ok(ContextStrategy::SlidingWindow {
+                max_tokens: arg.map(number).transpose()?.map(|n| n as u32),
+            }),
+            ("summarize", arg) => Ok(ContextStrategy::Summarize {
+                keep_turns: arg
+                    .map(number)
+                    .transpose()?
+                    .unwrap_or(DEFAULT_SUMMARY_KEEP_TURNS),
+            }),
+            _ => Err(format!("unknown context strategy '{}'", s)),
+        }
+    }
+}
+
+// What a strategy did to the history; rendered into the CONTEXT_STRATEGY_HEADER value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextReport {
+    pub strategy: &'static str,
+    pub dropped: usize,
+    pub summarized: usize,
+}
+
+impl ContextReport {
+    fn new(strategy: &'static str) -> Self {
+        Self {
+            strategy,
+            dropped: 0,
+            summarized: 0,
+        }
+    }
+}
+
+impl fmt::Display for ContextReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.strategy)?;
+        if self.dropped > 0 {
+            write!(f, "; dropped={}", self.dropped)?;
+        }
+        if self.summarized > 0 {
+            write!(f, "; summarized={}", self.summarized)?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn apply_context_strategy(
+    request: &mut ChatCompletionRequest,
+    model: &ModelInfo,
+    strategy: &ContextStrategy,
+    provider: &dyn ChatProvider,
+) -> ContextReport {
+    let mut report = ContextReport::new(strategy.name());
+    match strategy {
+        ContextStrategy::Full => {}
+        ContextStrategy::LastTurns { turns } => {
+            let cut = last_turns_cut(&request.messages, *turns);
+            report.dropped = drop_before(&mut request.messages, cut);
+        }
+        ContextStrategy::SlidingWindow { max_tokens } => {
+            let mut budget = prompt_budget(request, model);
+            if let Some(max_tokens) = max_tokens {
+                budget = budget.min(*max_tokens);
+            }
+            let cut = sliding_window_cut(request, model.encoding(), budget);
+            report.dropped = drop_before(&mut request.messages, cut);
+        }
+        ContextStrategy::Summarize { keep_turns } => {
+            let cut = last_turns_cut(&request.messages, *keep_turns);
+            let older: Vec<&ChatMessage> = request.messages[..cut]
+                .iter()
+                .filter(|m| !is_pinned(m))
+                .collect();
+            if older.is_empty() {
+                return report;
+            }
+
+            match summarize(provider, &request.model, &older).await {
+                Ok(summary) => {
+                    let position = request.messages[..cut]
+                        .iter()
+                        .filter(|m| is_pinned(m))
+                        .count();
+                    report.summarized = drop_before(&mut request.messages, cut);
+                    request.messages.insert(
+                        position,
+                        ChatMessage {
+                            role: Role::System,
+                            content: Some(
+                                format!("Summary of the earlier conversation:\n{}", summary).into(),
+                            ),
+                            tool_calls: None,
+                            tool_call_id: None,
+                            name: Some("conversation_summary".to_string()),
+                        },
+                    );
+                }
+                Err(e) => {
+                    // Same cut without the summary, so the prompt size stays predictable
+                    log::warn!(
+                        "⚠️ Summarizing older turns with provider '{}' failed, dropping them instead: {}",
+                        provider.name(),
+                        e
+                    );
+                    report = ContextReport::new("last_turns");
+                    report.dropped = drop_before(&mut request.messages, cut);
+                }
+            }
+        }
+    }
+
+    if report.dropped > 0 || report.summarized > 0 {
+        log::info!("🧹 Context strategy applied: {}", report);
+    }
+    report
+}
+
+// Check the prompt (plus any requested max_tokens) against the model's context window and
+// return the prompt size in tokens
+pub fn enforce_context_window(
+    request: &mut ChatCompletionRequest,
+    model: &ModelInfo,
+    overflow: ContextOverflow,
+) -> Result<u32, AiError> {
+    let encoding = model.encoding();
+    let budget = prompt_budget(request, model);
+
+    let mut prompt_tokens = encoding.count_prompt(request);
+    if prompt_tokens > budget && overflow == ContextOverflow::Trim {
+        let cut = sliding_window_cut(request, encoding, budget);
+        let dropped = drop_before(&mut request.messages, cut);
+        if dropped > 0 {
+            log::info!(
+                "✂️ Trimmed {} messages to fit the {}-token context window of '{}'",
+                dropped,
+                model.context_window,
+                model.id
+            );
+            prompt_tokens = encoding.count_prompt(request);
+        }
+    }
+
+    if prompt_tokens > budget {
+        return Err(AiError::InvalidRequest {
+            message: format!(
+                "This model's maximum context length is {} tokens. However, your messages \
+                 resulted in {} tokens ({} reserved for the completion). Please reduce the \
+                 length of the messages.",
+                model.context_window,
+                prompt_tokens,
+                model.context_window - budget
+            ),
+            param: Some("messages".to_string()),
+            code: Some("context_length_exceeded".to_string()),
+        });
+    }
+    Ok(prompt_tokens)
+}
+
+// Tokens the prompt may use once the requested completion is reserved
+fn prompt_budget(request: &ChatCompletionRequest, model: &ModelInfo) -> u32 {
+    model
+        .context_window
+        .saturating_sub(request.max_tokens.unwrap_or(0))
+}
+
+fn is_pinned(message: &ChatMessage) -> bool {
+    matches!(message.role, Role::System | Role::Developer)
+}
+
+// Index of the latest user message; everything from there on is the current turn
+fn current_turn_start(messages: &[ChatMessage]) -> usize {
+    messages
+        .iter()
+        .rposition(|m| m.role == Role::User)
+        .unwrap_or(0)
+}
+
+fn last_turns_cut(messages: &[ChatMessage], turns: usize) -> usize {
+    let starts: Vec<usize> = messages
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.role == Role::User)
+        .map(|(i, _)| i)
+        .collect();
+    match starts.len().checked_sub(turns.max(1)) {
+        Some(first_kept) if first_kept > 0 => starts[first_kept],
+        _ => 0,
+    }
+}
+
+// Oldest index whose history still fits in `budget`, walking back from the current turn one
+// unit at a time; an assistant message and the tool results answering it form a single unit
+fn sliding_window_cut(request: &ChatCompletionRequest, encoding: Encoding, budget: u32) -> usize {
+    let messages = &request.messages;
+    let current = current_turn_start(messages);
+    let cost = |range: std::ops::Range<usize>| -> u32 {
+        messages[range]
+            .iter()
+            .filter(|m| !is_pinned(m))
+            .map(|m| encoding.count_message(m))
+            .sum()
+    };
+
+    let mut used = encoding.count_prompt(request) - cost(0..current);
+    let mut cut = current;
+    while cut > 0 {
+        let mut start = cut - 1;
+        while start > 0 && messages[start].role == Role::Tool {
+            start -= 1;
+        }
+        let unit = cost(start..cut);
+        if used + unit > budget {
+            break;
+        }
+        used += unit;
+        cut = start;
+    }
+    cut
+}
+
+// Drop every message before `cut` except system and developer ones; returns how many went
+fn drop_before(messages: &mut Vec<ChatMessage>, cut: usize) -> usize {
+    let before = messages.len();
+    let mut index = 0;
+    messages.retain(|m| {
+        let keep = index >= cut || is_pinned(m);
+        index += 1;
+        keep
+    });
+    before - messages.len()
+}
+
+async fn summarize(
+    provider: &dyn ChatProvider,
+    model: &str,
+    messages: &[&ChatMessage],
+) -> Result<String, ProviderError> {
+    let transcript: Vec<String> = messages
+        .iter()
+        .map(|m| {
+            let mut line = format!(
+                "{}: {}",
+                m.role.as_str(),
+                m.content
+                    .as_ref()
+                    .map(MessageContent::to_text)
+                    .unwrap_or_default()
+            );
+            for call in m.tool_calls.iter().flatten() {
+                line.push_str(&format!(
+                    "\n[called {}({})]",
+                    call.function.name, call.function.arguments
+                ));
+            }
+            line
+        })
+        .collect();
+
+    let request = ChatCompletionRequest {
+        model: model.to_string(),
+        messages: vec![
+            ChatMessage {
+                role: Role::System,
+                content: Some(SUMMARY_PROMPT.into()),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            },
+            ChatMessage {
+                role: Role::User,
+                content: Some(transcript.join("\n\n").into()),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            },
+        ],
+        max_tokens: Some(SUMMARY_MAX_TOKENS),
+        ..Default::default()
+    };
+
+    let response = provider.complete(&request).await?;
+    response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.as_ref())
+        .map(MessageContent::to_text)
+        .filter(|summary| !summary.trim().is_empty())
+        .ok_or_else(|| ProviderError::InvalidResponse("empty summary".to_string()))
+}
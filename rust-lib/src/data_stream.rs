@@ -0,0 +1,290 @@
+// Vercel AI SDK "data stream" protocol, spoken natively by assistant-ui's data stream runtime and
+// `useChat`: one `<code>:<json>\n` part per line (`0:` text, `9:` tool call, `a:` tool result,
+// `8:` annotation naming the upstream that served the step, `e:` step finish, `d:` message
+// finish, `3:` error). Selected with POST /chat/data-stream, which also accepts the SDK's UI
+// message bodies, or with `x-stream-protocol: data` on /chat/completions. MCP tools run on the server so their results appear inline.
+use crate::agent::{stream_agent_loop, with_mcp_tools, AgentEvent, AgentOutcome};
+use crate::ai::{prepare_chat_request, ChatCompletionRequest, FinishReason, StreamOptions, Usage};
+use crate::context::CONTEXT_STRATEGY_HEADER;
+use crate::error::{deserialize_value, AiError, ApiJson};
+use crate::state::AppState;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::Response;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde_json::{json, Map, Value};
+use std::convert::Infallible;
+
+// Response header the AI SDK client checks before parsing the stream
+pub const DATA_STREAM_HEADER: &str = "x-vercel-ai-data-stream";
+// Request header switching /chat/completions to this protocol ("data")
+pub const STREAM_PROTOCOL_HEADER: &str = "x-stream-protocol";
+
+// Model used when a UI message body does not name one
+pub const DEFAULT_DATA_STREAM_MODEL: &str = "default";
+
+pub fn wants_data_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(STREAM_PROTOCOL_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("data"))
+}
+
+// One line of the stream
+fn part(code: char, value: Value) -> String {
+    format!("{}:{}\n", code, value)
+}
+
+fn finish_reason(reason: FinishReason) -> &'static str {
+    match reason {
+        FinishReason::Stop => "stop",
+        FinishReason::Length => "length",
+        FinishReason::ToolCalls | FinishReason::FunctionCall => "tool-calls",
+        FinishReason::ContentFilter => "content-filter",
+    }
+}
+
+fn usage(usage: &Usage) -> Value {
+    json!({
+        "promptTokens": usage.prompt_tokens,
+        "completionTokens": usage.completion_tokens,
+    })
+}
+
+pub async fn data_stream_handler(
+    State(state): State<AppState>,
+    ApiJson(body): ApiJson<Value>,
+) -> Result<Response, AiError> {
+    let request = from_ui_body(body)?;
+    respond(state, request).await
+}
+
+// Accept both OpenAI-shaped requests and the bodies the AI SDK sends: a missing model, a
+// top-level `system` prompt, tools keyed by name, and `useChat` messages whose tool calls live in
+// `toolInvocations` and whose text may only be in `parts`
+fn from_ui_body(mut body: Value) -> Result<ChatCompletionRequest, AiError> {
+    let Some(object) = body.as_object_mut() else {
+        return Err(AiError::invalid_request(
+            "request body must be a JSON object",
+            None,
+        ));
+    };
+    object
+        .entry("model")
+        .or_insert_with(|| json!(DEFAULT_DATA_STREAM_MODEL));
+
+    if let Some(Value::Object(tools)) = object.get("tools") {
+        let tools: Vec<Value> = tools
+            .iter()
+            .map(|(name, tool)| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": name,
+                        "description": tool["description"].as_str().unwrap_or_default(),
+                        "parameters": tool.get("parameters").cloned().unwrap_or_else(|| json!({})),
+                    }
+                })
+            })
+            .collect();
+        object.insert("tools".to_string(), Value::Array(tools));
+    }
+
+    let mut messages = Vec::new();
+    if let Some(system) = object.remove("system").filter(|s| s.is_string()) {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    if let Some(Value::Array(ui_messages)) = object.remove("messages") {
+        for message in ui_messages {
+            messages.extend(from_ui_message(message));
+        }
+    }
+    object.insert("messages".to_string(), Value::Array(messages));
+
+    deserialize_value(body)
+}
+
+fn from_ui_message(mut message: Value) -> Vec<Value> {
+    let Some(object) = message.as_object_mut() else {
+        return vec![message];
+    };
+    let text: Vec<&str> = object
+        .get("parts")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|part| part["type"] == "text")
+        .filter_map(|part| part["text"].as_str())
+        .collect();
+    let text = text.join("\n");
+    if !text.is_empty() && object.get("content").is_none_or(|c| c.is_null() || c == "") {
+        object.insert("content".to_string(), json!(text));
+    }
+    object.remove("parts");
+
+    let Some(Value::Array(invocations)) = object.remove("toolInvocations") else {
+        return vec![message];
+    };
+    let tool_calls: Vec<Value> = invocations
+        .iter()
+        .map(|invocation| {
+            json!({
+                "id": invocation["toolCallId"],
+                "type": "function",
+                "function": {
+                    "name": invocation["toolName"],
+                    "arguments": invocation.get("args").unwrap_or(&json!({})).to_string(),
+                }
+            })
+        })
+        .collect();
+    if !tool_calls.is_empty() {
+        object.insert("tool_calls".to_string(), Value::Array(tool_calls));
+    }
+
+    let results = invocations
+        .into_iter()
+        .filter(|invocation| invocation["state"] == "result")
+        .map(|invocation| {
+            let result = match &invocation["result"] {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            json!({
+                "role": "tool",
+                "tool_call_id": invocation["toolCallId"],
+                "name": invocation["toolName"],
+                "content": result,
+            })
+        });
+    std::iter::once(message).chain(results).collect()
+}
+
+// Run the request through the chat pipeline and stream the result as data stream parts
+pub(crate) async fn respond(
+    state: AppState,
+    mut request: ChatCompletionRequest,
+) -> Result<Response, AiError> {
+    log::info!(
+        "📡 Data stream chat: model={}, messages={}, tools={}",
+        request.model,
+        request.messages.len(),
+        request.tools.as_ref().map(|t| t.len()).unwrap_or(0)
+    );
+
+    let registry = state.mcp_snapshot().await;
+    let client_tools: Vec<String> = request
+        .tools
+        .iter()
+        .flatten()
+        .map(|tool| tool.function.name.clone())
+        .collect();
+    request.tools = with_mcp_tools(request.tools.take().unwrap_or_default(), &registry);
+    request.stream = Some(true);
+    request.stream_options = Some(StreamOptions {
+        include_usage: true,
+    });
+    let report = prepare_chat_request(&state, &mut request).await?;
+
+    let (parts, receiver) = mpsc::unbounded::<String>();
+    tokio::spawn(async move {
+        let send = |line: String| parts.unbounded_send(line).is_ok();
+        let mut total = Usage::default();
+        let mut last_reason = FinishReason::Stop;
+
+        let result = stream_agent_loop(
+            state.provider.as_ref(),
+            &registry,
+            &mut request,
+            &client_tools,
+            state.config.max_tool_iterations,
+            &mut |event| match event {
+                AgentEvent::StepStarted { step } => {
+                    send(part('f', json!({ "messageId": format!("msg_{}", step) })))
+                }
+                AgentEvent::TextDelta(delta) => send(part('0', json!(delta))),
+                AgentEvent::ToolCallStarted { id, name } => {
+                    send(part('b', json!({ "toolCallId": id, "toolName": name })))
+                }
+                AgentEvent::ToolCallArgs { id, delta } => send(part(
+                    'c',
+                    json!({ "toolCallId": id, "argsTextDelta": delta }),
+                )),
+                AgentEvent::ToolCallReady(call) => {
+                    let args: Value = serde_json::from_str(&call.function.arguments)
+                        .unwrap_or_else(|_| Value::Object(Map::new()));
+                    send(part(
+                        '9',
+                        json!({
+                            "toolCallId": call.id,
+                            "toolName": call.function.name,
+                            "args": args,
+                        }),
+                    ))
+                }
+                AgentEvent::ToolResult { call, content } => {
+                    // MCP results are usually JSON text; send structured results when they parse
+                    let result =
+                        serde_json::from_str::<Value>(&content).unwrap_or(Value::String(content));
+                    send(part(
+                        'a',
+                        json!({ "toolCallId": call.id, "result": result }),
+                    ))
+                }
+                AgentEvent::StepFinished {
+                    finish_reason: reason,
+                    usage: step_usage,
+                    continued,
+                    served_by,
+                    ..
+                } => {
+                    let served_by = served_by.unwrap_or_else(|| state.provider.name().to_string());
+                    send(part('8', json!([{ "served_by": served_by }])));
+                    total.prompt_tokens += step_usage.prompt_tokens;
+                    total.completion_tokens += step_usage.completion_tokens;
+                    total.total_tokens += step_usage.total_tokens;
+                    last_reason = reason;
+                    send(part(
+                        'e',
+                        json!({
+                            "finishReason": finish_reason(reason),
+                            "usage": usage(&step_usage),
+                            "isContinued": continued,
+                        }),
+                    ))
+                }
+            },
+        )
+        .await;
+
+        match result {
+            Ok(outcome) => {
+                if outcome == AgentOutcome::AwaitingClientTools {
+                    last_reason = FinishReason::ToolCalls;
+                }
+                send(part(
+                    'd',
+                    json!({ "finishReason": finish_reason(last_reason), "usage": usage(&total) }),
+                ));
+            }
+            Err(e) => {
+                log::error!("❌ Data stream chat failed: {}", e);
+                send(part('3', json!(AiError::from(e).body().message)));
+            }
+        }
+    });
+
+    let mut response = Response::new(Body::from_stream(receiver.map(Ok::<_, Infallible>)));
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    headers.insert(DATA_STREAM_HEADER, HeaderValue::from_static("v1"));
+    if let Ok(value) = HeaderValue::from_str(&report.to_string()) {
+        headers.insert(CONTEXT_STRATEGY_HEADER, value);
+    }
+    Ok(response)
+}
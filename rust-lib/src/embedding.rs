@@ -0,0 +1,266 @@
+// Text embedders for RAG: OpenAI-compatible `/v1/embeddings` upstreams and an offline hashing model
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub const DEFAULT_HASHING_DIMENSIONS: usize = 384;
+
+// Cosine scores of learned embeddings for related texts sit well above 0.7; hashed word
+// features overlap far less, so related texts score around 0.3-0.6 and unrelated ones below 0.2
+pub const DEFAULT_RELEVANCE_THRESHOLD: f32 = 0.7;
+pub const HASHING_RELEVANCE_THRESHOLD: f32 = 0.25;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmbeddingError {
+    #[error("request to embedding provider failed: {0}")]
+    Request(String),
+    #[error("embedding provider returned {status}: {message}")]
+    Status { status: u16, message: String },
+    #[error("invalid response from embedding provider: {0}")]
+    InvalidResponse(String),
+}
+
+// Turns text into fixed-size vectors; one vector per input, in input order
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    // Model identifier reported to clients ("local-hashing-384", "text-embedding-3-small", ...)
+    fn model(&self) -> &str;
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, EmbeddingError>;
+
+    // Retrieval score separating related documents from noise, used by RAG unless
+    // RAG_RELEVANCE_THRESHOLD overrides it
+    fn default_relevance_threshold(&self) -> f32 {
+        DEFAULT_RELEVANCE_THRESHOLD
+    }
+
+    async fn embed_one(&self, input: &str) -> Result<Vec<f32>, EmbeddingError> {
+        self.embed(&[input.to_string()])
+            .await?
+            .pop()
+            .ok_or_else(|| EmbeddingError::InvalidResponse("no embedding returned".to_string()))
+    }
+}
+
+// Deterministic feature-hashing embedder: word unigrams and bigrams are hashed into signed
+// buckets with sublinear term frequency, then L2-normalized. Needs no network or model files.
+pub struct HashingEmbedder {
+    model: String,
+    dimensions: usize,
+}
+
+impl HashingEmbedder {
+    pub fn new(dimensions: usize) -> Self {
+        let dimensions = dimensions.max(1);
+        Self {
+            model: format!("local-hashing-{}", dimensions),
+            dimensions,
+        }
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    pub fn embed_text(&self, text: &str) -> Vec<f32> {
+        let words: Vec<String> = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+            .collect();
+
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for word in &words {
+            *counts.entry(word.clone()).or_default() += 1;
+        }
+        for pair in words.windows(2) {
+            *counts
+                .entry(format!("{} {}", pair[0], pair[1]))
+                .or_default() += 1;
+        }
+
+        let mut vector = vec![0.0f32; self.dimensions];
+        for (feature, count) in counts {
+            let hash = fnv1a(feature.as_bytes());
+            let bucket = (hash % self.dimensions as u64) as usize;
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[bucket] += sign * (1.0 + (count as f32).ln());
+        }
+
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        vector
+    }
+}
+
+impl Default for HashingEmbedder {
+    fn default() -> Self {
+        Self::new(DEFAULT_HASHING_DIMENSIONS)
+    }
+}
+
+#[async_trait]
+impl Embedder for HashingEmbedder {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        Ok(inputs.iter().map(|input| self.embed_text(input)).collect())
+    }
+
+    fn default_relevance_threshold(&self) -> f32 {
+        HASHING_RELEVANCE_THRESHOLD
+    }
+}
+
+// Stable across platforms and Rust releases, unlike std's DefaultHasher, so persisted
+// embeddings stay comparable with freshly computed ones
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "do", "does", "for", "from", "how", "i", "in",
+    "is", "it", "me", "my", "of", "on", "or", "that", "the", "this", "to", "was", "what", "when",
+    "where", "which", "who", "why", "with", "you",
+];
+
+// Calls any server implementing the OpenAI embeddings API
+pub struct OpenAiEmbedder {
+    // Base URL including the version prefix, e.g. "https://api.openai.com/v1"
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+impl OpenAiEmbedder {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            api_key: None,
+            model: model.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+}
+
+#[async_trait]
+impl Embedder for OpenAiEmbedder {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        log::info!(
+            "🌐 Requesting {} embeddings (model={})",
+            inputs.len(),
+            self.model
+        );
+
+        let mut builder = self
+            .client
+            .post(format!(
+                "{}/embeddings",
+                self.base_url.trim_end_matches('/')
+            ))
+            .json(&json!({ "model": self.model, "input": inputs }));
+        if let Some(api_key) = &self.api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| EmbeddingError::Request(e.to_string()))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            return Err(EmbeddingError::Status {
+                status: status.as_u16(),
+                message,
+            });
+        }
+
+        let mut body = response
+            .json::<EmbeddingsResponse>()
+            .await
+            .map_err(|e| EmbeddingError::InvalidResponse(e.to_string()))?;
+        if body.data.len() != inputs.len() {
+            return Err(EmbeddingError::InvalidResponse(format!(
+                "expected {} embeddings, got {}",
+                inputs.len(),
+                body.data.len()
+            )));
+        }
+
+        body.data.sort_by_key(|data| data.index);
+        Ok(body.data.into_iter().map(|data| data.embedding).collect())
+    }
+}
+
+// Build the embedder selected by the environment:
+//   EMBEDDING_PROVIDER    "local" (default) or "openai"
+//   EMBEDDING_MODEL       upstream model (default: RagConfig.embedding_model)
+//   EMBEDDING_DIMENSIONS  vector size of the local embedder (default 384)
+//   OPENAI_BASE_URL / OPENAI_API_KEY are shared with the chat provider
+pub fn embedder_from_env(default_model: &str) -> Arc<dyn Embedder> {
+    match std::env::var("EMBEDDING_PROVIDER").as_deref() {
+        Ok("openai") => {
+            let base_url = std::env::var("OPENAI_BASE_URL")
+                .unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
+            let model =
+                std::env::var("EMBEDDING_MODEL").unwrap_or_else(|_| default_model.to_string());
+            let mut embedder = OpenAiEmbedder::new(base_url, model);
+            if let Ok(api_key) = std::env::var("OPENAI_API_KEY") {
+                embedder = embedder.with_api_key(api_key);
+            }
+            log::info!(
+                "🌐 Using OpenAI-compatible embeddings at {}",
+                embedder.base_url
+            );
+            Arc::new(embedder)
+        }
+        Ok(other) if other != "local" => {
+            log::warn!(
+                "⚠️ Unknown EMBEDDING_PROVIDER '{}', falling back to local",
+                other
+            );
+            Arc::new(local_embedder_from_env())
+        }
+        _ => Arc::new(local_embedder_from_env()),
+    }
+}
+
+fn local_embedder_from_env() -> HashingEmbedder {
+    let dimensions = std::env::var("EMBEDDING_DIMENSIONS")
+        .ok()
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(DEFAULT_HASHING_DIMENSIONS);
+    HashingEmbedder::new(dimensions)
+}
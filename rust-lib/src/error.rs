@@ -0,0 +1,243 @@
+// Errors returned by the OpenAI-compatible handlers, rendered as the OpenAI error envelope:
+// {"error": {"message", "type", "param", "code"}}
+use crate::embedding::EmbeddingError;
+use crate::provider::ProviderError;
+use crate::threads::ThreadStoreError;
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Json as AxumJson, Response},
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AiError {
+    #[error("{message}")]
+    InvalidRequest {
+        message: String,
+        param: Option<String>,
+        code: Option<String>,
+    },
+    #[error("The model '{0}' does not exist")]
+    ModelNotFound(String),
+    #[error("No thread found with id '{0}'")]
+    ThreadNotFound(String),
+    #[error("Unknown tool '{name}'")]
+    UnknownTool { name: String, param: Option<String> },
+    #[error(transparent)]
+    Provider(#[from] ProviderError),
+    #[error(transparent)]
+    Embedding(#[from] EmbeddingError),
+    #[error(transparent)]
+    Threads(#[from] ThreadStoreError),
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub message: String,
+    pub r#type: String,
+    pub param: Option<String>,
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorEnvelope {
+    pub error: ErrorBody,
+}
+
+impl AiError {
+    pub fn invalid_request(message: impl Into<String>, param: Option<&str>) -> Self {
+        AiError::InvalidRequest {
+            message: message.into(),
+            param: param.map(str::to_string),
+            code: None,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AiError::InvalidRequest { .. } | AiError::UnknownTool { .. } => StatusCode::BAD_REQUEST,
+            AiError::ModelNotFound(_)
+            | AiError::ThreadNotFound(_)
+            | AiError::Threads(ThreadStoreError::UnknownMessage(_)) => StatusCode::NOT_FOUND,
+            AiError::Provider(ProviderError::Status { status: 429, .. }) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            AiError::Provider(ProviderError::Timeout(_)) => StatusCode::GATEWAY_TIMEOUT,
+            AiError::Provider(ProviderError::Unavailable(_)) => StatusCode::SERVICE_UNAVAILABLE,
+            // Upstream client errors are the caller's fault; anything else is a bad gateway
+            AiError::Provider(ProviderError::Status { status, .. })
+                if (400..500).contains(status) =>
+            {
+                StatusCode::from_u16(*status).unwrap_or(StatusCode::BAD_REQUEST)
+            }
+            AiError::Provider(_) | AiError::Embedding(_) => StatusCode::BAD_GATEWAY,
+            AiError::Threads(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        let (r#type, param, code) = match self {
+            AiError::InvalidRequest { param, code, .. } => {
+                ("invalid_request_error", param.clone(), code.clone())
+            }
+            AiError::ModelNotFound(_) => (
+                "invalid_request_error",
+                Some("model".to_string()),
+                Some("model_not_found".to_string()),
+            ),
+            AiError::ThreadNotFound(_) => (
+                "invalid_request_error",
+                None,
+                Some("thread_not_found".to_string()),
+            ),
+            AiError::Threads(ThreadStoreError::UnknownMessage(_)) => (
+                "invalid_request_error",
+                None,
+                Some("message_not_found".to_string()),
+            ),
+            AiError::UnknownTool { param, .. } => (
+                "invalid_request_error",
+                param.clone(),
+                Some("unknown_tool".to_string()),
+            ),
+            AiError::Provider(ProviderError::Status { status: 429, .. }) => (
+                "rate_limit_error",
+                None,
+                Some("rate_limit_exceeded".to_string()),
+            ),
+            AiError::Provider(ProviderError::Timeout(_)) => {
+                ("timeout_error", None, Some("timeout".to_string()))
+            }
+            AiError::Provider(ProviderError::Status { status, .. })
+                if (400..500).contains(status) =>
+            {
+                (
+                    "invalid_request_error",
+                    None,
+                    Some("upstream_error".to_string()),
+                )
+            }
+            AiError::Provider(ProviderError::Unavailable(_)) => {
+                ("api_error", None, Some("upstream_unavailable".to_string()))
+            }
+            AiError::Provider(_) | AiError::Embedding(_) => {
+                ("api_error", None, Some("upstream_error".to_string()))
+            }
+            AiError::Threads(_) => ("server_error", None, None),
+        };
+
+        ErrorBody {
+            message: self.message(),
+            r#type: r#type.to_string(),
+            param,
+            code,
+        }
+    }
+
+    // Upstream errors usually carry their own OpenAI envelope; surface its message when present
+    fn message(&self) -> String {
+        if let AiError::Provider(ProviderError::Status { message, .. }) = self {
+            if let Some(upstream) = serde_json::from_str::<serde_json::Value>(message)
+                .ok()
+                .and_then(|body| body["error"]["message"].as_str().map(str::to_string))
+            {
+                return upstream;
+            }
+        }
+        self.to_string()
+    }
+
+    pub fn envelope(&self) -> ErrorEnvelope {
+        ErrorEnvelope { error: self.body() }
+    }
+}
+
+impl IntoResponse for AiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            log::error!("❌ {} ({})", self, status);
+        } else {
+            log::warn!("⚠️ {} ({})", self, status);
+        }
+
+        let mut response = (status, AxumJson(self.envelope())).into_response();
+        // Pass an upstream's Retry-After on when its rate limit is what the client hit
+        let retry_after = match &self {
+            AiError::Provider(error @ ProviderError::Status { status: 429, .. }) => {
+                error.retry_after()
+            }
+            _ => None,
+        };
+        if let Some(seconds) = retry_after.map(|delay| delay.as_secs_f64().ceil() as u64) {
+            if let Ok(value) = HeaderValue::from_str(&seconds.to_string()) {
+                response.headers_mut().insert(header::RETRY_AFTER, value);
+            }
+        }
+        response
+    }
+}
+
+// JSON body extractor whose rejections use the OpenAI envelope, with `param` pointing at the
+// offending field (e.g. "messages[0].role")
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| AiError::invalid_request(e.body_text(), None))?;
+
+        let mut deserializer = serde_json::Deserializer::from_slice(&bytes);
+        let value = serde_path_to_error::deserialize(&mut deserializer).map_err(invalid_body)?;
+        deserializer
+            .end()
+            .map_err(|e| AiError::invalid_request(format!("Invalid request body: {}", e), None))?;
+
+        Ok(ApiJson(value))
+    }
+}
+
+// Same errors as `ApiJson`, for bodies that are reshaped before they are parsed
+pub(crate) fn deserialize_value<T: DeserializeOwned>(
+    value: serde_json::Value,
+) -> Result<T, AiError> {
+    serde_path_to_error::deserialize(value).map_err(invalid_body)
+}
+
+fn invalid_body(e: serde_path_to_error::Error<serde_json::Error>) -> AiError {
+    let message = format!("Invalid request body: {}", e.inner());
+    AiError::invalid_request(message, param_path(e.path(), e.inner()).as_deref())
+}
+
+// Dotted path of the field a deserialization error refers to; missing fields are appended
+// to the path of their parent object
+fn param_path(path: &serde_path_to_error::Path, error: &serde_json::Error) -> Option<String> {
+    if error.is_syntax() || error.is_eof() || error.is_io() {
+        return None;
+    }
+
+    let path = path.to_string();
+    let parent = (path != "." && path != "?").then_some(path);
+    let missing = error
+        .to_string()
+        .strip_prefix("missing field `")
+        .and_then(|rest| rest.split_once('`'))
+        .map(|(field, _)| field.to_string());
+
+    match (parent, missing) {
+        (Some(parent), Some(field)) => Some(format!("{}.{}", parent, field)),
+        (parent, missing) => parent.or(missing),
+    }
+}
@@ -1,10 +1,29 @@
-use axum::{response::Json as AxumJson, http::StatusCode};
+use axum::{response::Json as AxumJson, http::StatusCode, routing::{get, post}, Router};
 use serde_json::{json, Value};
 
 pub mod api;
+pub mod agent;
+pub mod agui;
 pub mod ai;
+pub mod config;
+pub mod context;
+pub mod data_stream;
+pub mod embedding;
+pub mod error;
 pub mod mcp;
+pub mod mcp_client;
+pub mod models;
+pub mod provider;
 pub mod rag;
+pub mod state;
+pub mod stream;
+pub mod structured;
+pub mod threads;
+pub mod tokenizer;
+pub mod tool_arguments;
+pub mod upstream;
+pub mod validation;
+pub mod vector_store;
 
 // Re-export common types
 pub use axum;
@@ -19,6 +38,43 @@ impl SharedHandlers {
     }
 }
 
+impl Default for SharedHandlers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// OpenAI-compatible routes plus the AG-UI, AI SDK data stream and thread endpoints, relative to
+// the `/v1` prefix both platforms mount them under
+pub fn openai_router(state: state::AppState) -> Router {
+    Router::new()
+        .route("/chat/completions", post(ai::chat_completions_handler))
+        .route("/chat/data-stream", post(data_stream::data_stream_handler))
+        .route("/agui", post(agui::run_agent_handler))
+        .route("/embeddings", post(ai::embeddings_handler))
+        .route("/models", get(ai::list_models_handler))
+        // Wildcard so namespaced ids like "org/model" resolve too
+        .route("/models/*model", get(ai::retrieve_model_handler))
+        .route(
+            "/threads",
+            post(threads::create_thread_handler).get(threads::list_threads_handler),
+        )
+        .route(
+            "/threads/:thread_id",
+            get(threads::get_thread_handler).delete(threads::delete_thread_handler),
+        )
+        .route(
+            "/threads/:thread_id/messages",
+            get(threads::list_messages_handler).post(threads::append_message_handler),
+        )
+        .route(
+            "/threads/:thread_id/messages/:message_id/branches",
+            get(threads::list_branches_handler),
+        )
+        .route("/threads/:thread_id/runs", post(threads::run_thread_handler))
+        .with_state(state)
+}
+
 // API handlers that return Axum-compatible responses
 pub async fn health_check_handler() -> Result<StatusCode, StatusCode> {
     log::info!("🩺 Health check requested");
@@ -1,7 +1,14 @@
 // MCP server registry and tool calling framework
 use crate::ai::{FunctionDefinition, Tool, ToolCall};
+use crate::mcp_client::{McpStdioClient, StdioTransport};
+use crate::tool_arguments::prepare_arguments;
+use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct McpServer {
@@ -31,6 +38,15 @@ pub struct McpTool {
 pub struct McpRegistry {
     servers: HashMap<String, McpServer>,
     tools: HashMap<String, McpTool>, // tool_name -> tool
+    clients: HashMap<String, Arc<McpStdioClient>>, // server_name -> live connection
+    timeouts: HashMap<String, Duration>, // server_name -> per-call timeout
+    coerce_arguments: bool,          // convert e.g. "5" to 5 where the schema expects it
+}
+
+impl Default for McpRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl McpRegistry {
@@ -38,9 +54,55 @@ impl McpRegistry {
         Self {
             servers: HashMap::new(),
             tools: HashMap::new(),
+            clients: HashMap::new(),
+            timeouts: HashMap::new(),
+            coerce_arguments: false,
         }
     }
 
+    pub fn set_argument_coercion(&mut self, enabled: bool) {
+        self.coerce_arguments = enabled;
+    }
+
+    // Per-call timeout for a server's tools; set before connecting so the client uses it too
+    pub fn set_tool_timeout(&mut self, server_name: &str, timeout: Duration) {
+        self.timeouts.insert(server_name.to_string(), timeout);
+    }
+
+    pub fn tool_timeout(&self, server_name: &str) -> Duration {
+        self.timeouts
+            .get(server_name)
+            .copied()
+            .unwrap_or(DEFAULT_TOOL_TIMEOUT)
+    }
+
+    // Spawn a stdio MCP server and register it with the tools it advertises via tools/list
+    pub async fn connect_stdio_server(
+        &mut self,
+        name: &str,
+        description: &str,
+        transport: &StdioTransport,
+    ) -> Result<(), crate::mcp_client::McpClientError> {
+        let client = McpStdioClient::spawn(name, transport, self.tool_timeout(name)).await?;
+        let tools = client.list_tools().await?;
+
+        log::info!(
+            "🧰 MCP server '{}' advertises {} tool(s)",
+            name,
+            tools.len()
+        );
+
+        self.register_server(McpServer {
+            name: name.to_string(),
+            description: description.to_string(),
+            version: client.server_info().version.clone(),
+            tools,
+            status: McpServerStatus::Active,
+        });
+        self.clients.insert(name.to_string(), Arc::new(client));
+        Ok(())
+    }
+
     // Register a new MCP server
     pub fn register_server(&mut self, server: McpServer) {
         log::info!("🔌 Registering MCP server: {}", server.name);
@@ -68,6 +130,10 @@ impl McpRegistry {
             .collect()
     }
 
+    pub fn has_tool(&self, tool_name: &str) -> bool {
+        self.tools.contains_key(tool_name)
+    }
+
     // Execute a tool call through the appropriate MCP server
     pub async fn execute_tool_call(&self, tool_call: &ToolCall) -> Result<String, String> {
         let tool_name = &tool_call.function.name;
@@ -79,11 +145,26 @@ impl McpRegistry {
                 mcp_tool.server
             );
 
-            // TODO: Implement actual MCP server communication via microsandbox
-            // For now, return a mock response
+            // Invalid arguments never reach the server; the model gets the issues back instead
+            let arguments = prepare_arguments(
+                tool_name,
+                &mcp_tool.schema,
+                &tool_call.function.arguments,
+                self.coerce_arguments,
+            )
+            .map_err(|e| e.to_tool_message())?;
+
+            if let Some(client) = self.clients.get(&mcp_tool.server) {
+                return client
+                    .call_tool(tool_name, arguments)
+                    .await
+                    .map_err(|e| e.to_string());
+            }
+
+            // Servers registered without a live connection fall back to a mock response
             let mock_result = format!(
                 "Tool '{}' executed with arguments: {}. (Mock result from MCP server '{}')",
-                tool_name, tool_call.function.arguments, mcp_tool.server
+                tool_name, arguments, mcp_tool.server
             );
 
             Ok(mock_result)
@@ -92,6 +173,46 @@ impl McpRegistry {
         }
     }
 
+    // Execute one assistant turn's tool calls, concurrently when `parallel` is set, each bounded
+    // by its server's timeout. Results are returned in the order of `tool_calls`.
+    pub async fn execute_tool_calls(
+        &self,
+        tool_calls: &[ToolCall],
+        parallel: bool,
+    ) -> Vec<Result<String, String>> {
+        if parallel && tool_calls.len() > 1 {
+            log::info!("⚡ Executing {} tool calls in parallel", tool_calls.len());
+            join_all(
+                tool_calls
+                    .iter()
+                    .map(|call| self.execute_with_timeout(call)),
+            )
+            .await
+        } else {
+            let mut results = Vec::with_capacity(tool_calls.len());
+            for call in tool_calls {
+                results.push(self.execute_with_timeout(call).await);
+            }
+            results
+        }
+    }
+
+    async fn execute_with_timeout(&self, tool_call: &ToolCall) -> Result<String, String> {
+        let timeout = self
+            .tools
+            .get(&tool_call.function.name)
+            .map_or(DEFAULT_TOOL_TIMEOUT, |tool| self.tool_timeout(&tool.server));
+
+        tokio::time::timeout(timeout, self.execute_tool_call(tool_call))
+            .await
+            .unwrap_or_else(|_| {
+                Err(format!(
+                    "Tool '{}' timed out after {:?}",
+                    tool_call.function.name, timeout
+                ))
+            })
+    }
+
     // Get list of registered servers
     pub fn get_servers(&self) -> Vec<&McpServer> {
         self.servers.values().collect()
@@ -107,103 +228,9 @@ impl McpRegistry {
                 self.tools.remove(&tool.name);
             }
         }
-    }
-}
-
-// Global MCP registry (in a real app, this would be managed by DI/state management)
-static mut GLOBAL_MCP_REGISTRY: Option<McpRegistry> = None;
-static INIT: std::sync::Once = std::sync::Once::new();
 
-pub fn get_mcp_registry() -> &'static mut McpRegistry {
-    unsafe {
-        INIT.call_once(|| {
-            GLOBAL_MCP_REGISTRY = Some(McpRegistry::new());
-        });
-        GLOBAL_MCP_REGISTRY.as_mut().unwrap()
+        // The subprocess is killed once the last handle to its client is dropped
+        self.clients.remove(server_name);
+        self.timeouts.remove(server_name);
     }
 }
-
-// Initialize with some default MCP servers for demonstration
-pub fn initialize_default_mcp_servers() {
-    let registry = get_mcp_registry();
-
-    // Example: File system MCP server
-    let fs_server = McpServer {
-        name: "filesystem".to_string(),
-        description: "File system operations".to_string(),
-        version: "1.0.0".to_string(),
-        status: McpServerStatus::Active,
-        tools: vec![
-            McpTool {
-                name: "read_file".to_string(),
-                description: "Read contents of a file".to_string(),
-                server: "filesystem".to_string(),
-                schema: serde_json::json!({
-                    "type": "object",
-                    "properties": {
-                        "path": {
-                            "type": "string",
-                            "description": "Path to the file to read"
-                        }
-                    },
-                    "required": ["path"]
-                }),
-            },
-            McpTool {
-                name: "write_file".to_string(),
-                description: "Write content to a file".to_string(),
-                server: "filesystem".to_string(),
-                schema: serde_json::json!({
-                    "type": "object",
-                    "properties": {
-                        "path": {
-                            "type": "string",
-                            "description": "Path to the file to write"
-                        },
-                        "content": {
-                            "type": "string",
-                            "description": "Content to write to the file"
-                        }
-                    },
-                    "required": ["path", "content"]
-                }),
-            },
-        ],
-    };
-
-    // Example: Web search MCP server
-    let web_server = McpServer {
-        name: "web_search".to_string(),
-        description: "Web search capabilities".to_string(),
-        version: "1.0.0".to_string(),
-        status: McpServerStatus::Active,
-        tools: vec![McpTool {
-            name: "search_web".to_string(),
-            description: "Search the web for information".to_string(),
-            server: "web_search".to_string(),
-            schema: serde_json::json!({
-                "type": "object",
-                "properties": {
-                    "query": {
-                        "type": "string",
-                        "description": "Search query"
-                    },
-                    "max_results": {
-                        "type": "integer",
-                        "description": "Maximum number of results to return",
-                        "default": 5
-                    }
-                },
-                "required": ["query"]
-            }),
-        }],
-    };
-
-    registry.register_server(fs_server);
-    registry.register_server(web_server);
-
-    log::info!(
-        "🚀 Initialized default MCP servers with {} tools",
-        registry.tools.len()
-    );
-}
@@ -0,0 +1,332 @@
+// MCP client speaking JSON-RPC 2.0 over a subprocess's stdin/stdout (newline-delimited)
+use crate::mcp::McpTool;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{oneshot, Mutex};
+
+pub const MCP_PROTOCOL_VERSION: &str = "2024-11-05";
+
+#[derive(Debug, thiserror::Error)]
+pub enum McpClientError {
+    #[error("failed to spawn MCP server: {0}")]
+    Spawn(#[source] std::io::Error),
+    #[error("I/O error talking to MCP server: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("MCP server returned error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("MCP protocol error: {0}")]
+    Protocol(String),
+    #[error("MCP tool reported an error: {0}")]
+    Tool(String),
+    #[error("MCP request '{0}' timed out")]
+    Timeout(String),
+    #[error("MCP server closed the connection")]
+    Closed,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StdioTransport {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+type PendingMap = HashMap<u64, oneshot::Sender<Result<Value, McpClientError>>>;
+
+pub struct McpStdioClient {
+    server_name: String,
+    server_info: ServerInfo,
+    stdin: Arc<Mutex<ChildStdin>>,
+    pending: Arc<Mutex<PendingMap>>,
+    next_id: AtomicU64,
+    request_timeout: Duration,
+    child: Mutex<Child>,
+}
+
+impl std::fmt::Debug for McpStdioClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpStdioClient")
+            .field("server_name", &self.server_name)
+            .field("server_info", &self.server_info)
+            .finish()
+    }
+}
+
+impl McpStdioClient {
+    // Spawn the server process and complete the initialize/initialized handshake
+    pub async fn spawn(
+        server_name: &str,
+        transport: &StdioTransport,
+        request_timeout: Duration,
+    ) -> Result<Self, McpClientError> {
+        log::info!(
+            "🔌 Spawning MCP server '{}': {} {}",
+            server_name,
+            transport.command,
+            transport.args.join(" ")
+        );
+
+        let mut command = Command::new(&transport.command);
+        command
+            .args(&transport.args)
+            .envs(&transport.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(cwd) = &transport.cwd {
+            command.current_dir(cwd);
+        }
+
+        let mut child = command.spawn().map_err(McpClientError::Spawn)?;
+        let stdin = child.stdin.take().ok_or(McpClientError::Closed)?;
+        let stdout = child.stdout.take().ok_or(McpClientError::Closed)?;
+
+        if let Some(stderr) = child.stderr.take() {
+            let name = server_name.to_string();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    log::debug!("[mcp:{}] {}", name, line);
+                }
+            });
+        }
+
+        let stdin = Arc::new(Mutex::new(stdin));
+        let pending: Arc<Mutex<PendingMap>> = Arc::new(Mutex::new(HashMap::new()));
+        tokio::spawn(read_loop(
+            server_name.to_string(),
+            BufReader::new(stdout),
+            stdin.clone(),
+            pending.clone(),
+        ));
+
+        let mut client = Self {
+            server_name: server_name.to_string(),
+            server_info: ServerInfo::default(),
+            stdin,
+            pending,
+            next_id: AtomicU64::new(1),
+            request_timeout,
+            child: Mutex::new(child),
+        };
+
+        let result = client
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": MCP_PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": "shared-handlers",
+                        "version": env!("CARGO_PKG_VERSION")
+                    }
+                }),
+            )
+            .await?;
+        client.server_info = serde_json::from_value(result["serverInfo"].clone())
+            .map_err(|e| McpClientError::Protocol(format!("invalid serverInfo: {}", e)))?;
+        client
+            .notify("notifications/initialized", json!({}))
+            .await?;
+
+        log::info!(
+            "✅ MCP server '{}' initialized ({} {})",
+            server_name,
+            client.server_info.name,
+            client.server_info.version
+        );
+        Ok(client)
+    }
+
+    pub fn server_info(&self) -> &ServerInfo {
+        &self.server_info
+    }
+
+    // Discover the server's tools via `tools/list`, following pagination cursors
+    pub async fn list_tools(&self) -> Result<Vec<McpTool>, McpClientError> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params).await?;
+
+            for tool in result["tools"].as_array().into_iter().flatten() {
+                let name = tool["name"]
+                    .as_str()
+                    .ok_or_else(|| McpClientError::Protocol("tool without a name".to_string()))?;
+                tools.push(McpTool {
+                    name: name.to_string(),
+                    description: tool["description"].as_str().unwrap_or_default().to_string(),
+                    schema: tool
+                        .get("inputSchema")
+                        .cloned()
+                        .unwrap_or_else(|| json!({ "type": "object" })),
+                    server: self.server_name.clone(),
+                });
+            }
+
+            cursor = result["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    // Invoke a tool via `tools/call` and flatten its text content into a single string
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<String, McpClientError> {
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await?;
+
+        let text = result["content"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|part| match part["type"].as_str() {
+                Some("text") => part["text"].as_str().unwrap_or_default().to_string(),
+                _ => part.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if result["isError"].as_bool().unwrap_or(false) {
+            Err(McpClientError::Tool(text))
+        } else {
+            Ok(text)
+        }
+    }
+
+    pub async fn shutdown(&self) {
+        log::info!("🛑 Stopping MCP server '{}'", self.server_name);
+        if let Err(e) = self.child.lock().await.kill().await {
+            log::warn!("⚠️ Failed to stop MCP server '{}': {}", self.server_name, e);
+        }
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, McpClientError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert(id, tx);
+
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if let Err(e) = write_message(&self.stdin, &message).await {
+            self.pending.lock().await.remove(&id);
+            return Err(e);
+        }
+
+        match tokio::time::timeout(self.request_timeout, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(McpClientError::Closed),
+            Err(_) => {
+                self.pending.lock().await.remove(&id);
+                Err(McpClientError::Timeout(method.to_string()))
+            }
+        }
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<(), McpClientError> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&self.stdin, &message).await
+    }
+}
+
+async fn write_message(stdin: &Mutex<ChildStdin>, message: &Value) -> Result<(), McpClientError> {
+    let mut line = message.to_string();
+    line.push('\n');
+    let mut stdin = stdin.lock().await;
+    stdin.write_all(line.as_bytes()).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+// Route responses to their waiting requests and answer server-initiated requests
+async fn read_loop(
+    server_name: String,
+    stdout: BufReader<tokio::process::ChildStdout>,
+    stdin: Arc<Mutex<ChildStdin>>,
+    pending: Arc<Mutex<PendingMap>>,
+) {
+    let mut lines = stdout.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!(
+                    "⚠️ Ignoring non-JSON output from MCP server '{}': {}",
+                    server_name,
+                    e
+                );
+                continue;
+            }
+        };
+
+        match (
+            message.get("id").and_then(Value::as_u64),
+            message.get("method"),
+        ) {
+            (Some(id), None) => {
+                let Some(tx) = pending.lock().await.remove(&id) else {
+                    continue;
+                };
+                let result = match message.get("error") {
+                    Some(error) => Err(McpClientError::Rpc {
+                        code: error["code"].as_i64().unwrap_or(0),
+                        message: error["message"].as_str().unwrap_or_default().to_string(),
+                    }),
+                    None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                };
+                let _ = tx.send(result);
+            }
+            (_, Some(method)) if message.get("id").is_some() => {
+                let reply = if method == "ping" {
+                    json!({ "jsonrpc": "2.0", "id": message["id"], "result": {} })
+                } else {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": { "code": -32601, "message": format!("Method not found: {}", method) }
+                    })
+                };
+                if let Err(e) = write_message(&stdin, &reply).await {
+                    log::warn!("⚠️ Failed to answer MCP server '{}': {}", server_name, e);
+                }
+            }
+            _ => log::debug!("[mcp:{}] notification: {}", server_name, line),
+        }
+    }
+
+    log::info!("🔌 MCP server '{}' closed its output", server_name);
+    for (_, tx) in pending.lock().await.drain() {
+        let _ = tx.send(Err(McpClientError::Closed));
+    }
+}
@@ -0,0 +1,215 @@
+// Model catalog served at /v1/models; aliases such as "default", "fast" and "smart" are
+// resolved to concrete model ids before a chat completion is dispatched
+use crate::config::{default_config_dir, expand_env_vars, read_yaml, ConfigError, ValidationIssue};
+use crate::tokenizer::Encoding;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+pub const MODELS_FILE: &str = "models.yaml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelInfo {
+    pub id: String,
+    #[serde(default = "default_object")]
+    pub object: String, // "model"
+    #[serde(default)]
+    pub created: u64,
+    #[serde(default = "default_owned_by")]
+    pub owned_by: String,
+    pub context_window: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(default)]
+    pub capabilities: ModelCapabilities,
+    // BPE vocabulary used for token counting; inferred from the model id when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<Encoding>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelCapabilities {
+    #[serde(default)]
+    pub tools: bool,
+    #[serde(default)]
+    pub vision: bool,
+    #[serde(default)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelList {
+    pub object: String, // "list"
+    pub data: Vec<ModelInfo>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelCatalog {
+    pub models: Vec<ModelInfo>,
+    // Alias -> model id
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+fn default_object() -> String {
+    "model".to_string()
+}
+
+fn default_owned_by() -> String {
+    "system".to_string()
+}
+
+impl Default for ModelCatalog {
+    fn default() -> Self {
+        let model = |id: &str, context_window, max_output_tokens, vision| ModelInfo {
+            id: id.to_string(),
+            object: default_object(),
+            created: 0,
+            owned_by: "openai".to_string(),
+            context_window,
+            max_output_tokens: Some(max_output_tokens),
+            capabilities: ModelCapabilities {
+                tools: true,
+                vision,
+                json: true,
+            },
+            tokenizer: None,
+        };
+
+        Self {
+            models: vec![
+                model("gpt-4o", 128_000, 16_384, true),
+                model("gpt-4o-mini", 128_000, 16_384, true),
+                model("gpt-4", 8_192, 8_192, false),
+            ],
+            aliases: [
+                ("default", "gpt-4o-mini"),
+                ("fast", "gpt-4o-mini"),
+                ("smart", "gpt-4o"),
+            ]
+            .into_iter()
+            .map(|(alias, id)| (alias.to_string(), id.to_string()))
+            .collect(),
+        }
+    }
+}
+
+impl ModelInfo {
+    pub fn encoding(&self) -> Encoding {
+        self.tokenizer
+            .unwrap_or_else(|| Encoding::for_model(&self.id))
+    }
+}
+
+impl ModelCatalog {
+    // Load and validate a catalog file (`${VAR}` references are expanded)
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let mut value = read_yaml(path)?;
+        expand_env_vars(&mut value);
+
+        let catalog: ModelCatalog =
+            serde_path_to_error::deserialize(value).map_err(|e| ConfigError::Schema {
+                path: path.to_path_buf(),
+                field: e.path().to_string(),
+                message: e.into_inner().to_string(),
+            })?;
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    // `<config dir>/models.yaml` when present, otherwise the built-in catalog
+    pub fn from_env() -> Self {
+        let path = default_config_dir().join(MODELS_FILE);
+        if !path.exists() {
+            log::info!(
+                "📋 No {} found, using the built-in model catalog",
+                MODELS_FILE
+            );
+            return Self::default();
+        }
+
+        match Self::load(&path) {
+            Ok(catalog) => {
+                log::info!(
+                    "📋 Loaded {} models and {} aliases from {}",
+                    catalog.models.len(),
+                    catalog.aliases.len(),
+                    path.display()
+                );
+                catalog
+            }
+            Err(e) => {
+                log::error!("❌ Failed to load model catalog: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut issues = Vec::new();
+        let mut ids = HashSet::new();
+
+        for (i, model) in self.models.iter().enumerate() {
+            let path = format!("models[{}]", i);
+            if model.id.trim().is_empty() {
+                issues.push(issue(format!("{}.id", path), "must not be empty"));
+            } else if !ids.insert(model.id.as_str()) {
+                issues.push(issue(
+                    format!("{}.id", path),
+                    &format!("duplicate model id '{}'", model.id),
+                ));
+            }
+            if model.context_window == 0 {
+                issues.push(issue(
+                    format!("{}.context_window", path),
+                    "must be greater than 0",
+                ));
+            }
+        }
+
+        for (alias, target) in &self.aliases {
+            let path = format!("aliases.{}", alias);
+            if ids.contains(alias.as_str()) {
+                issues.push(issue(path, "alias shadows a model id"));
+            } else if !ids.contains(target.as_str()) {
+                issues.push(issue(
+                    path,
+                    &format!("points to unknown model '{}'", target),
+                ));
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Validation(issues))
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ModelInfo> {
+        self.models.iter().find(|model| model.id == id)
+    }
+
+    // Look up a model by id or alias
+    pub fn resolve(&self, name: &str) -> Option<&ModelInfo> {
+        self.get(name)
+            .or_else(|| self.aliases.get(name).and_then(|id| self.get(id)))
+    }
+
+    pub fn list(&self) -> ModelList {
+        ModelList {
+            object: "list".to_string(),
+            data: self.models.clone(),
+        }
+    }
+}
+
+fn issue(path: String, message: &str) -> ValidationIssue {
+    ValidationIssue {
+        path,
+        message: message.to_string(),
+    }
+}
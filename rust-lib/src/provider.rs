@@ -0,0 +1,598 @@
+// Chat completion providers: the canned "mock" backend and OpenAI-compatible upstreams
+use crate::ai::{
+    ChatChoice, ChatCompletionRequest, ChatCompletionResponse, ChatMessage, ChoiceLogprobs,
+    FinishReason, FunctionCall, MessageContent, ResponseFormat, Role, TokenLogprob, Tool, ToolCall,
+    ToolChoiceMode, TopLogprob, Usage,
+};
+use crate::stream::{decode_sse, enforce_tool_choice_stream, response_to_chunks, ChunkStream};
+use crate::structured::sample_from_schema;
+use crate::tokenizer::Encoding;
+use crate::upstream::{parse_retry_after, UpstreamConfig};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProviderError {
+    #[error("request to upstream provider failed: {0}")]
+    Request(String),
+    #[error("upstream provider returned {status}: {message}")]
+    Status {
+        status: u16,
+        message: String,
+        // From the upstream's Retry-After header, if it sent one
+        retry_after: Option<Duration>,
+    },
+    #[error("invalid response from upstream provider: {0}")]
+    InvalidResponse(String),
+    #[error("upstream provider timed out: {0}")]
+    Timeout(String),
+    #[error("upstream provider did not honor tool_choice: {0}")]
+    ToolChoice(String),
+    #[error("upstream provider unavailable: {0}")]
+    Unavailable(String),
+}
+
+impl ProviderError {
+    // Transient upstream trouble worth another attempt: connection failures, timeouts, 408, 429
+    // and 5xx responses
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ProviderError::Request(_) | ProviderError::Timeout(_) => true,
+            ProviderError::Status { status, .. } => {
+                matches!(status, 408 | 429) || (500..600).contains(status)
+            }
+            _ => false,
+        }
+    }
+
+    // Rejections of the request itself, which no other upstream would accept either
+    pub fn is_caller_error(&self) -> bool {
+        matches!(
+            self,
+            ProviderError::Status {
+                status: 400 | 413 | 422,
+                ..
+            }
+        )
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ProviderError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+// A backend capable of answering OpenAI-shaped chat completion requests.
+// Implementations must honor `tool_choice` ("none", "required" or a named function).
+#[async_trait]
+pub trait ChatProvider: Send + Sync {
+    // Short identifier used in logs and configuration ("mock", "openai", ...)
+    fn name(&self) -> &str;
+
+    async fn complete(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError>;
+
+    // Streamed variant; providers without native streaming replay the full response as chunks
+    async fn complete_stream(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChunkStream, ProviderError> {
+        let include_usage = request
+            .stream_options
+            .as_ref()
+            .is_some_and(|options| options.include_usage);
+        let response = self.complete(request).await?;
+        let chunks = response_to_chunks(response, include_usage);
+        Ok(stream::iter(chunks.into_iter().map(Ok)).boxed())
+    }
+}
+
+// Canned responses for local development; decides on tool calls by keyword-matching the query
+pub struct MockProvider;
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ChatProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn complete(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let user_query = request
+            .messages
+            .iter()
+            .rev()
+            .find(|msg| msg.role == Role::User)
+            .and_then(|msg| msg.content.as_ref())
+            .map(MessageContent::to_text)
+            .unwrap_or_default();
+
+        let has_tool_call_response = request.messages.iter().any(|msg| msg.role == Role::Tool);
+        let available_tools = request.tools.as_ref();
+        let tools = available_tools.map(Vec::as_slice).unwrap_or_default();
+        let find_tool = |name: &str| tools.iter().find(|tool| tool.function.name == name);
+
+        // Keyword heuristic for "auto"; each matching tool becomes its own call, so a query
+        // like "search ... and read the file" produces parallel calls. tool_choice overrides it.
+        let keyword_tools: Vec<&Tool> = [
+            (user_query.contains("search"), "search_web"),
+            (
+                user_query.contains("file") || user_query.contains("read"),
+                "read_file",
+            ),
+        ]
+        .into_iter()
+        .filter(|(matched, _)| *matched)
+        .filter_map(|(_, name)| find_tool(name))
+        .collect();
+        let mut chosen_tools: Vec<&Tool> = match request.tool_choice_mode() {
+            ToolChoiceMode::None => Vec::new(),
+            ToolChoiceMode::Auto if has_tool_call_response => Vec::new(),
+            ToolChoiceMode::Auto => keyword_tools,
+            ToolChoiceMode::Required if keyword_tools.is_empty() => {
+                tools.first().into_iter().collect()
+            }
+            ToolChoiceMode::Required => keyword_tools,
+            ToolChoiceMode::Function(name) => find_tool(name).into_iter().collect(),
+        };
+        if !request.allows_parallel_tool_calls() {
+            chosen_tools.truncate(1);
+        }
+
+        let encoding = Encoding::for_model(&request.model);
+        let prompt_tokens = encoding.count_prompt(request);
+
+        let (response_message, finish_reason) = if !chosen_tools.is_empty() {
+            let timestamp = chrono::Utc::now().timestamp();
+            let tool_calls: Vec<ToolCall> = chosen_tools
+                .iter()
+                .enumerate()
+                .map(|(i, tool)| ToolCall {
+                    id: format!("call_{}_{}", timestamp, i),
+                    r#type: "function".to_string(),
+                    function: FunctionCall {
+                        name: tool.function.name.clone(),
+                        arguments: mock_arguments(tool, &user_query).to_string(),
+                    },
+                })
+                .collect();
+
+            let message = ChatMessage {
+                role: Role::Assistant,
+                content: None,
+                tool_calls: Some(tool_calls),
+                tool_call_id: None,
+                name: None,
+            };
+
+            (message, FinishReason::ToolCalls)
+        } else {
+            let context_info = if request
+                .messages
+                .iter()
+                .any(|msg| msg.name.as_deref() == Some("rag_context"))
+            {
+                " (Enhanced with RAG context from your documents)"
+            } else {
+                ""
+            };
+
+            let tool_info = available_tools
+                .map(|tools| format!(" {} MCP tools are available.", tools.len()))
+                .unwrap_or_default();
+
+            let canned = format!(
+                "This is a response from the shared Rust handler with full tool calling and RAG support. \
+                 You sent {} messages to model '{}'.{}{} \
+                 This response is compatible with assistant-ui and ag-ui.",
+                request.messages.len(),
+                request.model,
+                context_info,
+                tool_info
+            );
+            // Structured requests get JSON that satisfies the requested format
+            let content = match &request.response_format {
+                Some(ResponseFormat::JsonObject) => {
+                    serde_json::json!({ "message": canned }).to_string()
+                }
+                Some(format @ ResponseFormat::JsonSchema { .. }) => format
+                    .schema()
+                    .map_or_else(
+                        || serde_json::json!({ "message": canned }),
+                        sample_from_schema,
+                    )
+                    .to_string(),
+                _ => canned,
+            };
+            let (content, finish_reason) = end_generation(content, request, encoding);
+
+            let message = ChatMessage {
+                role: Role::Assistant,
+                content: Some(content.into()),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            };
+
+            (message, finish_reason)
+        };
+
+        // The canned answer is the only possible one, so every choice is identical and every
+        // token has probability 1
+        let logprobs = match (&response_message.content, request.logprobs) {
+            (Some(content), Some(true)) => Some(mock_logprobs(
+                &content.to_text(),
+                request.top_logprobs,
+                encoding,
+            )),
+            _ => None,
+        };
+        let n = request.n.unwrap_or(1).max(1);
+        let choices: Vec<ChatChoice> = (0..n)
+            .map(|index| ChatChoice {
+                index,
+                message: response_message.clone(),
+                finish_reason,
+                logprobs: logprobs.clone(),
+            })
+            .collect();
+        let completion_tokens = encoding.count_completion(&response_message) * n;
+
+        Ok(ChatCompletionResponse {
+            id: format!("chatcmpl-{}", chrono::Utc::now().timestamp()),
+            object: "chat.completion".to_string(),
+            created: chrono::Utc::now().timestamp() as u64,
+            model: request.model.clone(),
+            choices,
+            usage: Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            },
+            agent_trace: None,
+            served_by: None,
+        })
+    }
+}
+
+// Where generation ends: just before the first stop sequence (finish_reason "stop"), or after
+// `max_tokens` tokens (finish_reason "length")
+fn end_generation(
+    mut text: String,
+    request: &ChatCompletionRequest,
+    encoding: Encoding,
+) -> (String, FinishReason) {
+    let stop_at = request
+        .stop
+        .iter()
+        .flat_map(|stop| stop.as_slice())
+        .filter_map(|sequence| text.find(sequence.as_str()))
+        .min();
+    if let Some(position) = stop_at {
+        text.truncate(position);
+    }
+
+    match request
+        .max_tokens
+        .and_then(|max_tokens| encoding.truncate(&text, max_tokens))
+    {
+        Some(truncated) => (truncated, FinishReason::Length),
+        None => (text, FinishReason::Stop),
+    }
+}
+
+fn mock_logprobs(text: &str, top_logprobs: Option<u32>, encoding: Encoding) -> ChoiceLogprobs {
+    let content = encoding
+        .split(text)
+        .into_iter()
+        .map(|bytes| {
+            let token = String::from_utf8_lossy(&bytes).into_owned();
+            let top_logprobs = match top_logprobs {
+                Some(n) if n > 0 => vec![TopLogprob {
+                    token: token.clone(),
+                    logprob: 0.0,
+                    bytes: Some(bytes.clone()),
+                }],
+                _ => Vec::new(),
+            };
+            TokenLogprob {
+                token,
+                logprob: 0.0,
+                bytes: Some(bytes),
+                top_logprobs,
+            }
+        })
+        .collect();
+    ChoiceLogprobs {
+        content: Some(content),
+    }
+}
+
+// Canned arguments: the known demo tools keep their fixed shapes, other tools get the user
+// query for every required string parameter
+fn mock_arguments(tool: &Tool, user_query: &str) -> serde_json::Value {
+    match tool.function.name.as_str() {
+        "search_web" => serde_json::json!({"query": user_query, "max_results": 3}),
+        "read_file" => serde_json::json!({"path": "/example/file.txt"}),
+        _ => {
+            let schema = &tool.function.parameters;
+            let arguments: serde_json::Map<String, serde_json::Value> = schema["required"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|name| name.as_str())
+                .filter(|name| schema["properties"][*name]["type"] == "string")
+                .map(|name| (name.to_string(), serde_json::json!(user_query)))
+                .collect();
+            serde_json::Value::Object(arguments)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenAiConfig {
+    pub name: String,
+    // Base URL including the version prefix, e.g. "https://api.openai.com/v1"
+    pub base_url: String,
+    pub api_key: Option<String>,
+    // Client-facing model name -> upstream model name
+    pub model_map: HashMap<String, String>,
+}
+
+impl OpenAiConfig {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            name: "openai".to_string(),
+            base_url: base_url.into(),
+            api_key: None,
+            model_map: HashMap::new(),
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_model(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.model_map.insert(from.into(), to.into());
+        self
+    }
+}
+
+// Forwards completions to any server speaking the OpenAI chat completions API
+pub struct OpenAiProvider {
+    config: OpenAiConfig,
+    client: reqwest::Client,
+}
+
+impl OpenAiProvider {
+    pub fn new(config: OpenAiConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn upstream_model(&self, model: &str) -> String {
+        self.config
+            .model_map
+            .get(model)
+            .cloned()
+            .unwrap_or_else(|| model.to_string())
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}", self.config.base_url.trim_end_matches('/'), path)
+    }
+
+    // POST a chat completion upstream with the model mapped, rejecting non-2xx responses
+    async fn send(
+        &self,
+        request: &ChatCompletionRequest,
+        stream: bool,
+    ) -> Result<reqwest::Response, ProviderError> {
+        let mut upstream_request = request.clone();
+        upstream_request.model = self.upstream_model(&request.model);
+        upstream_request.stream = stream.then_some(true);
+        if !stream {
+            upstream_request.stream_options = None;
+        }
+
+        log::info!(
+            "🌐 Forwarding chat completion to '{}' (model={}, stream={})",
+            self.config.name,
+            upstream_request.model,
+            stream
+        );
+
+        let mut builder = self
+            .client
+            .post(self.endpoint("chat/completions"))
+            .json(&upstream_request);
+        if let Some(api_key) = &self.config.api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+
+        let response = builder.send().await.map_err(|e| {
+            if e.is_timeout() {
+                ProviderError::Timeout(e.to_string())
+            } else {
+                ProviderError::Request(e.to_string())
+            }
+        })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+            let message = response.text().await.unwrap_or_default();
+            return Err(ProviderError::Status {
+                status: status.as_u16(),
+                message,
+                retry_after,
+            });
+        }
+
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl ChatProvider for OpenAiProvider {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    async fn complete(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let response = self
+            .send(request, false)
+            .await?
+            .json::<ChatCompletionResponse>()
+            .await
+            .map_err(|e| ProviderError::InvalidResponse(e.to_string()))?;
+        enforce_tool_choice(
+            request.tool_choice_mode(),
+            request.allows_parallel_tool_calls(),
+            response,
+        )
+    }
+
+    async fn complete_stream(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChunkStream, ProviderError> {
+        let chunks = decode_sse(self.send(request, true).await?);
+        Ok(enforce_tool_choice_stream(
+            request.tool_choice_mode(),
+            request.allows_parallel_tool_calls(),
+            chunks,
+        ))
+    }
+}
+
+// Not every OpenAI-compatible server implements tool_choice or parallel_tool_calls, so check
+// the upstream answer: stray calls are dropped, a missing forced call is an error
+fn enforce_tool_choice(
+    mode: ToolChoiceMode<'_>,
+    parallel: bool,
+    mut response: ChatCompletionResponse,
+) -> Result<ChatCompletionResponse, ProviderError> {
+    for choice in &mut response.choices {
+        let message = &mut choice.message;
+        match mode {
+            ToolChoiceMode::Auto => {}
+            ToolChoiceMode::None => {
+                if message
+                    .tool_calls
+                    .take()
+                    .is_some_and(|calls| !calls.is_empty())
+                {
+                    log::warn!("⚠️ Dropping tool calls returned despite tool_choice 'none'");
+                }
+                message
+                    .content
+                    .get_or_insert_with(|| MessageContent::Text(String::new()));
+                if choice.finish_reason == FinishReason::ToolCalls {
+                    choice.finish_reason = FinishReason::Stop;
+                }
+            }
+            ToolChoiceMode::Required | ToolChoiceMode::Function(_) => {
+                if let (ToolChoiceMode::Function(name), Some(calls)) =
+                    (mode, message.tool_calls.as_mut())
+                {
+                    calls.retain(|call| call.function.name == name);
+                }
+                if message
+                    .tool_calls
+                    .as_ref()
+                    .is_none_or(|calls| calls.is_empty())
+                {
+                    return Err(ProviderError::ToolChoice(match mode {
+                        ToolChoiceMode::Function(name) => format!("expected a call to '{}'", name),
+                        _ => "expected at least one tool call".to_string(),
+                    }));
+                }
+                choice.finish_reason = FinishReason::ToolCalls;
+            }
+        }
+        if let (false, Some(calls)) = (parallel, choice.message.tool_calls.as_mut()) {
+            if calls.len() > 1 {
+                log::warn!(
+                    "⚠️ Keeping 1 of {} tool calls because parallel_tool_calls is false",
+                    calls.len()
+                );
+            }
+            calls.truncate(1);
+        }
+    }
+    Ok(response)
+}
+
+// Build the provider selected by the environment. `<config dir>/providers.yaml`, when present,
+// defines upstreams with retries and fallback chains (see upstream.rs); otherwise:
+//   AI_PROVIDER      "mock" (default) or "openai"
+//   OPENAI_BASE_URL  upstream base URL (default https://api.openai.com/v1)
+//   OPENAI_API_KEY   bearer token sent upstream
+//   AI_MODEL_MAP     comma-separated "client=upstream" model pairs
+pub fn provider_from_env() -> Arc<dyn ChatProvider> {
+    if let Some(upstreams) = UpstreamConfig::from_env() {
+        return upstreams.build();
+    }
+    match std::env::var("AI_PROVIDER").as_deref() {
+        Ok("openai") => {
+            let base_url = std::env::var("OPENAI_BASE_URL")
+                .unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
+            let mut config = OpenAiConfig::new(base_url);
+            if let Ok(api_key) = std::env::var("OPENAI_API_KEY") {
+                config = config.with_api_key(api_key);
+            }
+            if let Ok(model_map) = std::env::var("AI_MODEL_MAP") {
+                for pair in model_map.split(',') {
+                    if let Some((from, to)) = pair.split_once('=') {
+                        config = config.with_model(from.trim(), to.trim());
+                    }
+                }
+            }
+            log::info!("🌐 Using OpenAI-compatible provider at {}", config.base_url);
+            Arc::new(OpenAiProvider::new(config))
+        }
+        Ok(other) if other != "mock" => {
+            log::warn!("⚠️ Unknown AI_PROVIDER '{}', falling back to mock", other);
+            Arc::new(MockProvider::new())
+        }
+        _ => Arc::new(MockProvider::new()),
+    }
+}
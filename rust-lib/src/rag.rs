@@ -1,6 +1,11 @@
-// RAG (Retrieval-Augmented Generation) integration using PGLite
-use crate::ai::ChatMessage;
+// RAG (Retrieval-Augmented Generation) backed by the embedded vector store
+use crate::ai::{ChatMessage, Role};
+use crate::embedding::{Embedder, HashingEmbedder};
+use crate::tokenizer::Encoding;
+use crate::vector_store::{Similarity, VectorStore};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Document {
@@ -23,33 +28,86 @@ pub struct RagContext {
 #[derive(Debug, Clone)]
 pub struct RagConfig {
     pub max_documents: usize,
-    pub relevance_threshold: f32,
+    // Minimum retrieval score; None uses the embedder's default
+    pub relevance_threshold: Option<f32>,
     pub max_context_tokens: usize,
     pub embedding_model: String,
+    pub similarity: Similarity,
+    // redb file holding the index; None keeps documents in memory only
+    pub storage_path: Option<PathBuf>,
 }
 
 impl Default for RagConfig {
     fn default() -> Self {
         Self {
             max_documents: 5,
-            relevance_threshold: 0.7,
+            relevance_threshold: None,
             max_context_tokens: 4000,
             embedding_model: "text-embedding-ada-002".to_string(),
+            similarity: Similarity::Cosine,
+            storage_path: None,
         }
     }
 }
 
 pub struct RagService {
     config: RagConfig,
+    store: VectorStore,
+    embedder: Arc<dyn Embedder>,
+    relevance_threshold: f32,
 }
 
 impl RagService {
+    // Uses the offline hashing embedder; see `with_embedder` for other backends
     pub fn new(config: RagConfig) -> Self {
-        Self { config }
+        Self::with_embedder(config, Arc::new(HashingEmbedder::default()))
     }
 
-    // Retrieve relevant documents for a query
-    // Note: In a real implementation, this would query PGLite with vector search
+    pub fn with_embedder(config: RagConfig, embedder: Arc<dyn Embedder>) -> Self {
+        let store = match &config.storage_path {
+            Some(path) => VectorStore::open(path).unwrap_or_else(|e| {
+                log::error!(
+                    "❌ Failed to open vector store at {}: {}; falling back to memory",
+                    path.display(),
+                    e
+                );
+                VectorStore::in_memory()
+            }),
+            None => VectorStore::in_memory(),
+        };
+        let relevance_threshold = config
+            .relevance_threshold
+            .unwrap_or_else(|| embedder.default_relevance_threshold());
+        log::info!(
+            "🔍 RAG relevance threshold {} for embedder {}",
+            relevance_threshold,
+            embedder.model()
+        );
+        Self {
+            config,
+            store,
+            embedder,
+            relevance_threshold,
+        }
+    }
+
+    pub fn config(&self) -> &RagConfig {
+        &self.config
+    }
+
+    pub fn embedder(&self) -> &Arc<dyn Embedder> {
+        &self.embedder
+    }
+
+    pub fn relevance_threshold(&self) -> f32 {
+        self.relevance_threshold
+    }
+
+    pub fn document_count(&self) -> usize {
+        self.store.len()
+    }
+
+    // Retrieve relevant documents for a query by embedding it and searching the index
     pub async fn retrieve_context(
         &self,
         query: &str,
@@ -57,61 +115,52 @@ impl RagService {
     ) -> Result<RagContext, String> {
         log::info!("🔍 Retrieving RAG context for query: {}", query);
 
-        // TODO: Implement actual PGLite vector search
-        // This would involve:
-        // 1. Generate embedding for the query
-        // 2. Query PGLite for similar document embeddings
-        // 3. Rank by relevance score
-        // 4. Return top K documents under token limit
-
-        // Mock implementation for now
-        let mock_documents = vec![
-            Document {
-                id: "doc_1".to_string(),
-                title: "Architecture Overview".to_string(),
-                content: format!(
-                    "This document contains relevant information about {}. \
-                     The ONE reference architecture uses React, Rust, and Tauri for cross-platform development.",
-                    query
-                ),
-                metadata: serde_json::json!({
-                    "source": "user_documents",
-                    "user_id": user_id,
-                    "type": "architecture_doc"
-                }),
-                embedding: Some(vec![0.1, 0.2, 0.3]), // Mock embedding
-                created_at: chrono::Utc::now(),
-            },
-            Document {
-                id: "doc_2".to_string(),
-                title: "Implementation Details".to_string(),
-                content: format!(
-                    "Implementation details related to {}. \
-                     The shared Rust handlers provide consistent API responses across web and desktop platforms.",
-                    query
-                ),
-                metadata: serde_json::json!({
-                    "source": "user_documents", 
-                    "user_id": user_id,
-                    "type": "implementation_doc"
-                }),
-                embedding: Some(vec![0.2, 0.3, 0.4]), // Mock embedding
-                created_at: chrono::Utc::now(),
-            }
-        ];
+        let query_embedding = self
+            .embedder
+            .embed_one(query)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(self.retrieve_context_for_embedding(query, &query_embedding, user_id))
+    }
 
-        let relevance_scores = vec![0.85, 0.78]; // Mock relevance scores
-        let total_tokens = mock_documents
-            .iter()
-            .map(|doc| doc.content.len() / 4) // Rough token estimation
-            .sum();
+    // Top documents for a pre-computed query embedding, limited by max_documents,
+    // relevance_threshold and the max_context_tokens budget
+    pub fn retrieve_context_for_embedding(
+        &self,
+        query: &str,
+        query_embedding: &[f32],
+        user_id: Option<&str>,
+    ) -> RagContext {
+        let matches = self.store.search(
+            query_embedding,
+            self.config.max_documents,
+            self.relevance_threshold,
+            self.config.similarity,
+            |doc| visible_to(doc, user_id),
+        );
 
-        Ok(RagContext {
-            documents: mock_documents,
+        let mut context = RagContext {
+            documents: Vec::new(),
             query: query.to_string(),
-            relevance_scores,
-            total_tokens,
-        })
+            relevance_scores: Vec::new(),
+            total_tokens: 0,
+        };
+        for (document, score) in matches {
+            let tokens = Encoding::default().count(&document.content) as usize;
+            if context.total_tokens + tokens > self.config.max_context_tokens {
+                break;
+            }
+            context.total_tokens += tokens;
+            context.documents.push(document);
+            context.relevance_scores.push(score);
+        }
+
+        log::info!(
+            "🔍 Found {} relevant documents ({} tokens)",
+            context.documents.len(),
+            context.total_tokens
+        );
+        context
     }
 
     // Add RAG context to chat messages
@@ -128,8 +177,8 @@ impl RagService {
         let context_content = self.format_context_for_llm(context);
 
         let context_message = ChatMessage {
-            role: "system".to_string(),
-            content: Some(context_content),
+            role: Role::System,
+            content: Some(context_content.into()),
             tool_calls: None,
             tool_call_id: None,
             name: Some("rag_context".to_string()),
@@ -138,7 +187,7 @@ impl RagService {
         // Insert context message after any existing system messages but before user messages
         let insert_position = messages
             .iter()
-            .position(|msg| msg.role == "user")
+            .position(|msg| msg.role == Role::User)
             .unwrap_or(messages.len());
 
         messages.insert(insert_position, context_message);
@@ -171,11 +220,10 @@ impl RagService {
         formatted
     }
 
-    // Store a document for future RAG retrieval
-    // Note: In a real implementation, this would store in PGLite with vector embedding
+    // Store a document for future RAG retrieval, embedding its title and content if needed
     pub async fn store_document(
         &self,
-        document: Document,
+        mut document: Document,
         user_id: Option<&str>,
     ) -> Result<String, String> {
         log::info!(
@@ -184,14 +232,30 @@ impl RagService {
             user_id
         );
 
-        // TODO: Implement actual PGLite storage
-        // This would involve:
-        // 1. Generate embedding for document content
-        // 2. Store document and embedding in PGLite
-        // 3. Create indexes for efficient vector search
+        if let (Some(user_id), Some(metadata)) = (user_id, document.metadata.as_object_mut()) {
+            metadata.insert("user_id".to_string(), serde_json::json!(user_id));
+        }
+
+        if document.embedding.as_ref().is_none_or(|e| e.is_empty()) {
+            let text = format!("{}\n{}", document.title, document.content);
+            let embedding = self
+                .embedder
+                .embed_one(&text)
+                .await
+                .map_err(|e| e.to_string())?;
+            document.embedding = Some(embedding);
+        }
+
+        let id = document.id.clone();
+        self.store
+            .upsert(document)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(id)
+    }
 
-        // Mock implementation
-        Ok(document.id)
+    pub async fn delete_document(&self, id: &str) -> Result<bool, String> {
+        self.store.remove(id).await.map_err(|e| e.to_string())
     }
 
     // Search stored documents
@@ -199,7 +263,7 @@ impl RagService {
         &self,
         query: &str,
         user_id: Option<&str>,
-        _limit: usize,
+        limit: usize,
     ) -> Result<Vec<Document>, String> {
         log::info!(
             "🔍 Searching documents for: {} (user: {:?})",
@@ -207,30 +271,18 @@ impl RagService {
             user_id
         );
 
-        // TODO: Implement actual PGLite vector search
-        // This would query the vector embeddings stored in PGLite
-
-        // Mock implementation
-        let context = self.retrieve_context(query, user_id).await?;
+        let mut context = self.retrieve_context(query, user_id).await?;
+        context.documents.truncate(limit);
         Ok(context.documents)
     }
 }
 
-// Global RAG service (in a real app, this would be managed by DI/state management)
-static mut GLOBAL_RAG_SERVICE: Option<RagService> = None;
-static RAG_INIT: std::sync::Once = std::sync::Once::new();
-
-pub fn get_rag_service() -> &'static RagService {
-    unsafe {
-        RAG_INIT.call_once(|| {
-            GLOBAL_RAG_SERVICE = Some(RagService::new(RagConfig::default()));
-        });
-        GLOBAL_RAG_SERVICE.as_ref().unwrap()
+// Documents without an owner are shared; owned documents are only visible to their user, so
+// anonymous callers see shared documents only
+fn visible_to(document: &Document, user_id: Option<&str>) -> bool {
+    let owner = document.metadata.get("user_id").and_then(|v| v.as_str());
+    match user_id {
+        Some(user_id) => owner.is_none_or(|owner| owner == user_id),
+        None => owner.is_none(),
     }
 }
-
-// Initialize RAG service with default configuration
-pub fn initialize_rag_service() {
-    let _service = get_rag_service(); // Initialize through lazy static
-    log::info!("🚀 Initialized RAG service with default configuration");
-}
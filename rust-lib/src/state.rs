@@ -0,0 +1,71 @@
+// Shared application state injected into handlers through axum's `State` extractor
+use crate::config::AppConfig;
+use crate::embedding::embedder_from_env;
+use crate::mcp::McpRegistry;
+use crate::provider::{provider_from_env, ChatProvider};
+use crate::rag::RagService;
+use crate::threads::ThreadStore;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+#[derive(Clone)]
+pub struct AppState {
+    // Read-locked per request; write-locked only when servers are (un)registered at runtime
+    pub mcp: Arc<RwLock<McpRegistry>>,
+    pub rag: Arc<RagService>,
+    pub threads: Arc<ThreadStore>,
+    pub provider: Arc<dyn ChatProvider>,
+    pub config: Arc<AppConfig>,
+}
+
+impl AppState {
+    pub fn new(config: AppConfig, provider: Arc<dyn ChatProvider>, registry: McpRegistry) -> Self {
+        let embedder = embedder_from_env(&config.rag.embedding_model);
+        log::info!("🧮 Embedding model: {}", embedder.model());
+        let rag = RagService::with_embedder(config.rag.clone(), embedder);
+        let threads = match &config.threads_path {
+            Some(path) => ThreadStore::open(path).unwrap_or_else(|e| {
+                log::error!(
+                    "❌ Failed to open thread store at {}: {}; falling back to memory",
+                    path.display(),
+                    e
+                );
+                ThreadStore::in_memory()
+            }),
+            None => ThreadStore::in_memory(),
+        };
+        Self {
+            mcp: Arc::new(RwLock::new(registry)),
+            rag: Arc::new(rag),
+            threads: Arc::new(threads),
+            provider,
+            config: Arc::new(config),
+        }
+    }
+
+    // Build the state once at startup from environment-driven configuration
+    pub async fn from_env() -> Self {
+        Self::from_config(AppConfig::from_env()).await
+    }
+
+    // Start the configured MCP servers, open the RAG index and thread store and pick a provider
+    pub async fn from_config(config: AppConfig) -> Self {
+        let mut registry = McpRegistry::new();
+        config.mcp.register_servers(&mut registry).await;
+        log::info!(
+            "🚀 Initialized {} MCP servers with {} tools",
+            registry.get_servers().len(),
+            registry.get_available_tools().len()
+        );
+
+        let provider = provider_from_env();
+        log::info!("🤖 Chat provider: {}", provider.name());
+
+        Self::new(config, provider, registry)
+    }
+
+    // Point-in-time copy of the registry so tool execution never holds the lock across awaits
+    pub async fn mcp_snapshot(&self) -> McpRegistry {
+        self.mcp.read().await.clone()
+    }
+}
@@ -0,0 +1,363 @@
+// Server-sent event plumbing for streamed chat completions (`chat.completion.chunk`)
+use crate::ai::{
+    ChatCompletionChunk, ChatCompletionResponse, ChatDelta, ChatMessage, ChoiceLogprobs,
+    ChunkChoice, FinishReason, FunctionCall, FunctionCallDelta, Role, ToolCall, ToolCallDelta,
+    ToolChoiceMode,
+};
+use crate::error::AiError;
+use crate::provider::ProviderError;
+use axum::response::{
+    sse::{Event, Sse},
+    IntoResponse, Response,
+};
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+pub type ChunkStream = BoxStream<'static, Result<ChatCompletionChunk, ProviderError>>;
+
+// Split a complete response into the chunk sequence an upstream would have streamed:
+// a role delta, content/tool_call deltas, a finish_reason chunk and optionally a usage chunk
+pub fn response_to_chunks(
+    response: ChatCompletionResponse,
+    include_usage: bool,
+) -> Vec<ChatCompletionChunk> {
+    let chunk = |choices: Vec<ChunkChoice>| ChatCompletionChunk {
+        id: response.id.clone(),
+        object: "chat.completion.chunk".to_string(),
+        created: response.created,
+        model: response.model.clone(),
+        choices,
+        usage: None,
+        served_by: response.served_by.clone(),
+    };
+    let delta_choice = |index: u32, delta: ChatDelta| ChunkChoice {
+        index,
+        delta,
+        finish_reason: None,
+        logprobs: None,
+    };
+
+    let mut chunks = Vec::new();
+    for choice in &response.choices {
+        chunks.push(chunk(vec![delta_choice(
+            choice.index,
+            ChatDelta {
+                role: Some(choice.message.role),
+                content: Some(String::new()),
+                tool_calls: None,
+            },
+        )]));
+
+        // With logprobs, each token is its own delta carrying its entry, as upstreams send them
+        let text = choice.message.content.as_ref().map(|c| c.to_text());
+        let token_logprobs = choice
+            .logprobs
+            .as_ref()
+            .and_then(|logprobs| logprobs.content.as_ref())
+            .filter(|tokens| {
+                text.as_deref().is_some_and(|text| {
+                    tokens.iter().map(|t| t.token.as_str()).collect::<String>() == text
+                })
+            });
+        if let Some(tokens) = token_logprobs {
+            for token in tokens {
+                let mut delta = delta_choice(
+                    choice.index,
+                    ChatDelta {
+                        content: Some(token.token.clone()),
+                        ..Default::default()
+                    },
+                );
+                delta.logprobs = Some(ChoiceLogprobs {
+                    content: Some(vec![token.clone()]),
+                });
+                chunks.push(chunk(vec![delta]));
+            }
+        } else if let Some(text) = &text {
+            for piece in text.split_inclusive(' ') {
+                chunks.push(chunk(vec![delta_choice(
+                    choice.index,
+                    ChatDelta {
+                        content: Some(piece.to_string()),
+                        ..Default::default()
+                    },
+                )]));
+            }
+        }
+
+        for (index, tool_call) in choice.message.tool_calls.iter().flatten().enumerate() {
+            let index = index as u32;
+            chunks.push(chunk(vec![delta_choice(
+                choice.index,
+                ChatDelta {
+                    tool_calls: Some(vec![ToolCallDelta {
+                        index,
+                        id: Some(tool_call.id.clone()),
+                        r#type: Some(tool_call.r#type.clone()),
+                        function: Some(FunctionCallDelta {
+                            name: Some(tool_call.function.name.clone()),
+                            arguments: Some(String::new()),
+                        }),
+                    }]),
+                    ..Default::default()
+                },
+            )]));
+            chunks.push(chunk(vec![delta_choice(
+                choice.index,
+                ChatDelta {
+                    tool_calls: Some(vec![ToolCallDelta {
+                        index,
+                        id: None,
+                        r#type: None,
+                        function: Some(FunctionCallDelta {
+                            name: None,
+                            arguments: Some(tool_call.function.arguments.clone()),
+                        }),
+                    }]),
+                    ..Default::default()
+                },
+            )]));
+        }
+
+        chunks.push(chunk(vec![ChunkChoice {
+            index: choice.index,
+            delta: ChatDelta::default(),
+            finish_reason: Some(choice.finish_reason),
+            logprobs: None,
+        }]));
+    }
+
+    if include_usage {
+        let mut usage_chunk = chunk(Vec::new());
+        usage_chunk.usage = Some(response.usage.clone());
+        chunks.push(usage_chunk);
+    }
+
+    chunks
+}
+
+// Render chunks as `data:` events followed by the `data: [DONE]` terminator
+pub fn sse_response(chunks: ChunkStream) -> Response {
+    let events = chunks
+        .map(|item| match item {
+            Ok(chunk) => Event::default().json_data(&chunk),
+            Err(e) => {
+                log::error!("❌ Chat completion stream failed: {}", e);
+                // Headers are already sent, so the error travels as a final data event
+                Event::default().json_data(AiError::from(e).envelope())
+            }
+        })
+        .chain(stream::once(async { Ok(Event::default().data("[DONE]")) }));
+
+    Sse::new(events).into_response()
+}
+
+// The first choice's assistant message, assembled from its deltas as they stream past
+#[derive(Debug, Default)]
+pub(crate) struct MessageAccumulator {
+    content: String,
+    tool_calls: BTreeMap<u32, ToolCall>,
+}
+
+impl MessageAccumulator {
+    pub(crate) fn push(&mut self, chunk: &ChatCompletionChunk) {
+        let Some(choice) = chunk.choices.iter().find(|c| c.index == 0) else {
+            return;
+        };
+        if let Some(content) = &choice.delta.content {
+            self.content.push_str(content);
+        }
+        for delta in choice.delta.tool_calls.iter().flatten() {
+            let call = self
+                .tool_calls
+                .entry(delta.index)
+                .or_insert_with(|| ToolCall {
+                    id: String::new(),
+                    r#type: "function".to_string(),
+                    function: FunctionCall {
+                        name: String::new(),
+                        arguments: String::new(),
+                    },
+                });
+            if let Some(id) = &delta.id {
+                call.id.clone_from(id);
+            }
+            let Some(function) = &delta.function else {
+                continue;
+            };
+            if let Some(name) = &function.name {
+                call.function.name.push_str(name);
+            }
+            if let Some(arguments) = &function.arguments {
+                call.function.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    pub(crate) fn into_message(self) -> ChatMessage {
+        let tool_calls: Vec<ToolCall> = self.tool_calls.into_values().collect();
+        ChatMessage {
+            role: Role::Assistant,
+            content: (!self.content.is_empty() || tool_calls.is_empty())
+                .then(|| self.content.into()),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            tool_call_id: None,
+            name: None,
+        }
+    }
+}
+
+// Streaming counterpart of the provider's tool_choice and parallel_tool_calls checks for
+// upstreams that ignore them: disallowed tool call deltas are dropped (survivors re-indexed
+// from 0) and a choice that finishes without the forced call ends the stream with an error
+pub(crate) fn enforce_tool_choice_stream(
+    mode: ToolChoiceMode<'_>,
+    parallel: bool,
+    chunks: ChunkStream,
+) -> ChunkStream {
+    let forced_name = match mode {
+        ToolChoiceMode::Auto if parallel => return chunks,
+        ToolChoiceMode::Function(name) => Some(name.to_string()),
+        _ => None,
+    };
+    let calls_allowed = mode != ToolChoiceMode::None;
+    let forced = matches!(mode, ToolChoiceMode::Required | ToolChoiceMode::Function(_));
+
+    // (choice, upstream index) -> re-numbered index, or None when the call is dropped
+    let mut indices: HashMap<(u32, u32), Option<u32>> = HashMap::new();
+    let mut next_index: HashMap<u32, u32> = HashMap::new();
+    let mut called: HashSet<u32> = HashSet::new();
+
+    chunks
+        .map(move |item| {
+            let mut chunk = item?;
+            for choice in &mut chunk.choices {
+                let choice_index = choice.index;
+                if let Some(deltas) = choice.delta.tool_calls.take() {
+                    let kept: Vec<ToolCallDelta> = deltas
+                        .into_iter()
+                        .filter_map(|mut delta| {
+                            let slot =
+                                indices
+                                    .entry((choice_index, delta.index))
+                                    .or_insert_with(|| {
+                                        let name =
+                                            delta.function.as_ref().and_then(|f| f.name.as_deref());
+                                        let next = next_index.entry(choice_index).or_default();
+                                        let allowed = calls_allowed
+                                            && (parallel || *next == 0)
+                                            && (forced_name.is_none()
+                                                || name == forced_name.as_deref());
+                                        allowed.then(|| {
+                                            *next += 1;
+                                            *next - 1
+                                        })
+                                    });
+                            delta.index = (*slot)?;
+                            Some(delta)
+                        })
+                        .collect();
+                    if !kept.is_empty() {
+                        called.insert(choice_index);
+                        choice.delta.tool_calls = Some(kept);
+                    }
+                }
+
+                if choice.finish_reason.is_none() {
+                    continue;
+                }
+                if !calls_allowed {
+                    if choice.finish_reason == Some(FinishReason::ToolCalls) {
+                        choice.finish_reason = Some(FinishReason::Stop);
+                    }
+                } else if called.contains(&choice_index) {
+                    choice.finish_reason = Some(FinishReason::ToolCalls);
+                } else if forced {
+                    return Err(ProviderError::ToolChoice(match &forced_name {
+                        Some(name) => format!("expected a call to '{}'", name),
+                        None => "expected at least one tool call".to_string(),
+                    }));
+                }
+            }
+            Ok(chunk)
+        })
+        .boxed()
+}
+
+struct SseDecoder {
+    bytes: BoxStream<'static, Result<Vec<u8>, String>>,
+    buffer: Vec<u8>,
+    pending: VecDeque<Result<ChatCompletionChunk, ProviderError>>,
+    done: bool,
+}
+
+impl SseDecoder {
+    // Consume every complete line in the buffer; a trailing partial line stays buffered
+    fn drain_lines(&mut self, flush: bool) {
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            self.handle_line(&String::from_utf8_lossy(&line));
+        }
+        if flush && !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            self.handle_line(&String::from_utf8_lossy(&line));
+        }
+    }
+
+    fn handle_line(&mut self, line: &str) {
+        if self.done {
+            return;
+        }
+        let Some(payload) = line.trim_end_matches(['\r', '\n']).strip_prefix("data:") else {
+            return; // Comments, event names and blank separators carry no chunk data
+        };
+        let payload = payload.trim();
+        if payload == "[DONE]" {
+            self.done = true;
+            return;
+        }
+        self.pending.push_back(
+            serde_json::from_str::<ChatCompletionChunk>(payload)
+                .map_err(|e| ProviderError::InvalidResponse(format!("{}: {}", e, payload))),
+        );
+    }
+}
+
+// Decode an upstream `text/event-stream` body into chunks, stopping at `data: [DONE]`
+pub(crate) fn decode_sse(response: reqwest::Response) -> ChunkStream {
+    let decoder = SseDecoder {
+        bytes: response
+            .bytes_stream()
+            .map(|bytes| bytes.map(|b| b.to_vec()).map_err(|e| e.to_string()))
+            .boxed(),
+        buffer: Vec::new(),
+        pending: VecDeque::new(),
+        done: false,
+    };
+
+    stream::unfold(decoder, |mut decoder| async move {
+        loop {
+            if let Some(item) = decoder.pending.pop_front() {
+                return Some((item, decoder));
+            }
+            if decoder.done {
+                return None;
+            }
+            match decoder.bytes.next().await {
+                Some(Ok(bytes)) => {
+                    decoder.buffer.extend_from_slice(&bytes);
+                    decoder.drain_lines(false);
+                }
+                Some(Err(e)) => {
+                    decoder.done = true;
+                    decoder.pending.push_back(Err(ProviderError::Request(e)));
+                }
+                None => {
+                    decoder.drain_lines(true);
+                    decoder.done = true;
+                }
+            }
+        }
+    })
+    .boxed()
+}
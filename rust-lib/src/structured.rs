@@ -0,0 +1,313 @@
+// Structured outputs: replies to requests with a JSON `response_format` are checked before they
+// reach the client. Invalid output is repaired locally when possible (code fences, surrounding
+// prose, "5" for 5) and otherwise re-requested with the validation errors as feedback.
+use crate::ai::{
+    ChatCompletionRequest, ChatCompletionResponse, ChatMessage, FinishReason, MessageContent,
+    ResponseFormat, Role, Usage,
+};
+use crate::provider::{ChatProvider, ProviderError};
+use crate::stream::ChunkStream;
+use crate::tool_arguments::coerce_to_schema;
+use futures::StreamExt;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+
+// Extra provider round trips after the first invalid reply
+pub const MAX_STRUCTURED_OUTPUT_RETRIES: usize = 2;
+
+// Whether `content` satisfies the format; returns the problems otherwise
+pub fn check_output(format: &ResponseFormat, content: &str) -> Result<(), Vec<String>> {
+    let value: Value = serde_json::from_str(content)
+        .map_err(|e| vec![format!("reply is not valid JSON: {}", e)])?;
+
+    let Some(schema) = format.schema() else {
+        return if value.is_object() {
+            Ok(())
+        } else {
+            Err(vec!["reply must be a JSON object".to_string()])
+        };
+    };
+
+    let validator = match jsonschema::validator_for(schema) {
+        Ok(validator) => validator,
+        Err(e) => return Err(vec![format!("response_format schema is invalid: {}", e)]),
+    };
+    let issues: Vec<String> = validator
+        .iter_errors(&value)
+        .map(|error| match error.instance_path.to_string().as_str() {
+            "" => error.to_string(),
+            path => format!("{}: {}", path, error),
+        })
+        .collect();
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(issues)
+    }
+}
+
+// Best-effort local fix: take the JSON out of code fences or surrounding prose and coerce scalar
+// types to the schema. Returns compact JSON only when the result passes `check_output`.
+pub fn repair_output(format: &ResponseFormat, content: &str) -> Option<String> {
+    let trimmed = content.trim();
+    let unfenced = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.trim_end().strip_suffix("```"))
+        .unwrap_or(trimmed);
+
+    let candidates = [
+        Some(unfenced),
+        slice_between(unfenced, '{', '}'),
+        slice_between(unfenced, '[', ']'),
+    ];
+    candidates.into_iter().flatten().find_map(|candidate| {
+        let mut value: Value = serde_json::from_str(candidate).ok()?;
+        if let Some(schema) = format.schema() {
+            coerce_to_schema(&mut value, schema);
+        }
+        let repaired = value.to_string();
+        check_output(format, &repaired).ok().map(|_| repaired)
+    })
+}
+
+fn slice_between(text: &str, open: char, close: char) -> Option<&str> {
+    let start = text.find(open)?;
+    let end = text.rfind(close)?;
+    (start < end).then(|| &text[start..=end])
+}
+
+// `provider.complete` with the response_format enforced on every choice that answers in text
+pub async fn complete_structured(
+    provider: &dyn ChatProvider,
+    request: &ChatCompletionRequest,
+) -> Result<ChatCompletionResponse, ProviderError> {
+    let Some(format) = request.response_format.as_ref().filter(|f| f.is_json()) else {
+        return provider.complete(request).await;
+    };
+
+    let mut attempt_request = request.clone();
+    let mut usage = Usage::default();
+    let mut attempt = 0;
+    loop {
+        let mut response = provider.complete(&attempt_request).await?;
+        usage.prompt_tokens += response.usage.prompt_tokens;
+        usage.completion_tokens += response.usage.completion_tokens;
+        usage.total_tokens += response.usage.total_tokens;
+
+        let Err((content, issues)) = conform_choices(format, &mut response) else {
+            response.usage = usage;
+            return Ok(response);
+        };
+        log::warn!(
+            "⚠️ Reply does not match response_format (attempt {}): {}",
+            attempt + 1,
+            issues.join("; ")
+        );
+        if attempt == MAX_STRUCTURED_OUTPUT_RETRIES {
+            return Err(ProviderError::InvalidResponse(format!(
+                "reply did not match response_format after {} attempts: {}",
+                attempt + 1,
+                issues.join("; ")
+            )));
+        }
+
+        attempt_request.messages.push(ChatMessage {
+            role: Role::Assistant,
+            content: Some(content.into()),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        });
+        attempt_request.messages.push(ChatMessage {
+            role: Role::User,
+            content: Some(
+                format!(
+                    "Your reply did not match the required JSON format:\n- {}\nReply again with only the corrected JSON.",
+                    issues.join("\n- ")
+                )
+                .into(),
+            ),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        });
+        attempt += 1;
+    }
+}
+
+// Check (and repair in place) every text answer; tool-call turns and replies cut off by
+// max_tokens are left alone. Returns the first reply that could not be fixed with its problems.
+fn conform_choices(
+    format: &ResponseFormat,
+    response: &mut ChatCompletionResponse,
+) -> Result<(), (String, Vec<String>)> {
+    for choice in &mut response.choices {
+        let message = &mut choice.message;
+        if choice.finish_reason == FinishReason::Length
+            || message.tool_calls.as_ref().is_some_and(|c| !c.is_empty())
+        {
+            continue;
+        }
+        let content = message
+            .content
+            .as_ref()
+            .map(MessageContent::to_text)
+            .unwrap_or_default();
+        let Err(issues) = check_output(format, &content) else {
+            continue;
+        };
+        match repair_output(format, &content) {
+            Some(repaired) => {
+                log::info!("🔧 Repaired reply to match response_format");
+                message.content = Some(repaired.into());
+            }
+            None => return Err((content, issues)),
+        }
+    }
+    Ok(())
+}
+
+// Streamed replies cannot be taken back, so they are only checked: a choice whose accumulated
+// content fails the format ends the stream with an error instead of its finish chunk
+pub(crate) fn validate_structured_stream(
+    format: Option<ResponseFormat>,
+    chunks: ChunkStream,
+) -> ChunkStream {
+    let Some(format) = format.filter(ResponseFormat::is_json) else {
+        return chunks;
+    };
+    let mut contents: HashMap<u32, String> = HashMap::new();
+    let mut tool_calls: HashSet<u32> = HashSet::new();
+
+    chunks
+        .map(move |item| {
+            let chunk = item?;
+            for choice in &chunk.choices {
+                if let Some(content) = &choice.delta.content {
+                    contents.entry(choice.index).or_default().push_str(content);
+                }
+                if choice.delta.tool_calls.is_some() {
+                    tool_calls.insert(choice.index);
+                }
+                let Some(reason) = choice.finish_reason else {
+                    continue;
+                };
+                let content = contents.remove(&choice.index).unwrap_or_default();
+                if reason == FinishReason::Length || tool_calls.contains(&choice.index) {
+                    continue;
+                }
+                if let Err(issues) = check_output(&format, &content) {
+                    return Err(ProviderError::InvalidResponse(format!(
+                        "streamed reply does not match response_format: {}",
+                        issues.join("; ")
+                    )));
+                }
+            }
+            Ok(chunk)
+        })
+        .boxed()
+}
+
+// Deterministic value satisfying `schema`, used by the mock provider so frontends can be built
+// against structured outputs offline. Covers the keywords structured-output schemas use.
+pub fn sample_from_schema(schema: &Value) -> Value {
+    sample_value(schema, schema, "value", 0)
+}
+
+fn sample_value(schema: &Value, root: &Value, name: &str, depth: usize) -> Value {
+    if depth > 16 {
+        return Value::Null;
+    }
+    if let Some(reference) = schema["$ref"].as_str() {
+        let target = reference
+            .strip_prefix('#')
+            .and_then(|pointer| root.pointer(pointer));
+        return target.map_or(Value::Null, |target| {
+            sample_value(target, root, name, depth + 1)
+        });
+    }
+    if let Some(constant) = schema.get("const") {
+        return constant.clone();
+    }
+    if let Some(first) = schema["enum"].as_array().and_then(|values| values.first()) {
+        return first.clone();
+    }
+    for combinator in ["anyOf", "oneOf"] {
+        if let Some(first) = schema[combinator].as_array().and_then(|s| s.first()) {
+            return sample_value(first, root, name, depth + 1);
+        }
+    }
+    if let Some(all) = schema["allOf"].as_array() {
+        let mut merged = Map::new();
+        for part in all {
+            if let Value::Object(object) = sample_value(part, root, name, depth + 1) {
+                merged.extend(object);
+            }
+        }
+        return Value::Object(merged);
+    }
+
+    let ty = match &schema["type"] {
+        Value::String(ty) => ty.as_str(),
+        Value::Array(types) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|ty| *ty != "null")
+            .unwrap_or("null"),
+        _ if schema.get("properties").is_some() => "object",
+        _ if schema.get("items").is_some() => "array",
+        _ => "string",
+    };
+
+    match ty {
+        "object" => {
+            let object = schema["properties"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .map(|(key, property)| (key.clone(), sample_value(property, root, key, depth + 1)))
+                .collect();
+            Value::Object(object)
+        }
+        "array" => {
+            let count = schema["minItems"].as_u64().unwrap_or(1).max(1);
+            let items = (0..count)
+                .map(|_| sample_value(&schema["items"], root, name, depth + 1))
+                .collect();
+            Value::Array(items)
+        }
+        "integer" => json!(schema["minimum"]
+            .as_i64()
+            .or_else(|| schema["exclusiveMinimum"].as_i64().map(|n| n + 1))
+            .unwrap_or(1)),
+        "number" => json!(schema["minimum"]
+            .as_f64()
+            .or_else(|| schema["exclusiveMinimum"].as_f64().map(|n| n + 1.0))
+            .unwrap_or(1.0)),
+        "boolean" => json!(true),
+        "null" => Value::Null,
+        _ => json!(sample_string(schema, name)),
+    }
+}
+
+fn sample_string(schema: &Value, name: &str) -> String {
+    let text = match schema["format"].as_str() {
+        Some("date-time") => "2024-01-01T00:00:00Z".to_string(),
+        Some("date") => "2024-01-01".to_string(),
+        Some("time") => "00:00:00Z".to_string(),
+        Some("email") => "user@example.com".to_string(),
+        Some("uri") | Some("url") => "https://example.com".to_string(),
+        Some("uuid") => "00000000-0000-4000-8000-000000000000".to_string(),
+        _ => format!("example {}", name),
+    };
+    let min = schema["minLength"].as_u64().unwrap_or(0) as usize;
+    let max = schema["maxLength"]
+        .as_u64()
+        .map_or(usize::MAX, |n| n as usize);
+    let mut text: String = text.chars().take(max).collect();
+    while text.chars().count() < min {
+        text.push('x');
+    }
+    text
+}
@@ -0,0 +1,865 @@
+// Persistent conversation threads: stored message histories that completions can run against by
+// id, so clients no longer resend the whole conversation. Kept in an embedded redb file in the
+// data dir, the same store the web server and the desktop app open.
+use crate::agent::ToolExecution;
+use crate::ai::{
+    complete_chat, completed_stream_response, insert_served_by, open_chat_stream,
+    prepare_chat_request, ChatCompletionRequest, ChatMessage,
+};
+use crate::context::CONTEXT_STRATEGY_HEADER;
+use crate::error::{deserialize_value, AiError, ApiJson};
+use crate::state::AppState;
+use crate::stream::{ChunkStream, MessageAccumulator};
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::HeaderValue;
+use axum::response::{IntoResponse, Json as AxumJson, Response};
+use futures::stream::{self, StreamExt};
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+// Thread records, and their messages keyed by (thread id, position) so an append only writes
+// the new messages. Records written before the split carry their messages inline.
+const THREADS: TableDefinition<&str, &[u8]> = TableDefinition::new("threads");
+const MESSAGES: TableDefinition<(&str, u64), &[u8]> = TableDefinition::new("thread_messages");
+
+// Page size for GET /threads when no `limit` is given, and the largest one allowed
+pub const DEFAULT_THREAD_LIST_LIMIT: usize = 20;
+pub const MAX_THREAD_LIST_LIMIT: usize = 100;
+
+// Response header carrying the id under which a run stored its reply
+pub const THREAD_MESSAGE_HEADER: &str = "x-thread-message-id";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ThreadStoreError {
+    #[error("thread store database error: {0}")]
+    Database(Box<redb::Error>),
+    #[error("failed to create data directory {path}: {source}")]
+    DataDir {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("failed to (de)serialize thread: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("thread store write did not complete: {0}")]
+    Write(#[from] tokio::task::JoinError),
+    #[error("No message found with id '{0}'")]
+    UnknownMessage(String),
+}
+
+fn db_err(e: impl Into<redb::Error>) -> ThreadStoreError {
+    ThreadStoreError::Database(Box::new(e.into()))
+}
+
+// Unique, roughly time-ordered ids ("thread_...", "msg_...")
+fn new_id(prefix: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed) % 0x10000;
+    format!("{}_{:x}{:04x}", prefix, nanos, count)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Thread {
+    pub id: String,
+    pub object: String, // "thread"
+    pub created_at: i64,
+    pub updated_at: i64,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    // Last message of the active branch; new messages attach here unless told otherwise
+    #[serde(default)]
+    pub head_id: Option<String>,
+}
+
+// Messages form a tree: editing a user message or regenerating a reply adds a sibling under the
+// same parent instead of overwriting, and each root-to-leaf path is one branch of the conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadMessage {
+    pub id: String,
+    pub object: String, // "thread.message"
+    pub created_at: i64,
+    // None for the first message of a branch
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(flatten)]
+    pub message: ChatMessage,
+}
+
+impl ThreadMessage {
+    fn new(message: ChatMessage, parent_id: Option<String>) -> Self {
+        Self {
+            id: new_id("msg"),
+            object: "thread.message".to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            parent_id,
+            message,
+        }
+    }
+}
+
+// A message on a branch with its position among its siblings, for the UI's branch picker
+#[derive(Debug, Clone, Serialize)]
+pub struct BranchMessage {
+    #[serde(flatten)]
+    pub message: ThreadMessage,
+    pub branch_index: usize,
+    pub branch_count: usize,
+}
+
+// Where new messages attach
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parent {
+    // After the last message of the active branch
+    Head,
+    // As the first message of a new branch
+    Root,
+    Message(String),
+}
+
+impl Parent {
+    // A missing `parent_id` means the head, an explicit null the root
+    pub fn from_field(parent_id: Option<Option<String>>) -> Self {
+        match parent_id {
+            None => Parent::Head,
+            Some(None) => Parent::Root,
+            Some(Some(id)) => Parent::Message(id),
+        }
+    }
+}
+
+// A thread and its message tree, in insertion order
+#[derive(Debug, Clone, Deserialize)]
+struct StoredThread {
+    thread: Thread,
+    #[serde(default)]
+    messages: Vec<ThreadMessage>,
+}
+
+impl StoredThread {
+    fn find(&self, id: &str) -> Option<&ThreadMessage> {
+        self.messages.iter().find(|message| message.id == id)
+    }
+
+    fn resolve(&self, parent: &Parent) -> Result<Option<String>, ThreadStoreError> {
+        match parent {
+            Parent::Head => Ok(self.thread.head_id.clone()),
+            Parent::Root => Ok(None),
+            Parent::Message(id) => self
+                .find(id)
+                .map(|message| Some(message.id.clone()))
+                .ok_or_else(|| ThreadStoreError::UnknownMessage(id.clone())),
+        }
+    }
+
+    // Root-to-`id` path
+    fn path_to(&self, id: Option<&str>) -> Vec<ThreadMessage> {
+        let mut path = Vec::new();
+        let mut next = id.and_then(|id| self.find(id));
+        while let Some(message) = next {
+            path.push(message.clone());
+            next = message.parent_id.as_deref().and_then(|id| self.find(id));
+        }
+        path.reverse();
+        path
+    }
+
+    // Messages sharing the parent of `message` (itself included), oldest first
+    fn siblings(&self, message: &ThreadMessage) -> Vec<&ThreadMessage> {
+        self.messages
+            .iter()
+            .filter(|other| other.parent_id == message.parent_id)
+            .collect()
+    }
+
+    // The branch through `id`, continued down the newest child at each step
+    fn branch_through(&self, id: Option<&str>) -> Vec<BranchMessage> {
+        let mut path = self.path_to(id);
+        let mut last = path.last().map(|message| message.id.clone());
+        while let Some(child) = last.as_deref().and_then(|id| {
+            self.messages
+                .iter()
+                .rev()
+                .find(|m| m.parent_id.as_deref() == Some(id))
+        }) {
+            last = Some(child.id.clone());
+            path.push(child.clone());
+        }
+
+        path.into_iter()
+            .map(|message| {
+                let siblings = self.siblings(&message);
+                BranchMessage {
+                    branch_index: siblings
+                        .iter()
+                        .position(|sibling| sibling.id == message.id)
+                        .unwrap_or_default(),
+                    branch_count: siblings.len(),
+                    message,
+                }
+            })
+            .collect()
+    }
+
+    // Add `messages` as a chain under `parent` and make the last one the head, under `last_id`
+    // when given
+    fn attach(
+        &mut self,
+        parent: Option<String>,
+        messages: Vec<ChatMessage>,
+        mut last_id: Option<String>,
+    ) -> Vec<ThreadMessage> {
+        let mut parent_id = parent;
+        let mut attached = Vec::new();
+        let count = messages.len();
+        for (i, message) in messages.into_iter().enumerate() {
+            let mut message = ThreadMessage::new(message, parent_id.take());
+            if i + 1 == count {
+                if let Some(id) = last_id.take() {
+                    message.id = id;
+                }
+            }
+            parent_id = Some(message.id.clone());
+            attached.push(message);
+        }
+        if parent_id.is_some() {
+            self.thread.head_id = parent_id;
+        }
+        self.messages.extend(attached.iter().cloned());
+        attached
+    }
+
+    // Threads written before messages had parents are one linear branch
+    fn link_linear_history(&mut self) {
+        if self.thread.head_id.is_some() || self.messages.is_empty() {
+            return;
+        }
+        let mut parent_id = None;
+        for message in &mut self.messages {
+            message.parent_id = parent_id.replace(message.id.clone());
+        }
+        self.thread.head_id = parent_id;
+    }
+}
+
+#[derive(Serialize)]
+struct ThreadRecord<'a> {
+    thread: &'a Thread,
+}
+
+// One redb transaction, run on the blocking pool
+enum Write {
+    // The thread record and the messages added since the last write, with their positions
+    Save {
+        thread: Thread,
+        messages: Vec<(u64, ThreadMessage)>,
+    },
+    Delete {
+        id: String,
+        messages: u64,
+    },
+}
+
+impl Write {
+    fn save(stored: &StoredThread, from: usize) -> Self {
+        Write::Save {
+            thread: stored.thread.clone(),
+            messages: (from as u64..)
+                .zip(stored.messages[from..].iter().cloned())
+                .collect(),
+        }
+    }
+
+    fn commit(&self, db: &Database) -> Result<(), ThreadStoreError> {
+        let txn = db.begin_write().map_err(db_err)?;
+        {
+            let mut threads = txn.open_table(THREADS).map_err(db_err)?;
+            let mut messages = txn.open_table(MESSAGES).map_err(db_err)?;
+            match self {
+                Write::Save {
+                    thread,
+                    messages: added,
+                } => {
+                    let record = serde_json::to_vec(&ThreadRecord { thread })?;
+                    threads
+                        .insert(thread.id.as_str(), record.as_slice())
+                        .map_err(db_err)?;
+                    for (position, message) in added {
+                        let bytes = serde_json::to_vec(message)?;
+                        messages
+                            .insert((thread.id.as_str(), *position), bytes.as_slice())
+                            .map_err(db_err)?;
+                    }
+                }
+                Write::Delete {
+                    id,
+                    messages: count,
+                } => {
+                    threads.remove(id.as_str()).map_err(db_err)?;
+                    for position in 0..*count {
+                        messages.remove((id.as_str(), position)).map_err(db_err)?;
+                    }
+                }
+            }
+        }
+        txn.commit().map_err(db_err)?;
+        Ok(())
+    }
+}
+
+// Every thread lives in memory; writes go through to disk when persistent. Disk commits run on
+// the blocking pool without holding the map lock, one at a time so they land in order.
+pub struct ThreadStore {
+    threads: RwLock<HashMap<String, StoredThread>>,
+    db: Option<Arc<Database>>,
+    writer: tokio::sync::Mutex<()>,
+}
+
+impl ThreadStore {
+    pub fn in_memory() -> Self {
+        Self {
+            threads: RwLock::new(HashMap::new()),
+            db: None,
+            writer: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    // Open (or create) the redb file at `path` and load every stored thread into memory
+    pub fn open(path: &Path) -> Result<Self, ThreadStoreError> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|source| ThreadStoreError::DataDir {
+                path: parent.display().to_string(),
+                source,
+            })?;
+        }
+
+        let db = Database::create(path).map_err(db_err)?;
+
+        // Make sure the tables exist so read transactions can open them
+        let txn = db.begin_write().map_err(db_err)?;
+        txn.open_table(THREADS).map_err(db_err)?;
+        txn.open_table(MESSAGES).map_err(db_err)?;
+        txn.commit().map_err(db_err)?;
+
+        let mut threads = HashMap::new();
+        let mut inline = Vec::new();
+        {
+            let txn = db.begin_read().map_err(db_err)?;
+            let table = txn.open_table(THREADS).map_err(db_err)?;
+            for entry in table.iter().map_err(db_err)? {
+                let (id, bytes) = entry.map_err(db_err)?;
+                match serde_json::from_slice::<StoredThread>(bytes.value()) {
+                    Ok(stored) => {
+                        if !stored.messages.is_empty() {
+                            inline.push(stored.thread.id.clone());
+                        }
+                        threads.insert(id.value().to_string(), stored);
+                    }
+                    Err(e) => log::warn!("⚠️ Skipping unreadable thread '{}': {}", id.value(), e),
+                }
+            }
+
+            // Keys sort by thread, then position, so messages arrive in insertion order
+            let table = txn.open_table(MESSAGES).map_err(db_err)?;
+            for entry in table.iter().map_err(db_err)? {
+                let (key, bytes) = entry.map_err(db_err)?;
+                let (id, position) = key.value();
+                let Some(stored) = threads.get_mut(id) else {
+                    continue;
+                };
+                match serde_json::from_slice::<ThreadMessage>(bytes.value()) {
+                    Ok(message) => stored.messages.push(message),
+                    Err(e) => log::warn!(
+                        "⚠️ Skipping unreadable message {} of thread '{}': {}",
+                        position,
+                        id,
+                        e
+                    ),
+                }
+            }
+        }
+        for stored in threads.values_mut() {
+            stored.link_linear_history();
+        }
+
+        // Move messages out of records written before the split
+        for id in &inline {
+            Write::save(&threads[id], 0).commit(&db)?;
+        }
+        if !inline.is_empty() {
+            log::info!(
+                "🧵 Migrated {} threads to per-message records",
+                inline.len()
+            );
+        }
+
+        log::info!(
+            "🧵 Opened thread store at {} with {} threads",
+            path.display(),
+            threads.len()
+        );
+
+        Ok(Self {
+            threads: RwLock::new(threads),
+            db: Some(Arc::new(db)),
+            writer: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub async fn create(
+        &self,
+        metadata: Map<String, Value>,
+        messages: Vec<ChatMessage>,
+    ) -> Result<Thread, ThreadStoreError> {
+        let now = chrono::Utc::now().timestamp();
+        let mut stored = StoredThread {
+            thread: Thread {
+                id: new_id("thread"),
+                object: "thread".to_string(),
+                created_at: now,
+                updated_at: now,
+                metadata,
+                head_id: None,
+            },
+            messages: Vec::new(),
+        };
+        stored.attach(None, messages, None);
+
+        let _writing = self.writer.lock().await;
+        self.persist(Write::save(&stored, 0)).await?;
+        let thread = stored.thread.clone();
+        self.write_threads().insert(thread.id.clone(), stored);
+        Ok(thread)
+    }
+
+    // Most recently updated first
+    pub fn list(&self, limit: usize) -> Vec<Thread> {
+        let threads = self.read_threads();
+        let mut list: Vec<&Thread> = threads.values().map(|stored| &stored.thread).collect();
+        list.sort_by(|a, b| (b.updated_at, &b.id).cmp(&(a.updated_at, &a.id)));
+        list.into_iter().take(limit).cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<Thread> {
+        self.read_threads()
+            .get(id)
+            .map(|stored| stored.thread.clone())
+    }
+
+    // The branch through `message_id` (the active one when None), followed to its newest leaf;
+    // None when the thread does not exist
+    pub fn branch(
+        &self,
+        id: &str,
+        message_id: Option<&str>,
+    ) -> Result<Option<Vec<BranchMessage>>, ThreadStoreError> {
+        let threads = self.read_threads();
+        let Some(stored) = threads.get(id) else {
+            return Ok(None);
+        };
+        let through = match message_id {
+            Some(message_id) => Parent::Message(message_id.to_string()),
+            None => Parent::Head,
+        };
+        let through = stored.resolve(&through)?;
+        Ok(Some(stored.branch_through(through.as_deref())))
+    }
+
+    // Root-to-parent history a completion from `parent` sees
+    pub fn history(
+        &self,
+        id: &str,
+        parent: &Parent,
+    ) -> Result<Option<Vec<ThreadMessage>>, ThreadStoreError> {
+        let threads = self.read_threads();
+        let Some(stored) = threads.get(id) else {
+            return Ok(None);
+        };
+        let parent = stored.resolve(parent)?;
+        Ok(Some(stored.path_to(parent.as_deref())))
+    }
+
+    // The alternatives at a node: the message and its siblings, oldest first
+    pub fn siblings(
+        &self,
+        id: &str,
+        message_id: &str,
+    ) -> Result<Option<Vec<ThreadMessage>>, ThreadStoreError> {
+        let threads = self.read_threads();
+        let Some(stored) = threads.get(id) else {
+            return Ok(None);
+        };
+        let message = stored
+            .find(message_id)
+            .ok_or_else(|| ThreadStoreError::UnknownMessage(message_id.to_string()))?;
+        Ok(Some(
+            stored.siblings(message).into_iter().cloned().collect(),
+        ))
+    }
+
+    // Attach `messages` as a chain under `parent`; the last becomes the thread's head. None when
+    // the thread does not exist.
+    pub async fn append(
+        &self,
+        id: &str,
+        parent: &Parent,
+        messages: Vec<ChatMessage>,
+    ) -> Result<Option<Vec<ThreadMessage>>, ThreadStoreError> {
+        self.append_as(id, parent, messages, None).await
+    }
+
+    // `append` with the id of the last message chosen up front, for streamed runs that announce
+    // the reply's id before the reply exists
+    async fn append_as(
+        &self,
+        id: &str,
+        parent: &Parent,
+        messages: Vec<ChatMessage>,
+        last_id: Option<String>,
+    ) -> Result<Option<Vec<ThreadMessage>>, ThreadStoreError> {
+        let _writing = self.writer.lock().await;
+        let Some(mut updated) = self.read_threads().get(id).cloned() else {
+            return Ok(None);
+        };
+
+        let from = updated.messages.len();
+        let parent = updated.resolve(parent)?;
+        let appended = updated.attach(parent, messages, last_id);
+        updated.thread.updated_at = chrono::Utc::now().timestamp();
+        self.persist(Write::save(&updated, from)).await?;
+        self.write_threads().insert(id.to_string(), updated);
+        Ok(Some(appended))
+    }
+
+    pub async fn delete(&self, id: &str) -> Result<bool, ThreadStoreError> {
+        let _writing = self.writer.lock().await;
+        let messages = self
+            .read_threads()
+            .get(id)
+            .map_or(0, |stored| stored.messages.len() as u64);
+        self.persist(Write::Delete {
+            id: id.to_string(),
+            messages,
+        })
+        .await?;
+        Ok(self.write_threads().remove(id).is_some())
+    }
+
+    async fn persist(&self, write: Write) -> Result<(), ThreadStoreError> {
+        let Some(db) = self.db.clone() else {
+            return Ok(());
+        };
+        tokio::task::spawn_blocking(move || write.commit(&db)).await?
+    }
+
+    fn read_threads(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, StoredThread>> {
+        self.threads.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write_threads(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, StoredThread>> {
+        self.threads.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// HTTP API, mounted under /v1 with the OpenAI-compatible routes
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateThreadRequest {
+    #[serde(default)]
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListThreadsQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThreadList<T> {
+    pub object: String, // "list"
+    pub data: Vec<T>,
+}
+
+impl<T> ThreadList<T> {
+    fn new(data: Vec<T>) -> Self {
+        Self {
+            object: "list".to_string(),
+            data,
+        }
+    }
+}
+
+pub async fn create_thread_handler(
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<CreateThreadRequest>,
+) -> Result<AxumJson<Thread>, AiError> {
+    let thread = state
+        .threads
+        .create(request.metadata, request.messages)
+        .await?;
+    log::info!("🧵 Created thread {}", thread.id);
+    Ok(AxumJson(thread))
+}
+
+pub async fn list_threads_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ListThreadsQuery>,
+) -> Result<AxumJson<ThreadList<Thread>>, AiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_THREAD_LIST_LIMIT);
+    if !(1..=MAX_THREAD_LIST_LIMIT).contains(&limit) {
+        return Err(AiError::invalid_request(
+            format!("'limit' must be between 1 and {}", MAX_THREAD_LIST_LIMIT),
+            Some("limit"),
+        ));
+    }
+    Ok(AxumJson(ThreadList::new(state.threads.list(limit))))
+}
+
+pub async fn get_thread_handler(
+    State(state): State<AppState>,
+    UrlPath(thread_id): UrlPath<String>,
+) -> Result<AxumJson<Thread>, AiError> {
+    state
+        .threads
+        .get(&thread_id)
+        .map(AxumJson)
+        .ok_or(AiError::ThreadNotFound(thread_id))
+}
+
+pub async fn delete_thread_handler(
+    State(state): State<AppState>,
+    UrlPath(thread_id): UrlPath<String>,
+) -> Result<AxumJson<Value>, AiError> {
+    if !state.threads.delete(&thread_id).await? {
+        return Err(AiError::ThreadNotFound(thread_id));
+    }
+    log::info!("🗑️ Deleted thread {}", thread_id);
+    Ok(AxumJson(json!({
+        "id": thread_id,
+        "object": "thread.deleted",
+        "deleted": true
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListMessagesQuery {
+    // Show the branch through this message instead of the active one
+    pub message_id: Option<String>,
+}
+
+pub async fn list_messages_handler(
+    State(state): State<AppState>,
+    UrlPath(thread_id): UrlPath<String>,
+    Query(query): Query<ListMessagesQuery>,
+) -> Result<AxumJson<ThreadList<BranchMessage>>, AiError> {
+    state
+        .threads
+        .branch(&thread_id, query.message_id.as_deref())?
+        .map(|messages| AxumJson(ThreadList::new(messages)))
+        .ok_or(AiError::ThreadNotFound(thread_id))
+}
+
+// Alternatives at a node (edits of a user message, regenerated replies), oldest first
+pub async fn list_branches_handler(
+    State(state): State<AppState>,
+    UrlPath((thread_id, message_id)): UrlPath<(String, String)>,
+) -> Result<AxumJson<ThreadList<ThreadMessage>>, AiError> {
+    state
+        .threads
+        .siblings(&thread_id, &message_id)?
+        .map(|messages| AxumJson(ThreadList::new(messages)))
+        .ok_or(AiError::ThreadNotFound(thread_id))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AppendMessageRequest {
+    #[serde(flatten)]
+    pub message: ChatMessage,
+    #[serde(default, deserialize_with = "explicit_null")]
+    pub parent_id: Option<Option<String>>,
+}
+
+// Tells an explicit `null` (Some(None)) apart from a missing field (None)
+fn explicit_null<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<String>>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(Some)
+}
+
+pub async fn append_message_handler(
+    State(state): State<AppState>,
+    UrlPath(thread_id): UrlPath<String>,
+    ApiJson(request): ApiJson<AppendMessageRequest>,
+) -> Result<AxumJson<ThreadMessage>, AiError> {
+    let parent = Parent::from_field(request.parent_id);
+    let mut appended = state
+        .threads
+        .append(&thread_id, &parent, vec![request.message])
+        .await?
+        .ok_or(AiError::ThreadNotFound(thread_id))?;
+    Ok(AxumJson(appended.remove(0)))
+}
+
+// A chat completion request whose history is the stored thread, up to `parent_id` (the head by
+// default, null for an empty history). `messages` is optional here: any given are attached under
+// that node before the run. Editing a user message is a run from its parent with the new text;
+// regenerating a reply is a run from the user message before it. The thread only changes when
+// the run succeeds, and then gains those messages plus the reply (the first choice), whose id is
+// returned in the `x-thread-message-id` header. Streamed runs forward chunks as they arrive and
+// store the reply once the stream has finished; one cut short leaves the thread unchanged.
+pub async fn run_thread_handler(
+    State(state): State<AppState>,
+    UrlPath(thread_id): UrlPath<String>,
+    ApiJson(mut body): ApiJson<Value>,
+) -> Result<Response, AiError> {
+    let mut parent_id = None;
+    if let Some(object) = body.as_object_mut() {
+        object.entry("messages").or_insert_with(|| json!([]));
+        parent_id = object.remove("parent_id").map(|id| match id {
+            Value::Null => Ok(None),
+            Value::String(id) => Ok(Some(id)),
+            _ => Err(AiError::invalid_request(
+                "'parent_id' must be a message id or null",
+                Some("parent_id"),
+            )),
+        });
+    }
+    let parent = Parent::from_field(parent_id.transpose()?);
+    let Some(history) = state.threads.history(&thread_id, &parent)? else {
+        return Err(AiError::ThreadNotFound(thread_id));
+    };
+    // Pin the parent so a concurrent append to the head cannot move this run
+    let parent = match history.last() {
+        Some(message) => Parent::Message(message.id.clone()),
+        None => Parent::Root,
+    };
+
+    let mut request: ChatCompletionRequest = deserialize_value(body)?;
+    let new_messages = std::mem::take(&mut request.messages);
+    request.messages = history
+        .into_iter()
+        .map(|stored| stored.message)
+        .chain(new_messages.iter().cloned())
+        .collect();
+
+    let streaming = request.stream.unwrap_or(false);
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .is_some_and(|options| options.include_usage);
+    log::info!(
+        "🧵 Thread run: thread={}, parent={:?}, model={}, messages={}, stream={}",
+        thread_id,
+        parent,
+        request.model,
+        request.messages.len(),
+        streaming
+    );
+
+    let report = prepare_chat_request(&state, &mut request).await?;
+    let (mut response, served_by, reply_id) =
+        if streaming && request.tool_execution != ToolExecution::Server {
+            let (chunks, served_by) = open_chat_stream(&state, &request).await?;
+            let reply_id = new_id("msg");
+            let chunks = store_when_finished(
+                state.threads.clone(),
+                thread_id,
+                parent,
+                new_messages,
+                reply_id.clone(),
+                chunks,
+            );
+            let response = crate::stream::sse_response(chunks);
+            (response, served_by, Some(reply_id))
+        } else {
+            let completion = complete_chat(&state, request).await?;
+            let served_by = completion.served_by.clone();
+
+            let mut appended = new_messages;
+            appended.extend(completion.choices.first().map(|c| c.message.clone()));
+            let stored = state
+                .threads
+                .append(&thread_id, &parent, appended)
+                .await?
+                .ok_or_else(|| AiError::ThreadNotFound(thread_id.clone()))?;
+
+            let response = if streaming {
+                completed_stream_response(completion, include_usage)
+            } else {
+                AxumJson(completion).into_response()
+            };
+            (
+                response,
+                served_by,
+                stored.last().map(|reply| reply.id.clone()),
+            )
+        };
+    if let Ok(value) = HeaderValue::from_str(&report.to_string()) {
+        response
+            .headers_mut()
+            .insert(CONTEXT_STRATEGY_HEADER, value);
+    }
+    if let Some(value) = reply_id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+        response.headers_mut().insert(THREAD_MESSAGE_HEADER, value);
+    }
+    insert_served_by(&mut response, served_by.as_deref(), state.provider.name());
+    Ok(response)
+}
+
+// Pass `chunks` through and, once all of them arrived without an error, attach `new_messages`
+// and the assembled reply under `parent`
+fn store_when_finished(
+    store: Arc<ThreadStore>,
+    thread_id: String,
+    parent: Parent,
+    new_messages: Vec<ChatMessage>,
+    reply_id: String,
+    chunks: ChunkStream,
+) -> ChunkStream {
+    let reply = Arc::new(Mutex::new(Some(MessageAccumulator::default())));
+    let collected = reply.clone();
+    let chunks = chunks.map(move |item| {
+        let mut reply = collected.lock().unwrap_or_else(|e| e.into_inner());
+        match &item {
+            Ok(chunk) => {
+                if let Some(reply) = reply.as_mut() {
+                    reply.push(chunk);
+                }
+            }
+            Err(_) => *reply = None,
+        }
+        item
+    });
+
+    let store = stream::once(async move {
+        let Some(reply) = reply.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+            log::warn!(
+                "⚠️ Thread {} run failed mid-stream; nothing stored",
+                thread_id
+            );
+            return;
+        };
+        let mut messages = new_messages;
+        messages.push(reply.into_message());
+        match store
+            .append_as(&thread_id, &parent, messages, Some(reply_id))
+            .await
+        {
+            Ok(Some(_)) => {}
+            Ok(None) => log::warn!("⚠️ Thread {} was deleted during a streamed run", thread_id),
+            Err(e) => log::error!(
+                "❌ Failed to store streamed reply in thread {}: {}",
+                thread_id,
+                e
+            ),
+        }
+    })
+    .filter_map(|()| async { None });
+    chunks.chain(store).boxed()
+}
@@ -0,0 +1,168 @@
+// Token counting with the BPE vocabularies OpenAI models use. The cl100k/o200k vocabularies are
+// bundled with tiktoken-rs, so nothing is downloaded at runtime. Chat overheads follow OpenAI's
+// published accounting: 3 tokens per message, 1 per name, 3 to prime the reply.
+use crate::ai::{ChatCompletionRequest, ChatMessage, ContentPart, MessageContent, Tool};
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton, CoreBPE};
+
+const TOKENS_PER_MESSAGE: u32 = 3;
+const TOKENS_PER_NAME: u32 = 1;
+const REPLY_PRIMING_TOKENS: u32 = 3;
+
+// Images are billed per tile upstream: a flat 85 tokens at low detail, roughly 765 otherwise
+// (a 1024x1024 image)
+const LOW_DETAIL_IMAGE_TOKENS: u32 = 85;
+const IMAGE_TOKENS: u32 = 765;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    // gpt-4, gpt-3.5-turbo and the usual approximation for non-OpenAI models
+    #[default]
+    Cl100kBase,
+    // gpt-4o, gpt-4.1, o1, o3, ...
+    O200kBase,
+}
+
+impl Encoding {
+    pub fn for_model(model: &str) -> Self {
+        match get_tokenizer(model) {
+            Some(Tokenizer::O200kBase) => Encoding::O200kBase,
+            _ => Encoding::Cl100kBase,
+        }
+    }
+
+    fn bpe(self) -> &'static CoreBPE {
+        match self {
+            Encoding::Cl100kBase => cl100k_base_singleton(),
+            Encoding::O200kBase => o200k_base_singleton(),
+        }
+    }
+
+    // Special-token markers in user text are counted as plain text, as the API does
+    pub fn count(self, text: &str) -> u32 {
+        if text.is_empty() {
+            return 0;
+        }
+        self.bpe().encode_ordinary(text).len() as u32
+    }
+
+    // Raw bytes of each token of `text`, in order; a multi-byte character may span tokens
+    pub fn split(self, text: &str) -> Vec<Vec<u8>> {
+        let bpe = self.bpe();
+        bpe._decode_native_and_split(bpe.encode_ordinary(text))
+            .collect()
+    }
+
+    // The first `max_tokens` tokens of `text`, or None when it already fits. A character cut in
+    // half at the boundary is dropped.
+    pub fn truncate(self, text: &str, max_tokens: u32) -> Option<String> {
+        let tokens = self.split(text);
+        if tokens.len() <= max_tokens as usize {
+            return None;
+        }
+        let bytes: Vec<u8> = tokens
+            .into_iter()
+            .take(max_tokens as usize)
+            .flatten()
+            .collect();
+        Some(match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => {
+                let valid = e.utf8_error().valid_up_to();
+                let mut bytes = e.into_bytes();
+                bytes.truncate(valid);
+                String::from_utf8(bytes).unwrap_or_default()
+            }
+        })
+    }
+
+    pub fn count_content(self, content: &MessageContent) -> u32 {
+        match content {
+            MessageContent::Text(text) => self.count(text),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text { text } => self.count(text),
+                    ContentPart::ImageUrl { image_url } => match image_url.detail.as_deref() {
+                        Some("low") => LOW_DETAIL_IMAGE_TOKENS,
+                        _ => IMAGE_TOKENS,
+                    },
+                    // Inline files are estimated from their decoded size; uploaded ones are
+                    // resolved upstream and cannot be counted here
+                    ContentPart::File { file } => file
+                        .file_data
+                        .as_ref()
+                        .map(|data| (data.len() as u32 * 3 / 4) / 4)
+                        .unwrap_or(0),
+                })
+                .sum(),
+        }
+    }
+
+    // One message as it appears in the prompt, including its framing tokens
+    pub fn count_message(self, message: &ChatMessage) -> u32 {
+        let mut tokens = TOKENS_PER_MESSAGE + self.count(message.role.as_str());
+        if let Some(content) = &message.content {
+            tokens += self.count_content(content);
+        }
+        if let Some(name) = &message.name {
+            tokens += TOKENS_PER_NAME + self.count(name);
+        }
+        if let Some(tool_call_id) = &message.tool_call_id {
+            tokens += self.count(tool_call_id);
+        }
+        for call in message.tool_calls.iter().flatten() {
+            tokens += TOKENS_PER_MESSAGE
+                + self.count(&call.function.name)
+                + self.count(&call.function.arguments);
+        }
+        tokens
+    }
+
+    pub fn count_messages(self, messages: &[ChatMessage]) -> u32 {
+        messages
+            .iter()
+            .map(|message| self.count_message(message))
+            .sum::<u32>()
+            + REPLY_PRIMING_TOKENS
+    }
+
+    // Function definitions are injected into the prompt upstream; their JSON is a close estimate
+    pub fn count_tools(self, tools: &[Tool]) -> u32 {
+        tools
+            .iter()
+            .map(|tool| {
+                self.count(&tool.function.name)
+                    + self.count(&tool.function.description)
+                    + self.count(&tool.function.parameters.to_string())
+            })
+            .sum()
+    }
+
+    pub fn count_prompt(self, request: &ChatCompletionRequest) -> u32 {
+        self.count_messages(&request.messages)
+            + request
+                .tools
+                .as_deref()
+                .map(|tools| self.count_tools(tools))
+                .unwrap_or(0)
+    }
+
+    // Tokens the model generated for an assistant message (no prompt framing)
+    pub fn count_completion(self, message: &ChatMessage) -> u32 {
+        let content = message
+            .content
+            .as_ref()
+            .map(|content| self.count_content(content))
+            .unwrap_or(0);
+        let tool_calls: u32 = message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| self.count(&call.function.name) + self.count(&call.function.arguments))
+            .sum();
+        content + tool_calls
+    }
+}
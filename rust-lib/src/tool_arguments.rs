@@ -0,0 +1,178 @@
+// Tool call arguments are checked against the tool's JSON Schema before they are dispatched to an
+// MCP server. Failures come back as JSON the model can read in the tool message and correct.
+use serde::Serialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArgumentIssue {
+    pub path: String, // JSON pointer into the arguments, "" for the arguments object itself
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, thiserror::Error)]
+#[error("Invalid arguments for tool '{tool}': {}", summarize(.issues))]
+pub struct ToolArgumentError {
+    pub tool: String,
+    pub issues: Vec<ArgumentIssue>,
+}
+
+impl ToolArgumentError {
+    fn new(tool: &str, path: &str, message: impl Into<String>) -> Self {
+        Self {
+            tool: tool.to_string(),
+            issues: vec![ArgumentIssue {
+                path: path.to_string(),
+                message: message.into(),
+            }],
+        }
+    }
+
+    // Content of the tool message sent back to the model
+    pub fn to_tool_message(&self) -> String {
+        json!({
+            "error": {
+                "type": "invalid_arguments",
+                "tool": self.tool,
+                "message": self.to_string(),
+                "issues": self.issues,
+            }
+        })
+        .to_string()
+    }
+}
+
+fn summarize(issues: &[ArgumentIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| match issue.path.as_str() {
+            "" => issue.message.clone(),
+            path => format!("{}: {}", path, issue.message),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// Parse the raw `function.arguments` string, optionally coerce scalar types to what the schema
+// expects ("5" -> 5, "true" -> true, 5 -> "5"), then validate. Returns the arguments to send.
+pub fn prepare_arguments(
+    tool: &str,
+    schema: &Value,
+    raw: &str,
+    coerce: bool,
+) -> Result<Value, ToolArgumentError> {
+    let mut arguments = if raw.trim().is_empty() {
+        json!({})
+    } else {
+        serde_json::from_str(raw).map_err(|e| {
+            ToolArgumentError::new(tool, "", format!("arguments are not valid JSON: {}", e))
+        })?
+    };
+
+    // Tools declared without a schema accept anything
+    if schema.as_object().is_none_or(|schema| schema.is_empty()) {
+        return Ok(arguments);
+    }
+    if coerce {
+        coerce_to_schema(&mut arguments, schema);
+    }
+
+    let validator = match jsonschema::validator_for(schema) {
+        Ok(validator) => validator,
+        Err(e) => {
+            log::warn!(
+                "⚠️ Skipping argument validation for tool '{}': invalid schema: {}",
+                tool,
+                e
+            );
+            return Ok(arguments);
+        }
+    };
+
+    let issues: Vec<ArgumentIssue> = validator
+        .iter_errors(&arguments)
+        .map(|error| ArgumentIssue {
+            path: error.instance_path.to_string(),
+            message: error.to_string(),
+        })
+        .collect();
+
+    if issues.is_empty() {
+        Ok(arguments)
+    } else {
+        Err(ToolArgumentError {
+            tool: tool.to_string(),
+            issues,
+        })
+    }
+}
+
+// Walk the value alongside `properties` / `items` and convert scalars whose type does not match
+pub fn coerce_to_schema(value: &mut Value, schema: &Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if let Some(child_schema) = schema["properties"].get(key) {
+                    coerce_to_schema(child, child_schema);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                coerce_to_schema(item, &schema["items"]);
+            }
+        }
+        _ => {
+            let types: Vec<&str> = match &schema["type"] {
+                Value::String(ty) => vec![ty.as_str()],
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                _ => return,
+            };
+            if types.iter().any(|ty| has_type(value, ty)) {
+                return;
+            }
+            if let Some(coerced) = types.iter().find_map(|ty| convert(value, ty)) {
+                log::debug!("🔧 Coerced tool argument {} to {}", value, coerced);
+                *value = coerced;
+            }
+        }
+    }
+}
+
+fn has_type(value: &Value, ty: &str) -> bool {
+    match ty {
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        _ => false,
+    }
+}
+
+fn convert(value: &Value, ty: &str) -> Option<Value> {
+    match (ty, value) {
+        ("integer", Value::String(s)) => s.trim().parse::<i64>().ok().map(Value::from),
+        ("integer", Value::Number(n)) => n
+            .as_f64()
+            .filter(|f| f.fract() == 0.0 && f.abs() < i64::MAX as f64)
+            .map(|f| Value::from(f as i64)),
+        ("number", Value::String(s)) => {
+            let s = s.trim();
+            s.parse::<i64>().ok().map(Value::from).or_else(|| {
+                s.parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Value::Number)
+            })
+        }
+        ("boolean", Value::String(s)) => match s.trim().to_ascii_lowercase().as_str() {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        ("string", Value::Number(_) | Value::Bool(_)) => Some(Value::String(value.to_string())),
+        _ => None,
+    }
+}
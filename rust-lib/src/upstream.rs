@@ -0,0 +1,672 @@
+// Resilient access to upstream providers: per-provider timeouts, retries with exponential backoff
+// that honor Retry-After, circuit breaking, and ordered fallback chains per model alias (say a
+// hosted OpenAI-compatible endpoint, then a local Ollama one). Configured in providers.yaml.
+use crate::ai::{ChatCompletionRequest, ChatCompletionResponse};
+use crate::config::{default_config_dir, expand_env_vars, read_yaml, ConfigError, ValidationIssue};
+use crate::provider::{ChatProvider, MockProvider, OpenAiConfig, OpenAiProvider, ProviderError};
+use crate::stream::ChunkStream;
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const PROVIDERS_FILE: &str = "providers.yaml";
+
+// Response header naming the upstream that produced the reply
+pub const SERVED_BY_HEADER: &str = "x-served-by";
+
+pub const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+// Retry-After carries either a number of seconds or an HTTP date
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    // Attempts after the first one
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    // Cap on any single wait, Retry-After included
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+fn default_max_retries() -> u32 {
+    2
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_ms() -> u64 {
+    10_000
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Wait before retry number `retry` (from 0): the backoff doubles each time, and an upstream's
+    // Retry-After wins when longer. None when the upstream asks for more than the cap, so the
+    // chain moves on instead of stalling the client.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        let max = Duration::from_millis(self.max_backoff_ms);
+        let backoff = Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(1u64 << retry.min(32)),
+        )
+        .min(max);
+        match retry_after {
+            Some(wait) if wait > max => None,
+            Some(wait) => Some(wait.max(backoff)),
+            None => Some(backoff),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    // Consecutive failed attempts that open the circuit
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    // How long an open circuit skips the upstream before letting one probe through
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_failure_threshold() -> u32 {
+    DEFAULT_FAILURE_THRESHOLD
+}
+
+fn default_cooldown_secs() -> u64 {
+    DEFAULT_COOLDOWN.as_secs()
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            cooldown_secs: default_cooldown_secs(),
+        }
+    }
+}
+
+// Closed: requests flow and failures are counted. Open: requests are refused until the cooldown
+// passes. Half-open: one probe goes out, and its outcome closes or reopens the circuit.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+    // When the half-open probe went out; a probe abandoned mid-flight expires after a cooldown
+    probe_started: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.lock()
+            .open_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    // Whether a request may go out now; otherwise how long until the next probe
+    fn acquire(&self) -> Result<(), Duration> {
+        let mut state = self.lock();
+        let Some(until) = state.open_until else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if now < until {
+            return Err(until - now);
+        }
+        match state.probe_started {
+            Some(started) if now < started + self.cooldown => Err(started + self.cooldown - now),
+            _ => {
+                state.probe_started = Some(now);
+                Ok(())
+            }
+        }
+    }
+
+    // Returns true when this failure opened (or reopened) the circuit
+    fn record(&self, healthy: bool) -> bool {
+        let mut state = self.lock();
+        state.probe_started = None;
+        if healthy {
+            *state = BreakerState::default();
+            return false;
+        }
+        state.failures += 1;
+        if state.failures >= self.failure_threshold || state.open_until.is_some() {
+            state.open_until = Some(Instant::now() + self.cooldown);
+            return true;
+        }
+        false
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Wraps a provider with a per-attempt timeout, retries of transient failures and a circuit
+// breaker. Streams are retried only until they open; a stream failing midway is not replayed.
+// Once open, the same timeout bounds the wait for each further chunk.
+pub struct ResilientProvider {
+    inner: Arc<dyn ChatProvider>,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
+}
+
+impl ResilientProvider {
+    pub fn new(inner: Arc<dyn ChatProvider>) -> Self {
+        Self {
+            inner,
+            timeout: Some(DEFAULT_UPSTREAM_TIMEOUT),
+            retry: RetryPolicy::default(),
+            breaker: CircuitBreaker::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Self {
+        self.breaker = CircuitBreaker::new(failure_threshold, cooldown);
+        self
+    }
+
+    pub fn circuit_open(&self) -> bool {
+        self.breaker.is_open()
+    }
+
+    async fn call<T, F, Fut>(&self, attempt: F) -> Result<T, ProviderError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, ProviderError>>,
+    {
+        let name = self.inner.name();
+        let mut retry = 0;
+        loop {
+            if let Err(wait) = self.breaker.acquire() {
+                return Err(ProviderError::Unavailable(format!(
+                    "circuit open for '{}', next attempt in {}s",
+                    name,
+                    wait.as_secs().max(1)
+                )));
+            }
+
+            let result = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, attempt())
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(ProviderError::Timeout(format!(
+                            "no response from '{}' within {}ms",
+                            name,
+                            timeout.as_millis()
+                        )))
+                    }),
+                None => attempt().await,
+            };
+            let error = match result {
+                Ok(value) => {
+                    self.breaker.record(true);
+                    return Ok(value);
+                }
+                Err(error) => error,
+            };
+
+            // Only transient failures say something about the upstream's health
+            if self.breaker.record(!error.is_retryable()) {
+                log::warn!(
+                    "🔌 Circuit opened for '{}' after: {}; skipping it for {}s",
+                    name,
+                    error,
+                    self.breaker.cooldown.as_secs()
+                );
+                return Err(error);
+            }
+            if !error.is_retryable() || retry >= self.retry.max_retries {
+                return Err(error);
+            }
+            let Some(delay) = self.retry.delay(retry, error.retry_after()) else {
+                log::warn!(
+                    "⚠️ '{}' asked to wait longer than the retry cap: {}",
+                    name,
+                    error
+                );
+                return Err(error);
+            };
+            retry += 1;
+            log::warn!(
+                "🔁 Retrying '{}' in {}ms (retry {}/{}): {}",
+                name,
+                delay.as_millis(),
+                retry,
+                self.retry.max_retries,
+                error
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for ResilientProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn complete(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        self.call(|| self.inner.complete(request)).await
+    }
+
+    async fn complete_stream(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChunkStream, ProviderError> {
+        let chunks = self.call(|| self.inner.complete_stream(request)).await?;
+        Ok(match self.timeout {
+            Some(timeout) => idle_timeout(chunks, timeout, self.inner.name().to_string()),
+            None => chunks,
+        })
+    }
+}
+
+// End a stream with a timeout error once the upstream stalls for longer than `timeout`
+fn idle_timeout(chunks: ChunkStream, timeout: Duration, name: String) -> ChunkStream {
+    futures::stream::unfold(Some(chunks), move |chunks| {
+        let name = name.clone();
+        async move {
+            let mut chunks = chunks?;
+            match tokio::time::timeout(timeout, chunks.next()).await {
+                Ok(Some(item)) => Some((item, Some(chunks))),
+                Ok(None) => None,
+                Err(_) => {
+                    log::warn!(
+                        "⏱️ '{}' stalled mid-stream for {}ms",
+                        name,
+                        timeout.as_millis()
+                    );
+                    let error = ProviderError::Timeout(format!(
+                        "no chunk from '{}' within {}ms",
+                        name,
+                        timeout.as_millis()
+                    ));
+                    Some((Err(error), None))
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+// Tries the providers of a chain in order until one answers. Chains are picked by the alias the
+// client asked for, then by the resolved model id, then the default chain applies. Responses
+// (and stream chunks) name the provider that served them in `served_by`.
+pub struct FallbackProvider {
+    default_chain: Vec<Arc<dyn ChatProvider>>,
+    chains: HashMap<String, Vec<Arc<dyn ChatProvider>>>,
+}
+
+impl FallbackProvider {
+    pub fn new(default_chain: Vec<Arc<dyn ChatProvider>>) -> Self {
+        Self {
+            default_chain,
+            chains: HashMap::new(),
+        }
+    }
+
+    // Chain for a model id or alias
+    pub fn with_chain(
+        mut self,
+        model: impl Into<String>,
+        chain: Vec<Arc<dyn ChatProvider>>,
+    ) -> Self {
+        self.chains.insert(model.into(), chain);
+        self
+    }
+
+    fn chain(&self, request: &ChatCompletionRequest) -> &[Arc<dyn ChatProvider>] {
+        request
+            .alias
+            .as_deref()
+            .and_then(|alias| self.chains.get(alias))
+            .or_else(|| self.chains.get(&request.model))
+            .unwrap_or(&self.default_chain)
+    }
+
+    async fn first_success<'a, T, F, Fut>(
+        &'a self,
+        request: &ChatCompletionRequest,
+        call: F,
+    ) -> Result<(T, &'a str), ProviderError>
+    where
+        F: Fn(&'a dyn ChatProvider) -> Fut,
+        Fut: Future<Output = Result<T, ProviderError>>,
+    {
+        let chain = self.chain(request);
+        let mut last_error = None;
+        for (position, provider) in chain.iter().enumerate() {
+            match call(provider.as_ref()).await {
+                Ok(value) => {
+                    if position > 0 {
+                        log::info!(
+                            "↪️ Served model '{}' by fallback '{}'",
+                            request.model,
+                            provider.name()
+                        );
+                    }
+                    return Ok((value, provider.name()));
+                }
+                // Another upstream would reject the same request
+                Err(error) if error.is_caller_error() => return Err(error),
+                Err(error) => {
+                    log::warn!(
+                        "⚠️ Upstream '{}' failed for model '{}': {}",
+                        provider.name(),
+                        request.model,
+                        error
+                    );
+                    last_error = Some(error);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            ProviderError::Unavailable(format!(
+                "no upstream configured for model '{}'",
+                request.model
+            ))
+        }))
+    }
+}
+
+#[async_trait]
+impl ChatProvider for FallbackProvider {
+    fn name(&self) -> &str {
+        "fallback"
+    }
+
+    async fn complete(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let (mut response, served_by) = self
+            .first_success(request, |provider| provider.complete(request))
+            .await?;
+        response.served_by = Some(served_by.to_string());
+        Ok(response)
+    }
+
+    async fn complete_stream(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChunkStream, ProviderError> {
+        let (chunks, served_by) = self
+            .first_success(request, |provider| provider.complete_stream(request))
+            .await?;
+        let served_by = served_by.to_string();
+        Ok(chunks
+            .map(move |chunk| {
+                chunk.map(|mut chunk| {
+                    chunk.served_by = Some(served_by.clone());
+                    chunk
+                })
+            })
+            .boxed())
+    }
+}
+
+// providers.yaml: named upstreams, fallback chains per model id or alias, and the chain used for
+// every other model. `${VAR}` references are expanded.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamConfig {
+    #[serde(default)]
+    pub providers: BTreeMap<String, UpstreamProviderConfig>,
+    // Model id or alias -> provider names, tried in order
+    #[serde(default)]
+    pub chains: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub default_chain: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamProviderConfig {
+    #[serde(default, rename = "type")]
+    pub kind: UpstreamKind,
+    // Base URL including the version prefix, e.g. "http://localhost:11434/v1"
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    // Client-facing model -> upstream model
+    #[serde(default)]
+    pub models: HashMap<String, String>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_UPSTREAM_TIMEOUT.as_secs()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum UpstreamKind {
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAi,
+    #[serde(rename = "mock")]
+    Mock,
+}
+
+impl UpstreamConfig {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let mut value = read_yaml(path)?;
+        expand_env_vars(&mut value);
+
+        let config: UpstreamConfig =
+            serde_path_to_error::deserialize(value).map_err(|e| ConfigError::Schema {
+                path: path.to_path_buf(),
+                field: e.path().to_string(),
+                message: e.into_inner().to_string(),
+            })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // `<config dir>/providers.yaml` when present and valid
+    pub fn from_env() -> Option<Self> {
+        let path = default_config_dir().join(PROVIDERS_FILE);
+        if !path.exists() {
+            return None;
+        }
+        match Self::load(&path) {
+            Ok(config) => {
+                log::info!(
+                    "🔗 Loaded {} upstream providers and {} fallback chains from {}",
+                    config.providers.len(),
+                    config.chains.len(),
+                    path.display()
+                );
+                Some(config)
+            }
+            Err(e) => {
+                log::error!("❌ Failed to load upstream providers: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut issues = Vec::new();
+        let mut issue =
+            |path: String, message: String| issues.push(ValidationIssue { path, message });
+
+        for (name, provider) in &self.providers {
+            let path = format!("providers.{}", name);
+            if provider.kind == UpstreamKind::OpenAi
+                && provider.base_url.as_deref().is_none_or(str::is_empty)
+            {
+                issue(
+                    format!("{}.base_url", path),
+                    "is required for openai providers".to_string(),
+                );
+            }
+            if provider.timeout_secs == 0 {
+                issue(
+                    format!("{}.timeout_secs", path),
+                    "must be greater than 0".to_string(),
+                );
+            }
+            if provider.circuit_breaker.failure_threshold == 0 {
+                issue(
+                    format!("{}.circuit_breaker.failure_threshold", path),
+                    "must be greater than 0".to_string(),
+                );
+            }
+        }
+
+        let chains = self
+            .chains
+            .iter()
+            .map(|(model, chain)| (format!("chains.{}", model), chain))
+            .chain(std::iter::once((
+                "default_chain".to_string(),
+                &self.default_chain,
+            )));
+        for (path, chain) in chains {
+            if chain.is_empty() {
+                issue(path.clone(), "must name at least one provider".to_string());
+            }
+            for (i, name) in chain.iter().enumerate() {
+                if !self.providers.contains_key(name) {
+                    issue(
+                        format!("{}[{}]", path, i),
+                        format!("unknown provider '{}'", name),
+                    );
+                }
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Validation(issues))
+        }
+    }
+
+    pub fn build(&self) -> Arc<dyn ChatProvider> {
+        let providers: HashMap<&str, Arc<dyn ChatProvider>> = self
+            .providers
+            .iter()
+            .map(|(name, config)| (name.as_str(), config.build(name)))
+            .collect();
+        let chain = |names: &[String]| -> Vec<Arc<dyn ChatProvider>> {
+            names
+                .iter()
+                .filter_map(|name| providers.get(name.as_str()).cloned())
+                .collect()
+        };
+
+        log::info!(
+            "🔗 Default upstream chain: {}",
+            self.default_chain.join(" -> ")
+        );
+        let fallback = self.chains.iter().fold(
+            FallbackProvider::new(chain(&self.default_chain)),
+            |fallback, (model, names)| {
+                log::info!("🔗 Upstream chain for '{}': {}", model, names.join(" -> "));
+                fallback.with_chain(model.clone(), chain(names))
+            },
+        );
+        Arc::new(fallback)
+    }
+}
+
+impl UpstreamProviderConfig {
+    fn build(&self, name: &str) -> Arc<dyn ChatProvider> {
+        let inner: Arc<dyn ChatProvider> = match self.kind {
+            UpstreamKind::Mock => Arc::new(MockProvider::new()),
+            UpstreamKind::OpenAi => {
+                let mut config =
+                    OpenAiConfig::new(self.base_url.clone().unwrap_or_default()).with_name(name);
+                // Unset variables are left as "${VAR}"; send no key rather than the placeholder
+                if let Some(api_key) = self
+                    .api_key
+                    .as_deref()
+                    .filter(|key| !key.is_empty() && !key.starts_with("${"))
+                {
+                    config = config.with_api_key(api_key);
+                }
+                for (from, to) in &self.models {
+                    config = config.with_model(from, to);
+                }
+                Arc::new(OpenAiProvider::new(config))
+            }
+        };
+        Arc::new(
+            ResilientProvider::new(inner)
+                .with_timeout(Duration::from_secs(self.timeout_secs))
+                .with_retry(self.retry.clone())
+                .with_circuit_breaker(
+                    self.circuit_breaker.failure_threshold,
+                    Duration::from_secs(self.circuit_breaker.cooldown_secs),
+                ),
+        )
+    }
+}
@@ -0,0 +1,437 @@
+// Semantic validation of chat completion requests, run after deserialization and before any
+// RAG, tool or provider work. Each error names the offending field in `param`.
+use crate::agent::ToolExecution;
+use crate::ai::{
+    ChatCompletionRequest, ChatMessage, ContentPart, ResponseFormat, Role, ToolChoice,
+};
+use crate::context::ContextStrategy;
+use crate::error::AiError;
+use base64::Engine;
+use std::collections::HashSet;
+
+pub const MAX_TOOL_NAME_LEN: usize = 64;
+pub const MAX_CHOICES: u32 = 128;
+pub const MAX_STOP_SEQUENCES: usize = 4;
+pub const MAX_TOP_LOGPROBS: u32 = 20;
+
+impl ChatCompletionRequest {
+    pub fn validate(&self) -> Result<(), AiError> {
+        self.validate_fields()?;
+        self.validate_tool_choice()
+    }
+
+    // Everything the client sent except `tool_choice`, which is checked separately once MCP
+    // tools have been injected into a request without `tools`
+    pub(crate) fn validate_fields(&self) -> Result<(), AiError> {
+        if self.messages.is_empty() {
+            return Err(invalid(
+                "'messages' must contain at least one message",
+                "messages",
+            ));
+        }
+
+        let mut tool_call_ids = HashSet::new();
+        for (i, message) in self.messages.iter().enumerate() {
+            validate_message(i, message, &tool_call_ids)?;
+            for call in message.tool_calls.iter().flatten() {
+                tool_call_ids.insert(call.id.as_str());
+            }
+        }
+
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(invalid(
+                    format!("'temperature' must be between 0 and 2, got {}", temperature),
+                    "temperature",
+                ));
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(0.0..=1.0).contains(&top_p) {
+                return Err(invalid(
+                    format!("'top_p' must be between 0 and 1, got {}", top_p),
+                    "top_p",
+                ));
+            }
+        }
+        for (param, penalty) in [
+            ("presence_penalty", self.presence_penalty),
+            ("frequency_penalty", self.frequency_penalty),
+        ] {
+            if let Some(penalty) = penalty.filter(|p| !(-2.0..=2.0).contains(p)) {
+                return Err(invalid(
+                    format!("'{}' must be between -2 and 2, got {}", param, penalty),
+                    param,
+                ));
+            }
+        }
+        if self.max_tokens == Some(0) {
+            return Err(invalid("'max_tokens' must be at least 1", "max_tokens"));
+        }
+        if let Some(n) = self.n {
+            if !(1..=MAX_CHOICES).contains(&n) {
+                return Err(invalid(
+                    format!("'n' must be between 1 and {}, got {}", MAX_CHOICES, n),
+                    "n",
+                ));
+            }
+            // The agent loop follows a single conversation
+            if n > 1 && self.tool_execution == ToolExecution::Server {
+                return Err(invalid(
+                    "'n' greater than 1 is not supported with server-side tool execution",
+                    "n",
+                ));
+            }
+        }
+        if let Some(stop) = &self.stop {
+            let sequences = stop.as_slice();
+            if sequences.len() > MAX_STOP_SEQUENCES {
+                return Err(invalid(
+                    format!(
+                        "'stop' accepts at most {} sequences, got {}",
+                        MAX_STOP_SEQUENCES,
+                        sequences.len()
+                    ),
+                    "stop",
+                ));
+            }
+            if sequences.iter().any(String::is_empty) {
+                return Err(invalid("'stop' sequences must not be empty", "stop"));
+            }
+        }
+        for (token, bias) in self.logit_bias.iter().flatten() {
+            if token.parse::<u32>().is_err() {
+                return Err(invalid(
+                    format!("'logit_bias' keys must be token ids, got '{}'", token),
+                    "logit_bias",
+                ));
+            }
+            if !(-100.0..=100.0).contains(bias) {
+                return Err(invalid(
+                    format!(
+                        "'logit_bias' values must be between -100 and 100, got {} for token {}",
+                        bias, token
+                    ),
+                    "logit_bias",
+                ));
+            }
+        }
+        if let Some(top_logprobs) = self.top_logprobs {
+            if top_logprobs > MAX_TOP_LOGPROBS {
+                return Err(invalid(
+                    format!(
+                        "'top_logprobs' must be between 0 and {}, got {}",
+                        MAX_TOP_LOGPROBS, top_logprobs
+                    ),
+                    "top_logprobs",
+                ));
+            }
+            if self.logprobs != Some(true) {
+                return Err(invalid(
+                    "'top_logprobs' requires 'logprobs' to be true",
+                    "top_logprobs",
+                ));
+            }
+        }
+        if self.max_tool_iterations == Some(0) {
+            return Err(invalid(
+                "'max_tool_iterations' must be at least 1",
+                "max_tool_iterations",
+            ));
+        }
+        match &self.context_strategy {
+            Some(ContextStrategy::LastTurns { turns: 0 }) => {
+                return Err(invalid(
+                    "'turns' must be at least 1",
+                    "context_strategy.turns",
+                ));
+            }
+            Some(ContextStrategy::Summarize { keep_turns: 0 }) => {
+                return Err(invalid(
+                    "'keep_turns' must be at least 1",
+                    "context_strategy.keep_turns",
+                ));
+            }
+            Some(ContextStrategy::SlidingWindow {
+                max_tokens: Some(0),
+            }) => {
+                return Err(invalid(
+                    "'max_tokens' must be at least 1",
+                    "context_strategy.max_tokens",
+                ));
+            }
+            _ => {}
+        }
+        if self.stream_options.is_some() && self.stream != Some(true) {
+            return Err(invalid(
+                "'stream_options' is only allowed when 'stream' is true",
+                "stream_options",
+            ));
+        }
+        if let Some(format) = &self.response_format {
+            validate_response_format(format, &self.messages)?;
+        }
+
+        let mut tool_names = HashSet::new();
+        for (i, tool) in self.tools.iter().flatten().enumerate() {
+            if tool.r#type != "function" {
+                return Err(invalid(
+                    format!("unsupported tool type '{}'", tool.r#type),
+                    &format!("tools[{}].type", i),
+                ));
+            }
+            let name = &tool.function.name;
+            let param = format!("tools[{}].function.name", i);
+            if !is_valid_tool_name(name) {
+                return Err(invalid(
+                    format!(
+                        "invalid tool name '{}': use up to {} letters, digits, '_' or '-'",
+                        name, MAX_TOOL_NAME_LEN
+                    ),
+                    &param,
+                ));
+            }
+            if !tool_names.insert(name.as_str()) {
+                return Err(invalid(format!("duplicate tool name '{}'", name), &param));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn validate_tool_choice(&self) -> Result<(), AiError> {
+        let tool_names: HashSet<&str> = self
+            .tools
+            .iter()
+            .flatten()
+            .map(|tool| tool.function.name.as_str())
+            .collect();
+        match &self.tool_choice {
+            Some(ToolChoice::Auto(mode)) if !matches!(mode.as_str(), "auto" | "none" | "required") => {
+                Err(invalid(
+                    format!(
+                        "invalid tool_choice '{}': expected 'auto', 'none', 'required' or a function",
+                        mode
+                    ),
+                    "tool_choice",
+                ))
+            }
+            Some(ToolChoice::Auto(mode)) if mode == "required" && tool_names.is_empty() => {
+                Err(invalid(
+                    "tool_choice 'required' needs at least one tool in 'tools'",
+                    "tool_choice",
+                ))
+            }
+            Some(ToolChoice::Function { r#type, function }) => {
+                if r#type != "function" {
+                    return Err(invalid(
+                        format!("unsupported tool_choice type '{}'", r#type),
+                        "tool_choice.type",
+                    ));
+                }
+                if !tool_names.contains(function.name.as_str()) {
+                    return Err(AiError::UnknownTool {
+                        name: function.name.clone(),
+                        param: Some("tool_choice.function.name".to_string()),
+                    });
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn validate_message(
+    index: usize,
+    message: &ChatMessage,
+    tool_call_ids: &HashSet<&str>,
+) -> Result<(), AiError> {
+    let path = |field: &str| format!("messages[{}].{}", index, field);
+    let has_content = message.content.as_ref().is_some_and(|c| !c.is_empty());
+
+    if message.tool_calls.is_some() && message.role != Role::Assistant {
+        return Err(invalid(
+            "only assistant messages may contain 'tool_calls'",
+            &path("tool_calls"),
+        ));
+    }
+
+    let parts = message
+        .content
+        .as_ref()
+        .map(|c| c.parts())
+        .unwrap_or_default();
+    for (j, part) in parts.iter().enumerate() {
+        let path = |field: &str| format!("messages[{}].content[{}].{}", index, j, field);
+        match part {
+            ContentPart::Text { .. } => {}
+            _ if message.role != Role::User => {
+                return Err(invalid(
+                    format!(
+                        "{} messages may only contain text parts",
+                        message.role.as_str()
+                    ),
+                    &path("type"),
+                ))
+            }
+            ContentPart::ImageUrl { image_url } => {
+                let url = image_url.url.as_str();
+                let valid = if url.starts_with("data:") {
+                    is_base64_data_url(url, "image/")
+                } else {
+                    url.starts_with("https://") || url.starts_with("http://")
+                };
+                if !valid {
+                    return Err(invalid(
+                        "'image_url.url' must be an http(s) URL or a base64 data:image/ URL",
+                        &path("image_url.url"),
+                    ));
+                }
+                if let Some(detail) = image_url.detail.as_deref() {
+                    if !matches!(detail, "auto" | "low" | "high") {
+                        return Err(invalid(
+                            format!(
+                                "invalid image detail '{}': expected 'auto', 'low' or 'high'",
+                                detail
+                            ),
+                            &path("image_url.detail"),
+                        ));
+                    }
+                }
+            }
+            ContentPart::File { file } => match (&file.file_id, &file.file_data) {
+                (None, None) => {
+                    return Err(invalid(
+                        "file parts need a 'file_id' or 'file_data'",
+                        &path("file"),
+                    ))
+                }
+                (_, Some(data)) if !is_base64_data_url(data, "") => {
+                    return Err(invalid(
+                        "'file_data' must be a base64 data URL (data:<mime type>;base64,...)",
+                        &path("file.file_data"),
+                    ))
+                }
+                _ => {}
+            },
+        }
+    }
+
+    match message.role {
+        Role::System | Role::Developer | Role::User if !has_content => Err(invalid(
+            format!(
+                "{} messages must have non-empty 'content'",
+                message.role.as_str()
+            ),
+            &path("content"),
+        )),
+        Role::Assistant
+            if message.content.is_none()
+                && message.tool_calls.as_ref().is_none_or(|c| c.is_empty()) =>
+        {
+            Err(invalid(
+                "assistant messages must have 'content' or 'tool_calls'",
+                &path("content"),
+            ))
+        }
+        Role::Tool => match message.tool_call_id.as_deref() {
+            None | Some("") => Err(invalid(
+                "tool messages must have a 'tool_call_id'",
+                &path("tool_call_id"),
+            )),
+            Some(id) if !tool_call_ids.contains(id) => Err(invalid(
+                format!(
+                    "'tool_call_id' {} does not match any preceding assistant tool call",
+                    id
+                ),
+                &path("tool_call_id"),
+            )),
+            Some(_) => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+fn validate_response_format(
+    format: &ResponseFormat,
+    messages: &[ChatMessage],
+) -> Result<(), AiError> {
+    match format {
+        ResponseFormat::Text => Ok(()),
+        // Same rule as the OpenAI API: without an instruction to answer in JSON, models in JSON
+        // mode tend to emit whitespace until they run out of tokens
+        ResponseFormat::JsonObject => {
+            let mentions_json = messages.iter().any(|message| {
+                message
+                    .content
+                    .as_ref()
+                    .is_some_and(|content| content.to_text().to_lowercase().contains("json"))
+            });
+            if mentions_json {
+                Ok(())
+            } else {
+                Err(invalid(
+                    "'messages' must contain the word 'json' in some form to use 'response_format' of type 'json_object'",
+                    "messages",
+                ))
+            }
+        }
+        ResponseFormat::JsonSchema { json_schema } => {
+            if !is_valid_tool_name(&json_schema.name) {
+                return Err(invalid(
+                    format!(
+                        "invalid schema name '{}': use up to {} letters, digits, '_' or '-'",
+                        json_schema.name, MAX_TOOL_NAME_LEN
+                    ),
+                    "response_format.json_schema.name",
+                ));
+            }
+            let Some(schema) = &json_schema.schema else {
+                return Ok(());
+            };
+            if !schema.is_object() {
+                return Err(invalid(
+                    "'schema' must be a JSON Schema object",
+                    "response_format.json_schema.schema",
+                ));
+            }
+            jsonschema::validator_for(schema).map(|_| ()).map_err(|e| {
+                invalid(
+                    format!("invalid JSON Schema: {}", e),
+                    "response_format.json_schema.schema",
+                )
+            })
+        }
+    }
+}
+
+// `data:<mime>;base64,<payload>` with a MIME type starting with `mime_prefix` and a payload
+// that actually decodes
+fn is_base64_data_url(url: &str, mime_prefix: &str) -> bool {
+    let Some((header, payload)) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(','))
+    else {
+        return false;
+    };
+    let Some(mime) = header.strip_suffix(";base64") else {
+        return false;
+    };
+    mime.starts_with(mime_prefix)
+        && mime.contains('/')
+        && base64::engine::general_purpose::STANDARD
+            .decode(payload)
+            .is_ok()
+}
+
+pub fn is_valid_tool_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_TOOL_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn invalid(message: impl Into<String>, param: &str) -> AiError {
+    AiError::invalid_request(message, Some(param))
+}
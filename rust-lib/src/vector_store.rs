@@ -0,0 +1,210 @@
+// In-process vector index for RAG documents, optionally persisted to an embedded redb file
+use crate::rag::Document;
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+const DOCUMENTS: TableDefinition<&str, &[u8]> = TableDefinition::new("documents");
+
+#[derive(Debug, thiserror::Error)]
+pub enum VectorStoreError {
+    #[error("vector store database error: {0}")]
+    Database(Box<redb::Error>),
+    #[error("failed to create data directory {path}: {source}")]
+    DataDir {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("failed to (de)serialize document: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("vector store write did not complete: {0}")]
+    Write(#[from] tokio::task::JoinError),
+    #[error("document '{0}' has no embedding")]
+    MissingEmbedding(String),
+}
+
+fn db_err(e: impl Into<redb::Error>) -> VectorStoreError {
+    VectorStoreError::Database(Box::new(e.into()))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Similarity {
+    #[default]
+    Cosine,
+    DotProduct,
+}
+
+impl Similarity {
+    pub fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        match self {
+            Similarity::DotProduct => dot,
+            Similarity::Cosine => {
+                let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+                let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+                if norm_a == 0.0 || norm_b == 0.0 {
+                    0.0
+                } else {
+                    dot / (norm_a * norm_b)
+                }
+            }
+        }
+    }
+}
+
+// Brute-force index: every document lives in memory, writes go through to disk when persistent.
+// Disk commits run on the blocking pool, one at a time so they land in order.
+pub struct VectorStore {
+    documents: RwLock<HashMap<String, Document>>,
+    db: Option<Arc<Database>>,
+    writer: tokio::sync::Mutex<()>,
+}
+
+impl VectorStore {
+    pub fn in_memory() -> Self {
+        Self {
+            documents: RwLock::new(HashMap::new()),
+            db: None,
+            writer: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    // Open (or create) the redb file at `path` and load every stored document into memory
+    pub fn open(path: &Path) -> Result<Self, VectorStoreError> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|source| VectorStoreError::DataDir {
+                path: parent.display().to_string(),
+                source,
+            })?;
+        }
+
+        let db = Database::create(path).map_err(db_err)?;
+
+        // Make sure the table exists so read transactions can open it
+        let txn = db.begin_write().map_err(db_err)?;
+        txn.open_table(DOCUMENTS).map_err(db_err)?;
+        txn.commit().map_err(db_err)?;
+
+        let mut documents = HashMap::new();
+        {
+            let txn = db.begin_read().map_err(db_err)?;
+            let table = txn.open_table(DOCUMENTS).map_err(db_err)?;
+            for entry in table.iter().map_err(db_err)? {
+                let (id, bytes) = entry.map_err(db_err)?;
+                match serde_json::from_slice::<Document>(bytes.value()) {
+                    Ok(document) => {
+                        documents.insert(id.value().to_string(), document);
+                    }
+                    Err(e) => log::warn!("⚠️ Skipping unreadable document '{}': {}", id.value(), e),
+                }
+            }
+        }
+
+        log::info!(
+            "📚 Opened vector store at {} with {} documents",
+            path.display(),
+            documents.len()
+        );
+
+        Ok(Self {
+            documents: RwLock::new(documents),
+            db: Some(Arc::new(db)),
+            writer: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub async fn upsert(&self, document: Document) -> Result<(), VectorStoreError> {
+        if document.embedding.as_ref().is_none_or(|e| e.is_empty()) {
+            return Err(VectorStoreError::MissingEmbedding(document.id));
+        }
+
+        let _writing = self.writer.lock().await;
+        let bytes = serde_json::to_vec(&document)?;
+        let id = document.id.clone();
+        self.persist(move |table| table.insert(id.as_str(), bytes.as_slice()).map(|_| ()))
+            .await?;
+
+        self.write_documents().insert(document.id.clone(), document);
+        Ok(())
+    }
+
+    pub async fn remove(&self, id: &str) -> Result<bool, VectorStoreError> {
+        let _writing = self.writer.lock().await;
+        let key = id.to_string();
+        self.persist(move |table| table.remove(key.as_str()).map(|_| ()))
+            .await?;
+
+        Ok(self.write_documents().remove(id).is_some())
+    }
+
+    // Run `write` against the documents table in its own transaction on the blocking pool
+    async fn persist<F>(&self, write: F) -> Result<(), VectorStoreError>
+    where
+        F: FnOnce(&mut redb::Table<&str, &[u8]>) -> Result<(), redb::StorageError> + Send + 'static,
+    {
+        let Some(db) = self.db.clone() else {
+            return Ok(());
+        };
+        tokio::task::spawn_blocking(move || {
+            let txn = db.begin_write().map_err(db_err)?;
+            {
+                let mut table = txn.open_table(DOCUMENTS).map_err(db_err)?;
+                write(&mut table).map_err(db_err)?;
+            }
+            txn.commit().map_err(db_err)
+        })
+        .await?
+    }
+
+    pub fn get(&self, id: &str) -> Option<Document> {
+        self.read_documents().get(id).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.read_documents().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Top-k documents scoring at least `threshold`, best first; documents whose embedding
+    // dimension differs from the query are skipped
+    pub fn search(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        threshold: f32,
+        similarity: Similarity,
+        filter: impl Fn(&Document) -> bool,
+    ) -> Vec<(Document, f32)> {
+        let documents = self.read_documents();
+        let mut scored: Vec<(&Document, f32)> = documents
+            .values()
+            .filter(|doc| filter(doc))
+            .filter_map(|doc| {
+                let embedding = doc.embedding.as_ref()?;
+                (embedding.len() == query.len()).then(|| (doc, similarity.score(query, embedding)))
+            })
+            .filter(|(_, score)| *score >= threshold)
+            .collect();
+
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored
+            .into_iter()
+            .take(top_k)
+            .map(|(doc, score)| (doc.clone(), score))
+            .collect()
+    }
+
+    fn read_documents(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, Document>> {
+        self.documents.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write_documents(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, Document>> {
+        self.documents.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
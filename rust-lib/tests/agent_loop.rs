@@ -0,0 +1,463 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use shared_handlers::agent::{run_agent_loop, stream_agent_loop, AgentEvent, AgentOutcome};
+use shared_handlers::ai::{
+    ChatChoice, ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatDelta,
+    ChatMessage, ChunkChoice, FinishReason, FunctionCall, FunctionCallDelta, Role, ToolCall,
+    ToolCallDelta, Usage,
+};
+use shared_handlers::mcp::{McpRegistry, McpServer, McpServerStatus, McpTool};
+use shared_handlers::mcp_client::StdioTransport;
+use shared_handlers::provider::{ChatProvider, MockProvider, ProviderError};
+use shared_handlers::stream::ChunkStream;
+use shared_handlers::{config::AppConfig, state::AppState};
+use std::sync::{Arc, Mutex};
+
+// Calls `echo` on its first `tool_turns` turns, then answers; records every request it sees.
+// `also_calls` names another tool called alongside `echo`.
+struct EchoingProvider {
+    tool_turns: usize,
+    also_calls: Option<&'static str>,
+    requests: Mutex<Vec<ChatCompletionRequest>>,
+}
+
+impl EchoingProvider {
+    fn new(tool_turns: usize) -> Self {
+        Self {
+            tool_turns,
+            also_calls: None,
+            requests: Mutex::default(),
+        }
+    }
+
+    fn also_calling(mut self, tool: &'static str) -> Self {
+        self.also_calls = Some(tool);
+        self
+    }
+}
+
+#[async_trait]
+impl ChatProvider for EchoingProvider {
+    fn name(&self) -> &str {
+        "echoing"
+    }
+
+    async fn complete(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let mut requests = self.requests.lock().unwrap();
+        let turn = requests.len();
+        requests.push(request.clone());
+
+        let (message, finish_reason) = if turn < self.tool_turns {
+            let call = ToolCall {
+                id: format!("call_{}", turn),
+                r#type: "function".to_string(),
+                function: FunctionCall {
+                    name: "echo".to_string(),
+                    arguments: json!({ "message": format!("ping {}", turn) }).to_string(),
+                },
+            };
+            let mut calls = vec![call];
+            if let Some(tool) = self.also_calls {
+                calls.push(ToolCall {
+                    id: format!("call_{}_{}", turn, tool),
+                    r#type: "function".to_string(),
+                    function: FunctionCall {
+                        name: tool.to_string(),
+                        arguments: "{}".to_string(),
+                    },
+                });
+            }
+            let message = ChatMessage {
+                role: Role::Assistant,
+                content: None,
+                tool_calls: Some(calls),
+                tool_call_id: None,
+                name: None,
+            };
+            (message, FinishReason::ToolCalls)
+        } else {
+            let message = ChatMessage {
+                role: Role::Assistant,
+                content: Some("All done".into()),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            };
+            (message, FinishReason::Stop)
+        };
+
+        Ok(ChatCompletionResponse {
+            id: "chatcmpl-echoing".to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: request.model.clone(),
+            choices: vec![ChatChoice {
+                index: 0,
+                message,
+                finish_reason,
+                logprobs: None,
+            }],
+            usage: Usage {
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                total_tokens: 15,
+            },
+            agent_trace: None,
+            served_by: None,
+        })
+    }
+}
+
+// Registry whose tools have no live connection, so calls get the mock fallback result
+fn mock_registry() -> McpRegistry {
+    let mut registry = McpRegistry::new();
+    let tool = |name: &str, property: &str| McpTool {
+        name: name.to_string(),
+        description: format!("Mock {}", name),
+        schema: json!({ "type": "object", "properties": { property: { "type": "string" } } }),
+        server: "mock".to_string(),
+    };
+    registry.register_server(McpServer {
+        name: "mock".to_string(),
+        description: "Mock tools".to_string(),
+        version: "1.0.0".to_string(),
+        tools: vec![tool("search_web", "query"), tool("echo", "message")],
+        status: McpServerStatus::Active,
+    });
+    registry
+}
+
+async fn spawn_app(provider: Arc<dyn ChatProvider>) -> String {
+    let state = AppState::new(AppConfig::default(), provider, mock_registry());
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/chat/completions", addr)
+}
+
+#[tokio::test]
+async fn server_tool_execution_answers_after_running_mcp_tools() {
+    let url = spawn_app(Arc::new(MockProvider::new())).await;
+    let response = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({
+            "model": "gpt-4",
+            "messages": [{ "role": "user", "content": "search for rust" }],
+            "tool_execution": "server"
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let completion: Value = response.json().await.unwrap();
+
+    assert_eq!(completion["choices"][0]["finish_reason"], "stop");
+    assert!(completion["choices"][0]["message"]["tool_calls"].is_null());
+    let trace = completion["agent_trace"].as_array().unwrap();
+    assert_eq!(trace.len(), 1);
+    assert_eq!(trace[0]["iteration"], 1);
+    let call = &trace[0]["assistant"]["tool_calls"][0];
+    assert_eq!(call["function"]["name"], "search_web");
+    let result = &trace[0]["tool_results"][0];
+    assert_eq!(result["role"], "tool");
+    assert_eq!(result["tool_call_id"], call["id"]);
+    assert!(result["content"]
+        .as_str()
+        .unwrap()
+        .contains("Mock result from MCP server 'mock'"));
+}
+
+#[tokio::test]
+async fn tool_results_are_fed_back_and_usage_is_summed() {
+    let mut registry = McpRegistry::new();
+    let transport = StdioTransport {
+        command: env!("CARGO_BIN_EXE_mcp_echo_server").to_string(),
+        ..Default::default()
+    };
+    registry
+        .connect_stdio_server("echo", "Echo test server", &transport)
+        .await
+        .unwrap();
+    let provider = EchoingProvider::new(2);
+    let request: ChatCompletionRequest = serde_json::from_value(json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": "Echo twice" }]
+    }))
+    .unwrap();
+
+    let response = run_agent_loop(&provider, &registry, request, &[], 5)
+        .await
+        .unwrap();
+
+    let trace = response.agent_trace.as_ref().unwrap();
+    assert_eq!(trace.len(), 2);
+    for (i, step) in trace.iter().enumerate() {
+        assert_eq!(step.iteration, i as u32 + 1);
+        let call = &step.assistant.tool_calls.as_ref().unwrap()[0];
+        let result = &step.tool_results[0];
+        assert_eq!(result.role, Role::Tool);
+        assert_eq!(result.tool_call_id.as_deref(), Some(call.id.as_str()));
+        assert_eq!(result.name.as_deref(), Some("echo"));
+        assert_eq!(
+            result.content.as_ref().unwrap().to_text(),
+            format!("ping {}", i)
+        );
+    }
+    assert_eq!(response.choices[0].finish_reason, FinishReason::Stop);
+    assert_eq!(response.usage.prompt_tokens, 30);
+    assert_eq!(response.usage.completion_tokens, 15);
+    assert_eq!(response.usage.total_tokens, 45);
+
+    // The last turn saw both rounds: assistant call followed by its tool result
+    let requests = provider.requests.lock().unwrap();
+    let roles: Vec<Role> = requests[2].messages.iter().map(|m| m.role).collect();
+    assert_eq!(
+        roles,
+        [
+            Role::User,
+            Role::Assistant,
+            Role::Tool,
+            Role::Assistant,
+            Role::Tool
+        ]
+    );
+    assert_eq!(
+        requests[2].messages[2].tool_call_id.as_deref(),
+        Some("call_0")
+    );
+    assert_eq!(
+        requests[2].messages[4].tool_call_id.as_deref(),
+        Some("call_1")
+    );
+}
+
+#[tokio::test]
+async fn the_loop_stops_at_max_tool_iterations_with_pending_calls() {
+    let provider = Arc::new(EchoingProvider::new(usize::MAX));
+    let url = spawn_app(provider.clone()).await;
+    let completion: Value = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({
+            "model": "gpt-4",
+            "messages": [{ "role": "user", "content": "Keep echoing" }],
+            "tool_execution": "server",
+            "max_tool_iterations": 2
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(completion["agent_trace"].as_array().unwrap().len(), 2);
+    let choice = &completion["choices"][0];
+    assert_eq!(choice["finish_reason"], "tool_calls");
+    assert_eq!(choice["message"]["tool_calls"][0]["id"], "call_2");
+    assert_eq!(completion["usage"]["total_tokens"], 45);
+    assert_eq!(provider.requests.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn client_tool_calls_end_the_loop_after_mcp_calls_run() {
+    let provider = EchoingProvider::new(usize::MAX).also_calling("read_file");
+    let request: ChatCompletionRequest = serde_json::from_value(json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": "Echo and read" }]
+    }))
+    .unwrap();
+    let client_tools = ["read_file".to_string()];
+
+    let response = run_agent_loop(
+        &provider,
+        &mock_registry(),
+        request.clone(),
+        &client_tools,
+        5,
+    )
+    .await
+    .unwrap();
+    assert_eq!(provider.requests.lock().unwrap().len(), 1);
+    let choice = &response.choices[0];
+    assert_eq!(choice.finish_reason, FinishReason::ToolCalls);
+    let pending = choice.message.tool_calls.as_ref().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].function.name, "read_file");
+    // The MCP call of the same turn still ran
+    let trace = response.agent_trace.as_ref().unwrap();
+    assert_eq!(trace.len(), 1);
+    assert_eq!(trace[0].tool_results.len(), 1);
+    assert_eq!(trace[0].tool_results[0].name.as_deref(), Some("echo"));
+
+    // A client tool shadows the MCP tool of the same name
+    let provider = EchoingProvider::new(usize::MAX);
+    let client_tools = ["echo".to_string()];
+    let response = run_agent_loop(&provider, &mock_registry(), request, &client_tools, 5)
+        .await
+        .unwrap();
+    assert_eq!(provider.requests.lock().unwrap().len(), 1);
+    assert_eq!(response.choices[0].finish_reason, FinishReason::ToolCalls);
+    assert!(response.agent_trace.as_ref().unwrap().is_empty());
+
+    // Tools sent with the request are the client's
+    let provider = Arc::new(EchoingProvider::new(usize::MAX).also_calling("read_file"));
+    let url = spawn_app(provider.clone()).await;
+    let completion: Value = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({
+            "model": "gpt-4",
+            "messages": [{ "role": "user", "content": "Echo and read" }],
+            "tools": [{
+                "type": "function",
+                "function": { "name": "read_file", "description": "Read a file", "parameters": { "type": "object" } }
+            }],
+            "tool_execution": "server"
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let choice = &completion["choices"][0];
+    assert_eq!(choice["finish_reason"], "tool_calls");
+    assert_eq!(
+        choice["message"]["tool_calls"][0]["function"]["name"],
+        "read_file"
+    );
+    assert_eq!(
+        completion["agent_trace"][0]["tool_results"][0]["name"],
+        "echo"
+    );
+    assert_eq!(provider.requests.lock().unwrap().len(), 1);
+}
+
+// Streams two parallel tool calls whose deltas interleave, with the second call starting first
+struct InterleavingProvider;
+
+fn delta(index: u32, id: Option<&str>, name: Option<&str>, arguments: &str) -> ChatCompletionChunk {
+    ChatCompletionChunk {
+        id: "chatcmpl-interleaving".to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created: 0,
+        model: "gpt-4".to_string(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta: ChatDelta {
+                role: None,
+                content: None,
+                tool_calls: Some(vec![ToolCallDelta {
+                    index,
+                    id: id.map(str::to_string),
+                    r#type: id.map(|_| "function".to_string()),
+                    function: Some(FunctionCallDelta {
+                        name: name.map(str::to_string),
+                        arguments: Some(arguments.to_string()),
+                    }),
+                }]),
+            },
+            finish_reason: None,
+            logprobs: None,
+        }],
+        usage: None,
+        served_by: None,
+    }
+}
+
+#[async_trait]
+impl ChatProvider for InterleavingProvider {
+    fn name(&self) -> &str {
+        "interleaving"
+    }
+
+    async fn complete(
+        &self,
+        _request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        Err(ProviderError::InvalidResponse("streaming only".to_string()))
+    }
+
+    async fn complete_stream(
+        &self,
+        _request: &ChatCompletionRequest,
+    ) -> Result<ChunkStream, ProviderError> {
+        let mut finish = delta(0, None, None, "");
+        finish.choices[0].delta = ChatDelta::default();
+        finish.choices[0].finish_reason = Some(FinishReason::ToolCalls);
+        let chunks = vec![
+            delta(1, Some("call_b"), Some("search_web"), "{\"query\":"),
+            delta(0, Some("call_a"), Some("echo"), ""),
+            delta(1, None, None, "\"rust\"}"),
+            delta(0, None, None, "{\"message\":"),
+            delta(0, None, None, "\"hi\"}"),
+            finish,
+        ];
+        Ok(futures::stream::iter(chunks.into_iter().map(Ok)).boxed())
+    }
+}
+
+#[tokio::test]
+async fn interleaved_tool_call_deltas_are_assembled_by_index() {
+    let mut request: ChatCompletionRequest = serde_json::from_value(json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": "Search and echo" }]
+    }))
+    .unwrap();
+    let mut started = Vec::new();
+    let mut arguments: Vec<(String, String)> = Vec::new();
+    let mut emit = |event: AgentEvent| {
+        match event {
+            AgentEvent::ToolCallStarted { id, name } => started.push((id, name)),
+            AgentEvent::ToolCallArgs { id, delta } => arguments.push((id, delta)),
+            _ => {}
+        }
+        true
+    };
+
+    // Neither tool is on the server, so the calls are handed back to the client
+    let outcome = stream_agent_loop(
+        &InterleavingProvider,
+        &McpRegistry::new(),
+        &mut request,
+        &[],
+        5,
+        &mut emit,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(outcome, AgentOutcome::AwaitingClientTools));
+
+    assert_eq!(
+        started,
+        [
+            ("call_b".to_string(), "search_web".to_string()),
+            ("call_a".to_string(), "echo".to_string())
+        ]
+    );
+    let ids: Vec<&str> = arguments.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, ["call_b", "call_b", "call_a", "call_a"]);
+
+    let calls = request.messages.last().unwrap().tool_calls.clone().unwrap();
+    let calls: Vec<(&str, &str, &str)> = calls
+        .iter()
+        .map(|call| {
+            (
+                call.id.as_str(),
+                call.function.name.as_str(),
+                call.function.arguments.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        calls,
+        [
+            ("call_a", "echo", r#"{"message":"hi"}"#),
+            ("call_b", "search_web", r#"{"query":"rust"}"#)
+        ]
+    );
+}
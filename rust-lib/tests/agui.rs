@@ -0,0 +1,204 @@
+use serde_json::{json, Value};
+use shared_handlers::mcp::{McpRegistry, McpServer, McpServerStatus, McpTool};
+use shared_handlers::provider::{ChatProvider, MockProvider, OpenAiConfig, OpenAiProvider};
+use shared_handlers::{config::AppConfig, state::AppState};
+use std::sync::Arc;
+
+async fn spawn_app(provider: Arc<dyn ChatProvider>) -> String {
+    let mut registry = McpRegistry::new();
+    registry.register_server(McpServer {
+        name: "web".to_string(),
+        description: "Web search".to_string(),
+        version: "1.0.0".to_string(),
+        tools: vec![McpTool {
+            name: "search_web".to_string(),
+            description: "Search the web".to_string(),
+            schema: json!({
+                "type": "object",
+                "properties": { "query": { "type": "string" }, "max_results": { "type": "integer" } },
+                "required": ["query"]
+            }),
+            server: "web".to_string(),
+        }],
+        status: McpServerStatus::Active,
+    });
+
+    let app =
+        shared_handlers::openai_router(AppState::new(AppConfig::default(), provider, registry));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/agui", addr)
+}
+
+fn run_input(content: &str) -> Value {
+    json!({
+        "threadId": "thread-1",
+        "runId": "run-1",
+        "state": { "draft": "hello" },
+        "messages": [{ "id": "m1", "role": "user", "content": content }],
+        "tools": [],
+        "context": [{ "description": "Locale", "value": "en-GB" }],
+        "forwardedProps": { "model": "gpt-4" }
+    })
+}
+
+async fn run(url: &str, input: &Value) -> Vec<Value> {
+    let response = reqwest::Client::new()
+        .post(url)
+        .json(input)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    response
+        .text()
+        .await
+        .unwrap()
+        .split("\n\n")
+        .filter_map(|event| event.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect()
+}
+
+fn types(events: &[Value]) -> Vec<&str> {
+    let mut types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    types.dedup();
+    types
+}
+
+#[tokio::test]
+async fn text_runs_stream_lifecycle_message_and_state_events() {
+    let url = spawn_app(Arc::new(MockProvider::new())).await;
+    let events = run(&url, &run_input("Hello there")).await;
+
+    assert_eq!(
+        types(&events),
+        [
+            "RUN_STARTED",
+            "STATE_SNAPSHOT",
+            "TEXT_MESSAGE_START",
+            "TEXT_MESSAGE_CONTENT",
+            "TEXT_MESSAGE_END",
+            "STATE_DELTA",
+            "RUN_FINISHED"
+        ]
+    );
+    assert_eq!(events[0]["threadId"], "thread-1");
+    assert_eq!(events[0]["runId"], "run-1");
+    assert_eq!(events[1]["snapshot"]["draft"], "hello");
+    assert_eq!(events[1]["snapshot"]["agent"]["status"], "running");
+    assert_eq!(events[1]["snapshot"]["agent"]["served_by"], Value::Null);
+
+    let message_id = &events[2]["messageId"];
+    let text: String = events
+        .iter()
+        .filter(|e| e["type"] == "TEXT_MESSAGE_CONTENT")
+        .inspect(|e| assert_eq!(&e["messageId"], message_id))
+        .map(|e| e["delta"].as_str().unwrap())
+        .collect();
+    // Two messages: the user's and the context from the client
+    assert!(
+        text.contains("You sent 2 messages to model 'gpt-4'"),
+        "{}",
+        text
+    );
+
+    let status = &events[events.len() - 2]["delta"][0];
+    assert_eq!(status["path"], "/agent/status");
+    assert_eq!(status["value"], "finished");
+    let served_by = &events[events.len() - 2]["delta"][1];
+    assert_eq!(served_by["path"], "/agent/served_by");
+    assert_eq!(served_by["value"], "mock");
+}
+
+#[tokio::test]
+async fn mcp_tools_run_on_the_server_within_the_run() {
+    let url = spawn_app(Arc::new(MockProvider::new())).await;
+    let events = run(&url, &run_input("search for rust")).await;
+
+    assert_eq!(
+        types(&events),
+        [
+            "RUN_STARTED",
+            "STATE_SNAPSHOT",
+            "TOOL_CALL_START",
+            "TOOL_CALL_ARGS",
+            "TOOL_CALL_END",
+            "TOOL_CALL_RESULT",
+            "STATE_DELTA",
+            "TEXT_MESSAGE_START",
+            "TEXT_MESSAGE_CONTENT",
+            "TEXT_MESSAGE_END",
+            "STATE_DELTA",
+            "RUN_FINISHED"
+        ]
+    );
+    let start = &events[2];
+    assert_eq!(start["toolCallName"], "search_web");
+    let args: Value = serde_json::from_str(events[3]["delta"].as_str().unwrap()).unwrap();
+    assert_eq!(args["query"], "search for rust");
+    let result = &events[5];
+    assert_eq!(result["toolCallId"], start["toolCallId"]);
+    assert!(result["content"]
+        .as_str()
+        .unwrap()
+        .contains("Mock result from MCP server 'web'"));
+    assert_eq!(events[6]["delta"][0]["path"], "/agent/steps/-");
+    assert_eq!(
+        events[6]["delta"][0]["value"]["tools"],
+        json!(["search_web"])
+    );
+}
+
+#[tokio::test]
+async fn frontend_tool_calls_end_the_run_for_the_client() {
+    let url = spawn_app(Arc::new(MockProvider::new())).await;
+    let mut input = run_input("read the file");
+    input["tools"] = json!([{
+        "name": "read_file",
+        "description": "Read a file the user has open",
+        "parameters": { "type": "object", "properties": { "path": { "type": "string" } } }
+    }]);
+    let events = run(&url, &input).await;
+
+    assert_eq!(
+        types(&events),
+        [
+            "RUN_STARTED",
+            "STATE_SNAPSHOT",
+            "TOOL_CALL_START",
+            "TOOL_CALL_ARGS",
+            "TOOL_CALL_END",
+            "STATE_DELTA",
+            "RUN_FINISHED"
+        ]
+    );
+    assert_eq!(events[2]["toolCallName"], "read_file");
+    assert_eq!(events[5]["delta"][0]["value"], "awaiting_tools");
+}
+
+#[tokio::test]
+async fn failures_become_run_errors_and_bad_input_is_rejected() {
+    let unreachable = OpenAiProvider::new(OpenAiConfig::new("http://127.0.0.1:1/v1"));
+    let url = spawn_app(Arc::new(unreachable)).await;
+    let events = run(&url, &run_input("Hello there")).await;
+
+    let last = events.last().unwrap();
+    assert_eq!(last["type"], "RUN_ERROR");
+    assert_eq!(last["code"], "upstream_error");
+    assert!(!events.iter().any(|e| e["type"] == "RUN_FINISHED"));
+
+    let mut input = run_input("Hello there");
+    input["state"] = json!([1, 2]);
+    let response = reqwest::Client::new()
+        .post(&url)
+        .json(&input)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["param"], "state");
+}
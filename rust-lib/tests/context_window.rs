@@ -0,0 +1,228 @@
+use serde_json::{json, Value};
+use shared_handlers::ai::{ChatCompletionRequest, Role};
+use shared_handlers::context::{
+    apply_context_strategy, enforce_context_window, ContextOverflow, ContextStrategy,
+};
+use shared_handlers::error::AiError;
+use shared_handlers::models::ModelCatalog;
+use shared_handlers::provider::{ChatProvider, MockProvider};
+use shared_handlers::tokenizer::Encoding;
+use shared_handlers::{config::AppConfig, state::AppState};
+use std::sync::Arc;
+
+fn request(messages: Value) -> ChatCompletionRequest {
+    serde_json::from_value(json!({ "model": "gpt-4", "messages": messages })).unwrap()
+}
+
+#[test]
+fn counts_with_the_model_vocabulary() {
+    assert_eq!(Encoding::for_model("gpt-4"), Encoding::Cl100kBase);
+    assert_eq!(Encoding::for_model("gpt-4o-mini"), Encoding::O200kBase);
+    assert_eq!(Encoding::for_model("llama3:8b"), Encoding::Cl100kBase);
+
+    assert_eq!(Encoding::Cl100kBase.count("hello world"), 2);
+    assert_eq!(Encoding::Cl100kBase.count("<|endoftext|>"), 7);
+
+    // 3 framing tokens per message + role + content, plus 3 to prime the reply
+    let messages = request(json!([{ "role": "user", "content": "hello world" }])).messages;
+    assert_eq!(
+        Encoding::Cl100kBase.count_messages(&messages),
+        3 + 1 + 2 + 3
+    );
+}
+
+#[tokio::test]
+async fn mock_usage_is_counted_not_canned() {
+    let request = request(json!([{ "role": "user", "content": "hello world" }]));
+    let response = MockProvider::new().complete(&request).await.unwrap();
+
+    let encoding = Encoding::for_model("gpt-4");
+    assert_eq!(response.usage.prompt_tokens, 9);
+    assert_eq!(
+        response.usage.completion_tokens,
+        encoding.count_completion(&response.choices[0].message)
+    );
+    assert_eq!(
+        response.usage.total_tokens,
+        response.usage.prompt_tokens + response.usage.completion_tokens
+    );
+}
+
+#[test]
+fn over_long_prompts_are_rejected() {
+    let catalog = ModelCatalog::default();
+    let gpt4 = catalog.get("gpt-4").unwrap();
+    let long = "lorem ipsum ".repeat(5_000);
+
+    let mut fits = request(json!([{ "role": "user", "content": "hi" }]));
+    assert!(enforce_context_window(&mut fits, gpt4, ContextOverflow::Reject).unwrap() < 20);
+
+    // Requested completion tokens count against the window too
+    fits.max_tokens = Some(8_190);
+    let error = enforce_context_window(&mut fits, gpt4, ContextOverflow::Reject).unwrap_err();
+    assert!(
+        matches!(error, AiError::InvalidRequest { code: Some(ref code), .. } if code == "context_length_exceeded")
+    );
+
+    let mut too_long = request(json!([{ "role": "user", "content": long }]));
+    assert!(enforce_context_window(&mut too_long, gpt4, ContextOverflow::Reject).is_err());
+    // Nothing to trim when the latest user turn alone is too long
+    assert!(enforce_context_window(&mut too_long, gpt4, ContextOverflow::Trim).is_err());
+}
+
+#[test]
+fn trimming_drops_oldest_turns_and_keeps_tool_pairs_together() {
+    let catalog = ModelCatalog::default();
+    let gpt4 = catalog.get("gpt-4").unwrap();
+    let long = "lorem ipsum ".repeat(5_000);
+
+    let mut request = request(json!([
+        { "role": "system", "content": "You are helpful." },
+        { "role": "user", "content": "Search for lorem ipsum" },
+        {
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{ "id": "call_1", "type": "function", "function": { "name": "search_web", "arguments": "{}" } }]
+        },
+        { "role": "tool", "tool_call_id": "call_1", "content": long },
+        { "role": "assistant", "content": "Here is what I found." },
+        { "role": "user", "content": "Thanks, summarize it" }
+    ]));
+
+    let tokens = enforce_context_window(&mut request, gpt4, ContextOverflow::Trim).unwrap();
+    assert!(tokens <= gpt4.context_window);
+
+    let roles: Vec<Role> = request.messages.iter().map(|m| m.role).collect();
+    assert_eq!(roles, [Role::System, Role::Assistant, Role::User]);
+    assert!(request.messages.iter().all(|m| m.tool_calls.is_none()));
+}
+
+// Three turns, the middle one with a tool round trip
+fn conversation() -> ChatCompletionRequest {
+    request(json!([
+        { "role": "system", "content": "You are helpful." },
+        { "role": "user", "content": "First question" },
+        { "role": "assistant", "content": "First answer" },
+        { "role": "user", "content": "Search for lorem ipsum" },
+        {
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{ "id": "call_1", "type": "function", "function": { "name": "search_web", "arguments": "{}" } }]
+        },
+        { "role": "tool", "tool_call_id": "call_1", "content": "lorem ipsum ".repeat(200) },
+        { "role": "assistant", "content": "Here is what I found." },
+        { "role": "user", "content": "Thanks, summarize it" }
+    ]))
+}
+
+fn roles(request: &ChatCompletionRequest) -> Vec<Role> {
+    request.messages.iter().map(|m| m.role).collect()
+}
+
+#[test]
+fn strategies_parse_from_config_strings() {
+    assert_eq!("full".parse(), Ok(ContextStrategy::Full));
+    assert_eq!(
+        "last_turns:10".parse(),
+        Ok(ContextStrategy::LastTurns { turns: 10 })
+    );
+    assert_eq!(
+        "sliding_window".parse(),
+        Ok(ContextStrategy::SlidingWindow { max_tokens: None })
+    );
+    assert_eq!(
+        "summarize:3".parse(),
+        Ok(ContextStrategy::Summarize { keep_turns: 3 })
+    );
+    assert!("last_turns".parse::<ContextStrategy>().is_err());
+    assert!("last_turns:0".parse::<ContextStrategy>().is_err());
+    assert!("everything".parse::<ContextStrategy>().is_err());
+}
+
+#[tokio::test]
+async fn last_turns_keeps_system_messages_and_recent_turns() {
+    let gpt4 = ModelCatalog::default().get("gpt-4").cloned().unwrap();
+    let mut request = conversation();
+
+    let strategy = ContextStrategy::LastTurns { turns: 2 };
+    let report = apply_context_strategy(&mut request, &gpt4, &strategy, &MockProvider::new()).await;
+
+    assert_eq!(report.to_string(), "last_turns; dropped=2");
+    assert_eq!(
+        roles(&request),
+        [
+            Role::System,
+            Role::User,
+            Role::Assistant,
+            Role::Tool,
+            Role::Assistant,
+            Role::User
+        ]
+    );
+}
+
+#[tokio::test]
+async fn sliding_window_never_splits_tool_results_from_their_call() {
+    let gpt4 = ModelCatalog::default().get("gpt-4").cloned().unwrap();
+    let mut request = conversation();
+
+    // Room for the final answer but not for the long tool result
+    let strategy = ContextStrategy::SlidingWindow {
+        max_tokens: Some(100),
+    };
+    let report = apply_context_strategy(&mut request, &gpt4, &strategy, &MockProvider::new()).await;
+
+    assert_eq!(report.to_string(), "sliding_window; dropped=5");
+    assert_eq!(roles(&request), [Role::System, Role::Assistant, Role::User]);
+    request.validate().unwrap();
+}
+
+#[tokio::test]
+async fn summarize_replaces_older_turns_with_a_provider_summary() {
+    let gpt4 = ModelCatalog::default().get("gpt-4").cloned().unwrap();
+    let mut request = conversation();
+
+    let strategy = ContextStrategy::Summarize { keep_turns: 1 };
+    let report = apply_context_strategy(&mut request, &gpt4, &strategy, &MockProvider::new()).await;
+
+    assert_eq!(report.to_string(), "summarize; summarized=6");
+    assert_eq!(roles(&request), [Role::System, Role::System, Role::User]);
+    assert_eq!(
+        request.messages[1].name.as_deref(),
+        Some("conversation_summary")
+    );
+    assert!(request.messages[1]
+        .content
+        .as_ref()
+        .unwrap()
+        .to_text()
+        .starts_with("Summary of the earlier conversation:"));
+}
+
+#[tokio::test]
+async fn chosen_strategy_is_reported_in_a_response_header() {
+    let state = AppState::new(
+        AppConfig::default(),
+        Arc::new(MockProvider::new()),
+        Default::default(),
+    );
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let mut body = serde_json::to_value(conversation()).unwrap();
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/chat/completions", addr);
+
+    let response = client.post(&url).json(&body).send().await.unwrap();
+    assert_eq!(response.headers()["x-context-strategy"], "full");
+
+    body["context_strategy"] = json!({ "type": "last_turns", "turns": 1 });
+    let response = client.post(&url).json(&body).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["x-context-strategy"],
+        "last_turns; dropped=6"
+    );
+}
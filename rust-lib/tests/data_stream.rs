@@ -0,0 +1,220 @@
+use serde_json::{json, Value};
+use shared_handlers::mcp::{McpRegistry, McpServer, McpServerStatus, McpTool};
+use shared_handlers::provider::MockProvider;
+use shared_handlers::{config::AppConfig, state::AppState};
+use std::sync::Arc;
+
+async fn spawn_app() -> String {
+    let mut registry = McpRegistry::new();
+    registry.register_server(McpServer {
+        name: "web".to_string(),
+        description: "Web search".to_string(),
+        version: "1.0.0".to_string(),
+        tools: vec![McpTool {
+            name: "search_web".to_string(),
+            description: "Search the web".to_string(),
+            schema: json!({
+                "type": "object",
+                "properties": { "query": { "type": "string" } },
+                "required": ["query"]
+            }),
+            server: "web".to_string(),
+        }],
+        status: McpServerStatus::Active,
+    });
+
+    let state = AppState::new(
+        AppConfig::default(),
+        Arc::new(MockProvider::new()),
+        registry,
+    );
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+// (code, value) for every line of the stream
+async fn parts(response: reqwest::Response) -> Vec<(String, Value)> {
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["x-vercel-ai-data-stream"], "v1");
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/plain; charset=utf-8"
+    );
+    response
+        .text()
+        .await
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let (code, value) = line.split_once(':').unwrap();
+            (code.to_string(), serde_json::from_str(value).unwrap())
+        })
+        .collect()
+}
+
+fn codes(parts: &[(String, Value)]) -> Vec<&str> {
+    let mut codes: Vec<&str> = parts.iter().map(|(code, _)| code.as_str()).collect();
+    codes.dedup();
+    codes
+}
+
+fn find<'a>(parts: &'a [(String, Value)], code: &str) -> &'a Value {
+    &parts.iter().find(|(c, _)| c == code).unwrap().1
+}
+
+#[tokio::test]
+async fn text_replies_stream_text_parts_and_a_finish_part() {
+    let base = spawn_app().await;
+    let body = json!({
+        "system": "Be brief",
+        "messages": [{ "role": "user", "content": "Hello there" }]
+    });
+    let response = reqwest::Client::new()
+        .post(format!("{}/chat/data-stream", base))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let parts = parts(response).await;
+
+    assert_eq!(codes(&parts), ["f", "0", "8", "e", "d"]);
+    let text: String = parts
+        .iter()
+        .filter(|(code, _)| code == "0")
+        .map(|(_, value)| value.as_str().unwrap())
+        .collect();
+    // The top-level system prompt becomes a message; a missing model falls back to the
+    // "default" alias
+    assert!(
+        text.contains("You sent 2 messages to model 'gpt-4o-mini'"),
+        "{}",
+        text
+    );
+
+    let finish = find(&parts, "d");
+    assert_eq!(finish["finishReason"], "stop");
+    assert!(finish["usage"]["promptTokens"].as_u64().unwrap() > 0);
+    assert_eq!(find(&parts, "e")["isContinued"], false);
+    // Without a fallback chain the provider itself served the step
+    assert_eq!(find(&parts, "8"), &json!([{ "served_by": "mock" }]));
+}
+
+#[tokio::test]
+async fn mcp_tool_results_are_streamed_inline() {
+    let base = spawn_app().await;
+    let body = json!({ "messages": [{ "role": "user", "content": "search for rust" }] });
+    let response = reqwest::Client::new()
+        .post(format!("{}/chat/data-stream", base))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let parts = parts(response).await;
+
+    assert_eq!(
+        codes(&parts),
+        ["f", "b", "c", "9", "a", "8", "e", "f", "0", "8", "e", "d"]
+    );
+    let call = find(&parts, "9");
+    assert_eq!(call["toolName"], "search_web");
+    assert_eq!(call["args"]["query"], "search for rust");
+    let result = find(&parts, "a");
+    assert_eq!(result["toolCallId"], call["toolCallId"]);
+    assert!(result["result"]
+        .to_string()
+        .contains("Mock result from MCP server 'web'"));
+
+    let step = find(&parts, "e");
+    assert_eq!(step["finishReason"], "tool-calls");
+    assert_eq!(step["isContinued"], true);
+    assert_eq!(find(&parts, "d")["finishReason"], "stop");
+}
+
+#[tokio::test]
+async fn chat_completions_switches_protocol_on_the_header() {
+    let base = spawn_app().await;
+    let body = json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": "read the file" }],
+        "tools": [{
+            "type": "function",
+            "function": {
+                "name": "read_file",
+                "description": "Read a file",
+                "parameters": { "type": "object", "properties": { "path": { "type": "string" } } }
+            }
+        }]
+    });
+    let response = reqwest::Client::new()
+        .post(format!("{}/chat/completions", base))
+        .header("x-stream-protocol", "data")
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let parts = parts(response).await;
+
+    // Client tools are left for the frontend to run
+    assert_eq!(codes(&parts), ["f", "b", "c", "9", "8", "e", "d"]);
+    assert_eq!(find(&parts, "9")["toolName"], "read_file");
+    assert_eq!(find(&parts, "e")["isContinued"], false);
+    assert_eq!(find(&parts, "d")["finishReason"], "tool-calls");
+}
+
+#[tokio::test]
+async fn use_chat_bodies_with_tool_invocations_are_accepted() {
+    let base = spawn_app().await;
+    let body = json!({
+        "id": "chat-1",
+        "messages": [
+            { "id": "m1", "role": "user", "content": "", "parts": [{ "type": "text", "text": "read the file" }] },
+            {
+                "id": "m2",
+                "role": "assistant",
+                "content": "",
+                "toolInvocations": [{
+                    "state": "result",
+                    "toolCallId": "call_1",
+                    "toolName": "read_file",
+                    "args": { "path": "notes.txt" },
+                    "result": { "text": "hello" }
+                }]
+            }
+        ],
+        "tools": {
+            "read_file": {
+                "description": "Read a file",
+                "parameters": { "type": "object", "properties": { "path": { "type": "string" } } }
+            }
+        }
+    });
+    let response = reqwest::Client::new()
+        .post(format!("{}/chat/data-stream", base))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let parts = parts(response).await;
+
+    // The tool result answers the call, so the model replies in text
+    assert_eq!(codes(&parts), ["f", "0", "8", "e", "d"]);
+    let text: String = parts
+        .iter()
+        .filter(|(code, _)| code == "0")
+        .map(|(_, value)| value.as_str().unwrap())
+        .collect();
+    assert!(text.contains("You sent 3 messages"), "{}", text);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/chat/data-stream", base))
+        .json(&json!({ "messages": [{ "role": "wizard", "content": "hi" }] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["param"], "messages[0].role");
+}
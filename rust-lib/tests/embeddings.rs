@@ -0,0 +1,141 @@
+use base64::Engine;
+use serde_json::{json, Value};
+use shared_handlers::embedding::DEFAULT_HASHING_DIMENSIONS;
+use shared_handlers::provider::MockProvider;
+use shared_handlers::tokenizer::Encoding;
+use shared_handlers::{config::AppConfig, state::AppState};
+use std::sync::Arc;
+
+async fn spawn_app() -> String {
+    let state = AppState::new(
+        AppConfig::default(),
+        Arc::new(MockProvider::new()),
+        Default::default(),
+    );
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/embeddings", addr)
+}
+
+async fn embed(url: &str, body: Value) -> (u16, Value) {
+    let response = reqwest::Client::new()
+        .post(url)
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+fn floats(embedding: &Value) -> Vec<f32> {
+    embedding
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x.as_f64().unwrap() as f32)
+        .collect()
+}
+
+fn norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+#[tokio::test]
+async fn string_input_returns_one_unit_vector_with_token_usage() {
+    let url = spawn_app().await;
+    let (status, response) = embed(
+        &url,
+        json!({ "model": "text-embedding-3-small", "input": "The quick brown fox" }),
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    assert_eq!(response["object"], "list");
+    assert_eq!(response["data"].as_array().unwrap().len(), 1);
+    assert_eq!(response["data"][0]["object"], "embedding");
+    assert_eq!(response["data"][0]["index"], 0);
+    let vector = floats(&response["data"][0]["embedding"]);
+    assert_eq!(vector.len(), DEFAULT_HASHING_DIMENSIONS);
+    assert!((norm(&vector) - 1.0).abs() < 1e-4);
+
+    let model = response["model"].as_str().unwrap();
+    let tokens = Encoding::for_model(model).count("The quick brown fox");
+    assert_eq!(tokens, 4);
+    assert_eq!(response["usage"]["prompt_tokens"], tokens);
+    assert_eq!(response["usage"]["total_tokens"], tokens);
+}
+
+#[tokio::test]
+async fn array_input_returns_an_embedding_per_item() {
+    let url = spawn_app().await;
+    let (status, response) = embed(
+        &url,
+        json!({ "model": "text-embedding-3-small", "input": ["rust", "python", "rust"] }),
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    let data = response["data"].as_array().unwrap();
+    let indices: Vec<u64> = data.iter().map(|d| d["index"].as_u64().unwrap()).collect();
+    assert_eq!(indices, [0, 1, 2]);
+    assert_eq!(data[0]["embedding"], data[2]["embedding"]);
+    assert_ne!(data[0]["embedding"], data[1]["embedding"]);
+    assert_eq!(response["usage"]["prompt_tokens"], 3);
+}
+
+#[tokio::test]
+async fn dimensions_and_base64_encoding_are_applied() {
+    let url = spawn_app().await;
+    let body = |format: &str| {
+        json!({
+            "model": "text-embedding-3-small",
+            "input": "Embeddings can be shortened to fewer dimensions",
+            "dimensions": 64,
+            "encoding_format": format
+        })
+    };
+
+    let (_, float) = embed(&url, body("float")).await;
+    let vector = floats(&float["data"][0]["embedding"]);
+    assert_eq!(vector.len(), 64);
+    assert!((norm(&vector) - 1.0).abs() < 1e-4);
+
+    let (status, encoded) = embed(&url, body("base64")).await;
+    assert_eq!(status, 200);
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded["data"][0]["embedding"].as_str().unwrap())
+        .unwrap();
+    let decoded: Vec<f32> = bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    assert_eq!(decoded, vector);
+
+    for dimensions in [0, DEFAULT_HASHING_DIMENSIONS + 1] {
+        let (status, error) = embed(
+            &url,
+            json!({ "model": "text-embedding-3-small", "input": "x", "dimensions": dimensions }),
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(error["error"]["param"], "dimensions");
+    }
+}
+
+#[tokio::test]
+async fn empty_input_is_rejected() {
+    let url = spawn_app().await;
+    for input in [json!(""), json!([]), json!(["ok", ""])] {
+        let (status, error) = embed(
+            &url,
+            json!({ "model": "text-embedding-3-small", "input": input }),
+        )
+        .await;
+        assert_eq!(status, 400, "{}", input);
+        assert_eq!(error["error"]["type"], "invalid_request_error");
+        assert_eq!(error["error"]["param"], "input");
+    }
+}
@@ -0,0 +1,141 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Router};
+use serde_json::{json, Value};
+use shared_handlers::provider::{ChatProvider, MockProvider, OpenAiConfig, OpenAiProvider};
+use shared_handlers::{config::AppConfig, state::AppState};
+use std::sync::Arc;
+
+type Upstream = (u16, Option<&'static str>, &'static str);
+
+// OpenAI-compatible upstream answering every completion with the given status and body
+async fn spawn_upstream(
+    status: u16,
+    retry_after: Option<&'static str>,
+    body: &'static str,
+) -> String {
+    async fn completions(State((status, retry_after, body)): State<Upstream>) -> impl IntoResponse {
+        let mut response = (StatusCode::from_u16(status).unwrap(), body).into_response();
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert("retry-after", retry_after.parse().unwrap());
+        }
+        response
+    }
+
+    let app = Router::new()
+        .route("/v1/chat/completions", post(completions))
+        .with_state((status, retry_after, body));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/v1", addr)
+}
+
+async fn spawn_app(provider: Arc<dyn ChatProvider>) -> String {
+    let state = AppState::new(AppConfig::default(), provider, Default::default());
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/chat/completions", addr)
+}
+
+async fn send(url: &str, body: impl Into<reqwest::Body>) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(url)
+        .header("content-type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn hello() -> String {
+    json!({ "model": "gpt-4", "messages": [{ "role": "user", "content": "Hi" }] }).to_string()
+}
+
+#[tokio::test]
+async fn invalid_bodies_get_the_envelope_with_the_offending_param() {
+    let url = spawn_app(Arc::new(MockProvider::new())).await;
+
+    let response = send(&url, json!({ "model": "gpt-4" }).to_string()).await;
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    let body = error["error"].as_object().unwrap();
+    let mut keys: Vec<&str> = body.keys().map(String::as_str).collect();
+    keys.sort();
+    assert_eq!(keys, ["code", "message", "param", "type"]);
+    assert_eq!(error["error"]["type"], "invalid_request_error");
+    assert_eq!(error["error"]["param"], "messages");
+    assert!(error["error"]["code"].is_null());
+
+    let body = json!({ "model": "gpt-4", "messages": [{ "role": "user", "content": "Hi" }, { "content": "x" }] });
+    let error: Value = send(&url, body.to_string()).await.json().await.unwrap();
+    assert_eq!(error["error"]["param"], "messages[1].role");
+
+    // Syntax errors point at no field
+    let response = send(&url, "{\"model\": ").await;
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert!(error["error"]["param"].is_null());
+    assert!(error["error"]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Invalid request body"));
+}
+
+#[tokio::test]
+async fn upstream_rate_limits_pass_retry_after_through() {
+    let upstream = spawn_upstream(
+        429,
+        Some("7"),
+        r#"{"error": {"message": "Rate limit reached for gpt-4", "type": "requests"}}"#,
+    )
+    .await;
+    let url = spawn_app(Arc::new(OpenAiProvider::new(OpenAiConfig::new(upstream)))).await;
+
+    let response = send(&url, hello()).await;
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.headers()["retry-after"], "7");
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(
+        error,
+        json!({
+            "error": {
+                "message": "Rate limit reached for gpt-4",
+                "type": "rate_limit_error",
+                "param": null,
+                "code": "rate_limit_exceeded"
+            }
+        })
+    );
+}
+
+#[tokio::test]
+async fn other_upstream_failures_map_to_client_or_gateway_errors() {
+    let rejecting = spawn_upstream(401, None, "invalid api key").await;
+    let url = spawn_app(Arc::new(OpenAiProvider::new(OpenAiConfig::new(rejecting)))).await;
+    let response = send(&url, hello()).await;
+    assert_eq!(response.status(), 401);
+    assert!(response.headers().get("retry-after").is_none());
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["type"], "invalid_request_error");
+    assert_eq!(error["error"]["code"], "upstream_error");
+
+    let failing = spawn_upstream(500, Some("30"), "boom").await;
+    let url = spawn_app(Arc::new(OpenAiProvider::new(OpenAiConfig::new(failing)))).await;
+    let response = send(&url, hello()).await;
+    assert_eq!(response.status(), 502);
+    // Retry-After is only forwarded for rate limits
+    assert!(response.headers().get("retry-after").is_none());
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["type"], "api_error");
+    assert!(error["error"]["message"].as_str().unwrap().contains("boom"));
+
+    let unreachable = OpenAiConfig::new("http://127.0.0.1:1/v1");
+    let url = spawn_app(Arc::new(OpenAiProvider::new(unreachable))).await;
+    let response = send(&url, hello()).await;
+    assert_eq!(response.status(), 502);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["code"], "upstream_error");
+}
@@ -0,0 +1,214 @@
+use shared_handlers::config::{ConfigError, McpConfig, TransportConfig};
+use shared_handlers::mcp::{McpRegistry, McpServerStatus};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Fresh config dir holding `mcp_servers.yaml` and, when given, `environments/<env>.yaml`
+fn config_dir(name: &str, base: &str, overlay: Option<(&str, &str)>) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mcp-config-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("environments")).unwrap();
+    std::fs::write(dir.join("mcp_servers.yaml"), base).unwrap();
+    if let Some((environment, overlay)) = overlay {
+        std::fs::write(
+            dir.join("environments")
+                .join(format!("{}.yaml", environment)),
+            overlay,
+        )
+        .unwrap();
+    }
+    dir
+}
+
+fn issue_paths(error: ConfigError) -> Vec<String> {
+    let ConfigError::Validation(issues) = error else {
+        panic!("expected validation issues, got: {}", error);
+    };
+    issues.into_iter().map(|issue| issue.path).collect()
+}
+
+fn cleanup(dir: &Path) {
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn overlays_are_merged_and_variables_expanded() {
+    std::env::set_var("MCP_CONFIG_TEST_ROOT", "/srv/data");
+    let dir = config_dir(
+        "overlay",
+        r#"
+servers:
+  files:
+    description: "Files"
+    transport:
+      type: stdio
+      command: "files-server"
+      args: ["--root", "${MCP_CONFIG_TEST_ROOT}/files", "${MCP_CONFIG_TEST_UNSET}"]
+    enabled: false
+settings:
+  vm_pool_size: 3
+  log_level: "info"
+"#,
+        Some((
+            "staging",
+            r#"
+servers:
+  files:
+    enabled: true
+    resources:
+      timeout_seconds: 5
+settings:
+  log_level: "debug"
+  hot_reload: true
+"#,
+        )),
+    );
+
+    let config = McpConfig::load(&dir, Some("staging")).unwrap();
+    let files = &config.servers["files"];
+    assert!(files.enabled);
+    assert_eq!(files.description, "Files");
+    assert_eq!(files.resources.timeout_seconds, 5);
+    let TransportConfig::Stdio { command, args, .. } = &files.transport else {
+        panic!("expected a stdio transport");
+    };
+    assert_eq!(command.as_deref(), Some("files-server"));
+    // Unknown variables are left as written
+    assert_eq!(
+        args,
+        &["--root", "/srv/data/files", "${MCP_CONFIG_TEST_UNSET}"]
+    );
+    assert_eq!(config.settings.vm_pool_size, 3);
+    assert_eq!(config.settings.log_level, "debug");
+    assert_eq!(config.settings.extra["hot_reload"], true);
+
+    // Without an overlay for the environment the base file applies as-is
+    let config = McpConfig::load(&dir, Some("production")).unwrap();
+    assert!(!config.servers["files"].enabled);
+    assert_eq!(config.settings.log_level, "info");
+    cleanup(&dir);
+}
+
+#[test]
+fn validation_reports_every_issue_by_path() {
+    let dir = config_dir(
+        "invalid",
+        r#"
+servers:
+  "bad name":
+    transport: { type: http, port: 8080 }
+  broken:
+    transport: { type: stdio }
+    resources: { timeout_seconds: 0 }
+  remote:
+    transport: { type: http }
+    tools:
+      - name: "lookup"
+        schema: { type: "string" }
+  search:
+    transport: { type: http, port: 0 }
+    tools:
+      - name: "lookup"
+  disabled:
+    transport: { type: stdio }
+    enabled: false
+settings:
+  health_check_interval: 0
+"#,
+        None,
+    );
+
+    let paths = issue_paths(McpConfig::load(&dir, None).unwrap_err());
+    assert_eq!(
+        paths,
+        [
+            "settings.health_check_interval",
+            "servers.bad name",
+            "servers.broken.resources.timeout_seconds",
+            "servers.broken.transport.command",
+            "servers.remote.transport",
+            "servers.remote.tools[0].schema",
+            "servers.search.transport.port",
+            "servers.search.tools[0].name",
+        ]
+    );
+    cleanup(&dir);
+}
+
+#[test]
+fn schema_errors_name_the_offending_field() {
+    let dir = config_dir(
+        "schema",
+        "servers:\n  files:\n    transport:\n      type: stdio\n      comand: files-server\n",
+        None,
+    );
+    let error = McpConfig::load(&dir, None).unwrap_err();
+    let ConfigError::Schema { field, message, .. } = error else {
+        panic!("expected a schema error, got: {}", error);
+    };
+    assert_eq!(field, "servers.files.transport");
+    assert!(message.contains("comand"), "{}", message);
+    cleanup(&dir);
+}
+
+#[tokio::test]
+async fn only_enabled_servers_are_registered() {
+    let dir = config_dir(
+        "register",
+        &format!(
+            r#"
+servers:
+  echo:
+    description: "Echo test server"
+    transport:
+      type: stdio
+      command: "{}"
+    resources:
+      timeout_seconds: 7
+  search:
+    transport: {{ type: http, port: 8080 }}
+    tools:
+      - name: "search_web"
+        description: "Search the web"
+  files:
+    transport: {{ type: stdio, command: "files-server" }}
+    enabled: false
+  missing:
+    transport: {{ type: stdio, command: "/nonexistent/mcp-server" }}
+settings:
+  coerce_arguments: true
+"#,
+            env!("CARGO_BIN_EXE_mcp_echo_server")
+        ),
+        None,
+    );
+    let config = McpConfig::load(&dir, None).unwrap();
+
+    let mut registry = McpRegistry::new();
+    config.register_servers(&mut registry).await;
+
+    let mut servers: Vec<&str> = registry
+        .get_servers()
+        .iter()
+        .map(|server| server.name.as_str())
+        .collect();
+    servers.sort();
+    assert_eq!(servers, ["echo", "missing", "search"]);
+    let mut tools: Vec<String> = registry
+        .get_available_tools()
+        .into_iter()
+        .map(|tool| tool.function.name)
+        .collect();
+    tools.sort();
+    assert_eq!(tools, ["echo", "fail", "search_web", "sleep"]);
+    assert_eq!(registry.tool_timeout("echo"), Duration::from_secs(7));
+
+    // A server that fails to start stays visible with its error
+    let missing = registry
+        .get_servers()
+        .into_iter()
+        .find(|server| server.name == "missing")
+        .unwrap();
+    assert!(matches!(missing.status, McpServerStatus::Error(_)));
+    cleanup(&dir);
+}
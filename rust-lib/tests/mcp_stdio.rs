@@ -0,0 +1,158 @@
+use shared_handlers::ai::{FunctionCall, ToolCall};
+use shared_handlers::mcp::McpRegistry;
+use shared_handlers::mcp_client::{McpClientError, McpStdioClient, StdioTransport};
+use std::time::Duration;
+
+fn echo_transport() -> StdioTransport {
+    StdioTransport {
+        command: env!("CARGO_BIN_EXE_mcp_echo_server").to_string(),
+        ..Default::default()
+    }
+}
+
+fn tool_call(name: &str, arguments: &str) -> ToolCall {
+    ToolCall {
+        id: format!("call_{}", name),
+        r#type: "function".to_string(),
+        function: FunctionCall {
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        },
+    }
+}
+
+#[tokio::test]
+async fn client_handshakes_and_discovers_tools() {
+    let client = McpStdioClient::spawn("echo", &echo_transport(), Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(client.server_info().name, "mcp-echo-server");
+
+    let tools = client.list_tools().await.unwrap();
+    let names: Vec<_> = tools.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, ["echo", "fail", "sleep"]);
+    assert_eq!(tools[0].server, "echo");
+    assert_eq!(tools[0].schema["required"][0], "message");
+
+    let echoed = client
+        .call_tool("echo", serde_json::json!({ "message": "hello" }))
+        .await
+        .unwrap();
+    assert_eq!(echoed, "hello");
+
+    assert!(matches!(
+        client.call_tool("fail", serde_json::json!({})).await,
+        Err(McpClientError::Tool(_))
+    ));
+    assert!(matches!(
+        client.call_tool("missing", serde_json::json!({})).await,
+        Err(McpClientError::Rpc { code: -32602, .. })
+    ));
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn registry_routes_tool_calls_through_subprocess() {
+    let mut registry = McpRegistry::new();
+    registry
+        .connect_stdio_server("echo", "Echo test server", &echo_transport())
+        .await
+        .unwrap();
+
+    let tools = registry.get_available_tools();
+    assert_eq!(tools.len(), 3);
+
+    let result = registry
+        .execute_tool_call(&tool_call("echo", r#"{"message":"via registry"}"#))
+        .await
+        .unwrap();
+    assert_eq!(result, "via registry");
+
+    assert!(registry
+        .execute_tool_call(&tool_call("echo", "{not json"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn registry_validates_and_coerces_arguments() {
+    let mut registry = McpRegistry::new();
+    registry
+        .connect_stdio_server("echo", "Echo test server", &echo_transport())
+        .await
+        .unwrap();
+
+    // Missing required field: the error is a JSON tool message naming the problem
+    let error = registry
+        .execute_tool_call(&tool_call("echo", r#"{"text":"hi"}"#))
+        .await
+        .unwrap_err();
+    let error: serde_json::Value = serde_json::from_str(&error).unwrap();
+    assert_eq!(error["error"]["type"], "invalid_arguments");
+    assert_eq!(error["error"]["tool"], "echo");
+    assert!(error["error"]["issues"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("message"));
+
+    // Wrong scalar type is rejected unless coercion is enabled
+    let error = registry
+        .execute_tool_call(&tool_call("sleep", r#"{"ms":"5"}"#))
+        .await
+        .unwrap_err();
+    let error: serde_json::Value = serde_json::from_str(&error).unwrap();
+    assert_eq!(error["error"]["issues"][0]["path"], "/ms");
+
+    registry.set_argument_coercion(true);
+    let result = registry
+        .execute_tool_call(&tool_call("sleep", r#"{"ms":"5"}"#))
+        .await
+        .unwrap();
+    assert_eq!(result, "slept 5ms");
+}
+
+#[tokio::test]
+async fn registry_runs_parallel_calls_in_order_with_timeouts() {
+    let mut registry = McpRegistry::new();
+    registry.set_tool_timeout("echo", Duration::from_millis(500));
+    registry
+        .connect_stdio_server("echo", "Echo test server", &echo_transport())
+        .await
+        .unwrap();
+
+    let calls = [
+        tool_call("sleep", r#"{"ms":300}"#),
+        tool_call("echo", r#"{"message":"fast"}"#),
+        tool_call("sleep", r#"{"ms":5000}"#),
+        tool_call("fail", "{}"),
+    ];
+
+    let started = std::time::Instant::now();
+    let results = registry.execute_tool_calls(&calls, true).await;
+    // Run one after another the two sleeps alone would take 800ms
+    assert!(started.elapsed() < Duration::from_millis(750));
+
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].as_deref(), Ok("slept 300ms"));
+    assert_eq!(results[1].as_deref(), Ok("fast"));
+    assert!(results[2].as_ref().unwrap_err().contains("timed out"));
+    assert!(results[3].is_err());
+
+    let started = std::time::Instant::now();
+    let results = registry.execute_tool_calls(&calls[..2], false).await;
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert_eq!(results[1].as_deref(), Ok("fast"));
+}
+
+#[tokio::test]
+async fn spawn_reports_missing_command() {
+    let transport = StdioTransport {
+        command: "/nonexistent/mcp-server".to_string(),
+        ..Default::default()
+    };
+    assert!(matches!(
+        McpStdioClient::spawn("missing", &transport, Duration::from_secs(1)).await,
+        Err(McpClientError::Spawn(_))
+    ));
+}
@@ -0,0 +1,166 @@
+use serde_json::{json, Value};
+use shared_handlers::config::{AppConfig, ConfigError};
+use shared_handlers::models::ModelCatalog;
+use shared_handlers::provider::MockProvider;
+use shared_handlers::state::AppState;
+use std::sync::Arc;
+
+async fn spawn_app(config: AppConfig) -> String {
+    let state = AppState::new(config, Arc::new(MockProvider::new()), Default::default());
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+async fn get(url: String) -> (u16, Value) {
+    let response = reqwest::get(url).await.unwrap();
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+fn catalog_file(name: &str, yaml: &str) -> std::path::PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("models-test-{}-{}.yaml", name, std::process::id()));
+    std::fs::write(&path, yaml).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn models_are_listed_and_retrieved_by_id_or_alias() {
+    let base = spawn_app(AppConfig::default()).await;
+
+    let (status, list) = get(format!("{}/models", base)).await;
+    assert_eq!(status, 200);
+    assert_eq!(list["object"], "list");
+    let ids: Vec<&str> = list["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|model| model["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, ["gpt-4o", "gpt-4o-mini", "gpt-4"]);
+    assert_eq!(list["data"][0]["object"], "model");
+    assert_eq!(list["data"][2]["context_window"], 8192);
+
+    let (status, model) = get(format!("{}/models/gpt-4", base)).await;
+    assert_eq!(status, 200);
+    assert_eq!(model["id"], "gpt-4");
+    assert_eq!(model["capabilities"]["vision"], false);
+
+    let (status, model) = get(format!("{}/models/smart", base)).await;
+    assert_eq!(status, 200);
+    assert_eq!(model["id"], "gpt-4o");
+}
+
+#[tokio::test]
+async fn unknown_models_get_a_model_not_found_envelope() {
+    let base = spawn_app(AppConfig::default()).await;
+
+    let (status, error) = get(format!("{}/models/gpt-5-ultra", base)).await;
+    assert_eq!(status, 404);
+    assert_eq!(
+        error,
+        json!({
+            "error": {
+                "message": "The model 'gpt-5-ultra' does not exist",
+                "type": "invalid_request_error",
+                "param": "model",
+                "code": "model_not_found"
+            }
+        })
+    );
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/chat/completions", base))
+        .json(&json!({ "model": "gpt-5-ultra", "messages": [{ "role": "user", "content": "Hi" }] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["code"], "model_not_found");
+    assert_eq!(error["error"]["param"], "model");
+}
+
+#[tokio::test]
+async fn chat_requests_resolve_aliases_from_a_loaded_catalog() {
+    let path = catalog_file(
+        "aliases",
+        r#"
+models:
+  - id: "llama3:8b"
+    owned_by: "ollama"
+    context_window: 8192
+aliases:
+  local: "llama3:8b"
+"#,
+    );
+    let config = AppConfig {
+        models: ModelCatalog::load(&path).unwrap(),
+        ..Default::default()
+    };
+    let base = spawn_app(config).await;
+
+    let completion: Value = reqwest::Client::new()
+        .post(format!("{}/chat/completions", base))
+        .json(&json!({ "model": "local", "messages": [{ "role": "user", "content": "Hi" }] }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(completion["model"], "llama3:8b");
+    assert!(completion["choices"][0]["message"]["content"]
+        .as_str()
+        .unwrap()
+        .contains("to model 'llama3:8b'"));
+
+    // Models of the built-in catalog are gone once a file replaces it
+    let (status, _) = get(format!("{}/models/gpt-4o", base)).await;
+    assert_eq!(status, 404);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn catalog_validation_reports_duplicates_and_bad_aliases() {
+    let path = catalog_file(
+        "invalid",
+        r#"
+models:
+  - id: "gpt-4o"
+    context_window: 128000
+  - id: "gpt-4o"
+    context_window: 128000
+  - id: " "
+    context_window: 0
+aliases:
+  gpt-4o: "gpt-4o"
+  missing: "gpt-5"
+  ping: "pong"
+  pong: "ping"
+"#,
+    );
+    let ConfigError::Validation(issues) = ModelCatalog::load(&path).unwrap_err() else {
+        panic!("expected validation issues");
+    };
+    let issues: Vec<String> = issues.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        issues,
+        [
+            "models[1].id: duplicate model id 'gpt-4o'",
+            "models[2].id: must not be empty",
+            "models[2].context_window: must be greater than 0",
+            "aliases.gpt-4o: alias shadows a model id",
+            "aliases.missing: points to unknown model 'gpt-5'",
+            // Aliases must name a model directly, so alias chains and cycles are rejected
+            "aliases.ping: points to unknown model 'pong'",
+            "aliases.pong: points to unknown model 'ping'",
+        ]
+    );
+
+    assert!(ModelCatalog::default().validate().is_ok());
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,275 @@
+use axum::{extract::State, http::HeaderMap, response::IntoResponse, routing::post, Json, Router};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use shared_handlers::ai::{ChatCompletionRequest, ContentPart, FinishReason, MessageContent, Role};
+use shared_handlers::provider::{ChatProvider, MockProvider, OpenAiConfig, OpenAiProvider};
+use std::sync::{Arc, Mutex};
+
+type Captured = Arc<Mutex<Option<(Option<String>, Value)>>>;
+
+async fn spawn_upstream(captured: Captured) -> String {
+    async fn completions(
+        State(captured): State<Captured>,
+        headers: HeaderMap,
+        Json(body): Json<Value>,
+    ) -> Json<Value> {
+        let auth = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let model = body["model"].clone();
+        *captured.lock().unwrap() = Some((auth, body));
+
+        Json(json!({
+            "id": "chatcmpl-upstream",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": model,
+            "system_fingerprint": "fp_test",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hello from upstream" },
+                "logprobs": null,
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8 }
+        }))
+    }
+
+    let app = Router::new()
+        .route("/v1/chat/completions", post(completions))
+        .with_state(captured);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    format!("http://{}/v1", addr)
+}
+
+fn user_request(model: &str, content: &str) -> ChatCompletionRequest {
+    serde_json::from_value(json!({
+        "model": model,
+        "messages": [{ "role": "user", "content": content }],
+        "temperature": 0.2,
+        "stream": true
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn openai_provider_forwards_to_upstream() {
+    let captured: Captured = Arc::default();
+    let base_url = spawn_upstream(captured.clone()).await;
+
+    let provider = OpenAiProvider::new(
+        OpenAiConfig::new(base_url)
+            .with_api_key("sk-test")
+            .with_model("gpt-4", "llama3:8b"),
+    );
+    let response = provider
+        .complete(&user_request("gpt-4", "Hi"))
+        .await
+        .unwrap();
+
+    assert_eq!(provider.name(), "openai");
+    assert_eq!(response.model, "llama3:8b");
+    assert_eq!(
+        response.choices[0]
+            .message
+            .content
+            .as_ref()
+            .and_then(MessageContent::as_text),
+        Some("Hello from upstream")
+    );
+    assert_eq!(response.usage.total_tokens, 8);
+
+    let (auth, body) = captured.lock().unwrap().take().unwrap();
+    assert_eq!(auth.as_deref(), Some("Bearer sk-test"));
+    assert_eq!(body["model"], "llama3:8b");
+    assert_eq!(body["messages"][0]["content"], "Hi");
+    assert!(body.get("stream").is_none());
+    assert!(body.get("tools").is_none());
+}
+
+#[tokio::test]
+async fn openai_provider_surfaces_upstream_errors() {
+    let provider = OpenAiProvider::new(OpenAiConfig::new("http://127.0.0.1:1/v1"));
+    assert!(provider
+        .complete(&user_request("gpt-4", "Hi"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn openai_provider_decodes_streamed_chunks() {
+    async fn completions(Json(body): Json<Value>) -> impl IntoResponse {
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+        let chunk = |delta: Value, finish: Value| {
+            json!({
+                "id": "chatcmpl-upstream",
+                "object": "chat.completion.chunk",
+                "created": 1700000000,
+                "model": "gpt-4",
+                "choices": [{ "index": 0, "delta": delta, "finish_reason": finish }]
+            })
+        };
+        let body = format!(
+            ": keep-alive\n\ndata: {}\n\ndata: {}\r\n\r\ndata: {}\n\ndata: [DONE]\n\n",
+            chunk(json!({"role": "assistant", "content": ""}), Value::Null),
+            chunk(json!({"content": "Hé"}), Value::Null),
+            chunk(json!({}), json!("stop")),
+        );
+        ([("content-type", "text/event-stream")], body)
+    }
+
+    let app = Router::new().route("/v1/chat/completions", post(completions));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let provider = OpenAiProvider::new(OpenAiConfig::new(format!("http://{}/v1", addr)));
+    let mut request = user_request("gpt-4", "Hi");
+    request.stream_options = serde_json::from_value(json!({"include_usage": true})).unwrap();
+    let chunks: Vec<_> = provider
+        .complete_stream(&request)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(
+        chunks[0].choices[0].delta.role.as_ref().map(Role::as_str),
+        Some("assistant")
+    );
+    assert_eq!(chunks[1].choices[0].delta.content.as_deref(), Some("Hé"));
+    assert_eq!(
+        chunks[2].choices[0]
+            .finish_reason
+            .as_ref()
+            .map(FinishReason::as_str),
+        Some("stop")
+    );
+}
+
+#[tokio::test]
+async fn mock_provider_streams_usage_in_last_chunk() {
+    let mut request = user_request("gpt-4", "Hello there");
+    request.stream_options = serde_json::from_value(json!({"include_usage": true})).unwrap();
+    let chunks: Vec<_> = MockProvider::new()
+        .complete_stream(&request)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    let (last, rest) = chunks.split_last().unwrap();
+    assert!(last.choices.is_empty());
+    assert!(last.usage.is_some());
+    assert_eq!(
+        rest[0].choices[0].delta.role.as_ref().map(Role::as_str),
+        Some("assistant")
+    );
+    assert_eq!(
+        rest.last().unwrap().choices[0]
+            .finish_reason
+            .as_ref()
+            .map(FinishReason::as_str),
+        Some("stop")
+    );
+    assert!(rest
+        .iter()
+        .all(|chunk| chunk.object == "chat.completion.chunk"));
+}
+
+#[tokio::test]
+async fn mock_provider_keeps_canned_behavior() {
+    let provider = MockProvider::new();
+    assert_eq!(provider.name(), "mock");
+
+    let response = provider
+        .complete(&user_request("gpt-4", "Hello there"))
+        .await
+        .unwrap();
+    assert_eq!(response.choices[0].finish_reason, FinishReason::Stop);
+    assert!(response.choices[0]
+        .message
+        .content
+        .as_ref()
+        .unwrap()
+        .to_text()
+        .contains("shared Rust handler"));
+}
+
+#[tokio::test]
+async fn openai_provider_forwards_content_parts_unchanged() {
+    let captured: Captured = Arc::default();
+    let base_url = spawn_upstream(captured.clone()).await;
+    let provider = OpenAiProvider::new(OpenAiConfig::new(base_url));
+
+    let content = json!([
+        { "type": "text", "text": "What is in this image?" },
+        { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=", "detail": "low" } },
+        { "type": "file", "file": { "file_id": "file-abc123" } }
+    ]);
+    let request: ChatCompletionRequest = serde_json::from_value(json!({
+        "model": "gpt-4o",
+        "messages": [{ "role": "user", "content": content }]
+    }))
+    .unwrap();
+
+    let parts = request.messages[0].content.as_ref().unwrap().parts();
+    assert_eq!(parts.len(), 3);
+    assert!(
+        matches!(&parts[1], ContentPart::ImageUrl { image_url } if image_url.detail.as_deref() == Some("low"))
+    );
+    assert_eq!(
+        request.messages[0].content.as_ref().unwrap().to_text(),
+        "What is in this image?"
+    );
+
+    provider.complete(&request).await.unwrap();
+    let (_, body) = captured.lock().unwrap().take().unwrap();
+    assert_eq!(body["messages"][0]["content"], content);
+}
+
+#[tokio::test]
+async fn openai_provider_forwards_sampling_parameters() {
+    let captured: Captured = Arc::default();
+    let base_url = spawn_upstream(captured.clone()).await;
+    let provider = OpenAiProvider::new(OpenAiConfig::new(base_url));
+
+    let sampling = json!({
+        "top_p": 0.5,
+        "n": 2,
+        "stop": ["\n\n", "END"],
+        "seed": 42,
+        "presence_penalty": 0.5,
+        "frequency_penalty": -0.5,
+        "logit_bias": { "50256": -100.0 },
+        "logprobs": true,
+        "top_logprobs": 3,
+        "user": "user-1234"
+    });
+    let mut body = json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": "Hi" }]
+    });
+    body.as_object_mut()
+        .unwrap()
+        .extend(sampling.as_object().unwrap().clone());
+    let request: ChatCompletionRequest = serde_json::from_value(body).unwrap();
+    request.validate().unwrap();
+
+    provider.complete(&request).await.unwrap();
+    let (_, body) = captured.lock().unwrap().take().unwrap();
+    for (key, value) in sampling.as_object().unwrap() {
+        assert_eq!(&body[key], value, "{}", key);
+    }
+}
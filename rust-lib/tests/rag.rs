@@ -0,0 +1,149 @@
+use axum::{extract::State, http::HeaderMap, routing::post, Json, Router};
+use serde_json::{json, Value};
+use shared_handlers::embedding::{Embedder, HashingEmbedder, OpenAiEmbedder};
+use shared_handlers::rag::{Document, RagConfig, RagService};
+use shared_handlers::vector_store::Similarity;
+use std::sync::{Arc, Mutex};
+
+fn document(id: &str, title: &str, content: &str) -> Document {
+    Document {
+        id: id.to_string(),
+        title: title.to_string(),
+        content: content.to_string(),
+        metadata: json!({}),
+        embedding: None,
+        created_at: chrono::Utc::now(),
+    }
+}
+
+#[tokio::test]
+async fn hashing_embedder_is_deterministic_and_normalized() {
+    let embedder = HashingEmbedder::new(64);
+    let inputs = vec![
+        "Configure MCP servers in YAML".to_string(),
+        "Configure MCP servers in YAML".to_string(),
+        "".to_string(),
+    ];
+    let vectors = embedder.embed(&inputs).await.unwrap();
+
+    assert_eq!(embedder.model(), "local-hashing-64");
+    assert_eq!(vectors.len(), 3);
+    assert_eq!(vectors[0].len(), 64);
+    assert_eq!(vectors[0], vectors[1]);
+    let norm: f32 = vectors[0].iter().map(|x| x * x).sum::<f32>().sqrt();
+    assert!((norm - 1.0).abs() < 1e-5);
+    assert!(vectors[2].iter().all(|x| *x == 0.0));
+
+    let related = embedder.embed_text("How do I configure the MCP servers?");
+    let unrelated = embedder.embed_text("Bake bread with sourdough starter");
+    assert!(
+        Similarity::Cosine.score(&vectors[0], &related)
+            > Similarity::Cosine.score(&vectors[0], &unrelated)
+    );
+}
+
+#[tokio::test]
+async fn rag_service_embeds_and_retrieves_offline() {
+    let path = std::env::temp_dir()
+        .join(format!("rag-test-{}", std::process::id()))
+        .join("rag_index.redb");
+    let config = RagConfig {
+        relevance_threshold: Some(0.2),
+        storage_path: Some(path.clone()),
+        ..RagConfig::default()
+    };
+
+    {
+        let rag = RagService::new(config.clone());
+        rag.store_document(
+            document(
+                "mcp",
+                "MCP configuration",
+                "MCP servers are configured in config/mcp_servers.yaml with stdio or http transports.",
+            ),
+            None,
+        )
+        .await
+        .unwrap();
+        rag.store_document(
+            document(
+                "bread",
+                "Sourdough",
+                "Feed the sourdough starter before baking bread.",
+            ),
+            Some("alice"),
+        )
+        .await
+        .unwrap();
+    }
+
+    // Reopen to make sure documents and their embeddings survived on disk
+    let rag = RagService::new(config);
+    assert_eq!(rag.document_count(), 2);
+
+    let context = rag
+        .retrieve_context("Which transports can MCP servers use?", None)
+        .await
+        .unwrap();
+    assert_eq!(context.documents.len(), 1);
+    assert_eq!(context.documents[0].id, "mcp");
+
+    let context = rag
+        .retrieve_context("sourdough starter bread", Some("bob"))
+        .await
+        .unwrap();
+    assert!(context.documents.is_empty());
+
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+type Captured = Arc<Mutex<Option<(Option<String>, Value)>>>;
+
+#[tokio::test]
+async fn openai_embedder_calls_upstream() {
+    async fn embeddings(
+        State(captured): State<Captured>,
+        headers: HeaderMap,
+        Json(body): Json<Value>,
+    ) -> Json<Value> {
+        let auth = headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        *captured.lock().unwrap() = Some((auth, body));
+
+        // Deliberately out of order: the client must sort by index
+        Json(json!({
+            "object": "list",
+            "data": [
+                { "object": "embedding", "index": 1, "embedding": [0.0, 1.0] },
+                { "object": "embedding", "index": 0, "embedding": [1.0, 0.0] }
+            ],
+            "model": "text-embedding-3-small",
+            "usage": { "prompt_tokens": 4, "total_tokens": 4 }
+        }))
+    }
+
+    let captured: Captured = Arc::default();
+    let app = Router::new()
+        .route("/v1/embeddings", post(embeddings))
+        .with_state(captured.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let embedder = OpenAiEmbedder::new(format!("http://{}/v1", addr), "text-embedding-3-small")
+        .with_api_key("sk-test");
+    let vectors = embedder
+        .embed(&["first".to_string(), "second".to_string()])
+        .await
+        .unwrap();
+
+    assert_eq!(vectors, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+    let (auth, body) = captured.lock().unwrap().take().unwrap();
+    assert_eq!(auth.as_deref(), Some("Bearer sk-test"));
+    assert_eq!(body["model"], "text-embedding-3-small");
+    assert_eq!(body["input"], json!(["first", "second"]));
+}
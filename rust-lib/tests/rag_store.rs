@@ -0,0 +1,162 @@
+use serde_json::json;
+use shared_handlers::embedding::{
+    Embedder, HashingEmbedder, OpenAiEmbedder, DEFAULT_RELEVANCE_THRESHOLD,
+    HASHING_RELEVANCE_THRESHOLD,
+};
+use shared_handlers::rag::{Document, RagConfig, RagService};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn document(id: &str, title: &str, content: &str) -> Document {
+    Document {
+        id: id.to_string(),
+        title: title.to_string(),
+        content: content.to_string(),
+        metadata: json!({}),
+        embedding: None,
+        created_at: chrono::Utc::now(),
+    }
+}
+
+fn index_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rag-store-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir.join("rag_index.redb")
+}
+
+#[tokio::test]
+async fn the_default_threshold_follows_the_embedder() {
+    assert_eq!(
+        HashingEmbedder::default().default_relevance_threshold(),
+        HASHING_RELEVANCE_THRESHOLD
+    );
+    let remote = OpenAiEmbedder::new("http://127.0.0.1:1/v1", "text-embedding-3-small");
+    assert_eq!(
+        remote.default_relevance_threshold(),
+        DEFAULT_RELEVANCE_THRESHOLD
+    );
+
+    let rag = RagService::new(RagConfig::default());
+    assert_eq!(rag.relevance_threshold(), HASHING_RELEVANCE_THRESHOLD);
+    let rag = RagService::with_embedder(
+        RagConfig {
+            relevance_threshold: Some(0.5),
+            ..RagConfig::default()
+        },
+        Arc::new(HashingEmbedder::default()),
+    );
+    assert_eq!(rag.relevance_threshold(), 0.5);
+}
+
+#[tokio::test]
+async fn related_documents_are_retrieved_with_the_default_config() {
+    let rag = RagService::new(RagConfig::default());
+    for (id, title, content) in [
+        (
+            "refunds",
+            "Refund policy",
+            "Customers can request a refund within 30 days of purchase by contacting support.",
+        ),
+        (
+            "ownership",
+            "Rust ownership",
+            "Each value in Rust has a single owner, and the value is dropped when the owner goes out of scope.",
+        ),
+    ] {
+        rag.store_document(document(id, title, content), None)
+            .await
+            .unwrap();
+    }
+
+    let context = rag
+        .retrieve_context("Can I get a refund?", None)
+        .await
+        .unwrap();
+    let ids: Vec<&str> = context.documents.iter().map(|d| d.id.as_str()).collect();
+    assert_eq!(ids, ["refunds"]);
+    assert!(context.relevance_scores[0] >= HASHING_RELEVANCE_THRESHOLD);
+
+    let context = rag
+        .retrieve_context("What is the weather today?", None)
+        .await
+        .unwrap();
+    assert!(context.documents.is_empty());
+}
+
+#[tokio::test]
+async fn concurrent_writes_and_deletes_reach_the_disk() {
+    let path = index_path("concurrent");
+    let config = RagConfig {
+        storage_path: Some(path.clone()),
+        ..RagConfig::default()
+    };
+
+    {
+        let rag = Arc::new(RagService::new(config.clone()));
+        let writes: Vec<_> = (0..16)
+            .map(|i| {
+                let rag = rag.clone();
+                tokio::spawn(async move {
+                    let content = format!("Note number {} about release planning", i);
+                    rag.store_document(document(&format!("note-{}", i), "Note", &content), None)
+                        .await
+                })
+            })
+            .collect();
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+        assert!(rag.delete_document("note-3").await.unwrap());
+        assert!(!rag.delete_document("note-3").await.unwrap());
+    }
+
+    let rag = RagService::new(config);
+    assert_eq!(rag.document_count(), 15);
+    let found = rag
+        .search_documents("release planning note number 3", None, 16)
+        .await
+        .unwrap();
+    assert!(found.iter().all(|doc| doc.id != "note-3"));
+    assert!(!found.is_empty());
+
+    drop(rag);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn owned_documents_stay_hidden_from_anonymous_queries() {
+    let rag = RagService::new(RagConfig::default());
+    rag.store_document(
+        document(
+            "salary",
+            "Salary review",
+            "My salary review with the manager is scheduled for next Friday.",
+        ),
+        Some("alice"),
+    )
+    .await
+    .unwrap();
+    rag.store_document(
+        document(
+            "handbook",
+            "Salary handbook",
+            "Salary reviews happen once a year with the manager.",
+        ),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let ids = |documents: Vec<Document>| -> Vec<String> {
+        let mut ids: Vec<String> = documents.into_iter().map(|d| d.id).collect();
+        ids.sort();
+        ids
+    };
+    let query = "salary review with the manager";
+    let context = rag.retrieve_context(query, None).await.unwrap();
+    assert_eq!(ids(context.documents), ["handbook"]);
+    let context = rag.retrieve_context(query, Some("bob")).await.unwrap();
+    assert_eq!(ids(context.documents), ["handbook"]);
+    let context = rag.retrieve_context(query, Some("alice")).await.unwrap();
+    assert_eq!(ids(context.documents), ["handbook", "salary"]);
+}
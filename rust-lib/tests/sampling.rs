@@ -0,0 +1,133 @@
+use futures::StreamExt;
+use serde_json::{json, Value};
+use shared_handlers::ai::{
+    ChatCompletionRequest, ChatCompletionResponse, FinishReason, MessageContent,
+};
+use shared_handlers::error::AiError;
+use shared_handlers::provider::{ChatProvider, MockProvider};
+use shared_handlers::tokenizer::Encoding;
+
+fn request(extra: Value) -> ChatCompletionRequest {
+    let mut body = json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": "Hello there" }]
+    });
+    body.as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    serde_json::from_value(body).unwrap()
+}
+
+fn text(response: &ChatCompletionResponse, choice: usize) -> String {
+    response.choices[choice]
+        .message
+        .content
+        .as_ref()
+        .map(MessageContent::to_text)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn n_returns_indexed_choices_billed_together() {
+    let single = MockProvider::new()
+        .complete(&request(json!({})))
+        .await
+        .unwrap();
+    let request = request(json!({ "n": 3 }));
+    let response = MockProvider::new().complete(&request).await.unwrap();
+
+    let indexes: Vec<u32> = response.choices.iter().map(|c| c.index).collect();
+    assert_eq!(indexes, [0, 1, 2]);
+    assert_eq!(
+        response.usage.completion_tokens,
+        3 * single.usage.completion_tokens
+    );
+    assert_eq!(text(&response, 2), text(&single, 0));
+}
+
+#[tokio::test]
+async fn stop_sequences_and_max_tokens_end_generation() {
+    let request_stop = request(json!({ "stop": ["shared Rust", "handler"] }));
+    let response = MockProvider::new().complete(&request_stop).await.unwrap();
+    assert_eq!(response.choices[0].finish_reason, FinishReason::Stop);
+    assert_eq!(text(&response, 0), "This is a response from the ");
+
+    let request_length = request(json!({ "max_tokens": 5, "stop": "never matches" }));
+    let response = MockProvider::new().complete(&request_length).await.unwrap();
+    assert_eq!(response.choices[0].finish_reason, FinishReason::Length);
+    assert_eq!(text(&response, 0), "This is a response from");
+    assert_eq!(response.usage.completion_tokens, 5);
+    assert_eq!(Encoding::default().truncate("short", 5), None);
+}
+
+#[tokio::test]
+async fn logprobs_cover_every_token_and_stream_with_their_deltas() {
+    let request = request(json!({ "logprobs": true, "top_logprobs": 2, "max_tokens": 4 }));
+    let response = MockProvider::new().complete(&request).await.unwrap();
+    let tokens = response.choices[0]
+        .logprobs
+        .as_ref()
+        .and_then(|logprobs| logprobs.content.clone())
+        .unwrap();
+    assert_eq!(tokens.len(), 4);
+    assert_eq!(
+        tokens.iter().map(|t| t.token.as_str()).collect::<String>(),
+        text(&response, 0)
+    );
+    assert!(tokens
+        .iter()
+        .all(|t| t.logprob == 0.0 && t.top_logprobs.len() == 1));
+
+    let chunks: Vec<_> = MockProvider::new()
+        .complete_stream(&request)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    let streamed: Vec<String> = chunks
+        .iter()
+        .filter_map(|chunk| chunk.choices[0].logprobs.as_ref())
+        .flat_map(|logprobs| logprobs.content.clone().unwrap())
+        .map(|token| token.token)
+        .collect();
+    assert_eq!(streamed, ["This", " is", " a", " response"]);
+    assert_eq!(
+        chunks.last().unwrap().choices[0].finish_reason,
+        Some(FinishReason::Length)
+    );
+}
+
+#[test]
+fn out_of_range_sampling_parameters_are_rejected() {
+    let param = |extra: Value| match request(extra).validate() {
+        Err(AiError::InvalidRequest { param, .. }) => param,
+        other => panic!("expected an invalid request, got {:?}", other.map(|_| ())),
+    };
+
+    assert_eq!(param(json!({ "top_p": 1.5 })).as_deref(), Some("top_p"));
+    assert_eq!(param(json!({ "n": 0 })).as_deref(), Some("n"));
+    assert_eq!(
+        param(json!({ "n": 2, "tool_execution": "server" })).as_deref(),
+        Some("n")
+    );
+    assert_eq!(
+        param(json!({ "stop": ["a", "b", "c", "d", "e"] })).as_deref(),
+        Some("stop")
+    );
+    assert_eq!(
+        param(json!({ "frequency_penalty": -3 })).as_deref(),
+        Some("frequency_penalty")
+    );
+    assert_eq!(
+        param(json!({ "logit_bias": { "hello": 1 } })).as_deref(),
+        Some("logit_bias")
+    );
+    assert_eq!(
+        param(json!({ "top_logprobs": 2 })).as_deref(),
+        Some("top_logprobs")
+    );
+    assert!(request(json!({ "seed": 7, "user": "u-1", "stop": "\n" }))
+        .validate()
+        .is_ok());
+}
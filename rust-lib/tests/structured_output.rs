@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use shared_handlers::ai::{
+    ChatChoice, ChatCompletionRequest, ChatCompletionResponse, ChatMessage, FinishReason,
+    MessageContent, ResponseFormat, Role, Usage,
+};
+use shared_handlers::provider::{ChatProvider, MockProvider, ProviderError};
+use shared_handlers::structured::{complete_structured, repair_output, sample_from_schema};
+use shared_handlers::{config::AppConfig, state::AppState};
+use std::sync::{Arc, Mutex};
+
+fn person_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "name": { "type": "string", "minLength": 20 },
+            "age": { "type": "integer", "minimum": 18 },
+            "email": { "type": "string", "format": "email" },
+            "role": { "enum": ["admin", "member"] },
+            "tags": { "type": "array", "items": { "type": "string" }, "minItems": 2 },
+            "manager": { "anyOf": [{ "type": "null" }, { "type": "string" }] }
+        },
+        "required": ["name", "age", "email", "role", "tags", "manager"],
+        "additionalProperties": false
+    })
+}
+
+fn structured_request(schema: Value) -> ChatCompletionRequest {
+    serde_json::from_value(json!({
+        "model": "gpt-4o",
+        "messages": [{ "role": "user", "content": "Describe a person" }],
+        "response_format": {
+            "type": "json_schema",
+            "json_schema": { "name": "person", "schema": schema, "strict": true }
+        }
+    }))
+    .unwrap()
+}
+
+fn content(response: &ChatCompletionResponse) -> String {
+    response.choices[0]
+        .message
+        .content
+        .as_ref()
+        .map(MessageContent::to_text)
+        .unwrap()
+}
+
+// Replies with the queued contents in order and records how many messages each request had
+struct ScriptedProvider {
+    replies: Mutex<Vec<&'static str>>,
+    prompts: Mutex<Vec<usize>>,
+}
+
+impl ScriptedProvider {
+    fn new(mut replies: Vec<&'static str>) -> Self {
+        replies.reverse();
+        Self {
+            replies: Mutex::new(replies),
+            prompts: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for ScriptedProvider {
+    fn name(&self) -> &str {
+        "scripted"
+    }
+
+    async fn complete(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        self.prompts.lock().unwrap().push(request.messages.len());
+        let reply = self
+            .replies
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or("still not json");
+        Ok(ChatCompletionResponse {
+            id: "chatcmpl-scripted".to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: request.model.clone(),
+            choices: vec![ChatChoice {
+                index: 0,
+                message: ChatMessage {
+                    role: Role::Assistant,
+                    content: Some(reply.into()),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
+                },
+                finish_reason: FinishReason::Stop,
+                logprobs: None,
+            }],
+            usage: Usage {
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                total_tokens: 15,
+            },
+            agent_trace: None,
+            served_by: None,
+        })
+    }
+}
+
+#[tokio::test]
+async fn mock_output_conforms_to_the_requested_schema() {
+    let schema = person_schema();
+    let response = MockProvider::new()
+        .complete(&structured_request(schema.clone()))
+        .await
+        .unwrap();
+
+    let output: Value = serde_json::from_str(&content(&response)).unwrap();
+    let validator = jsonschema::validator_for(&schema).unwrap();
+    assert!(validator.is_valid(&output), "{}", output);
+    assert_eq!(output["role"], "admin");
+    assert_eq!(output["age"], 18);
+    assert_eq!(output, sample_from_schema(&schema));
+
+    let mut json_mode = structured_request(schema);
+    json_mode.response_format = Some(ResponseFormat::JsonObject);
+    let response = MockProvider::new().complete(&json_mode).await.unwrap();
+    let output: Value = serde_json::from_str(&content(&response)).unwrap();
+    assert!(output["message"].is_string());
+}
+
+#[tokio::test]
+async fn fenced_or_mistyped_output_is_repaired_without_a_retry() {
+    let schema = json!({
+        "type": "object",
+        "properties": { "city": { "type": "string" }, "population": { "type": "integer" } },
+        "required": ["city", "population"]
+    });
+    let provider = ScriptedProvider::new(vec![
+        "Sure! Here it is:\n```json\n{\"city\": \"Oslo\", \"population\": \"709000\"}\n```",
+    ]);
+
+    let response = complete_structured(&provider, &structured_request(schema))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        serde_json::from_str::<Value>(&content(&response)).unwrap(),
+        json!({ "city": "Oslo", "population": 709000 })
+    );
+    assert_eq!(provider.prompts.lock().unwrap().len(), 1);
+    assert_eq!(
+        repair_output(&ResponseFormat::JsonObject, "no json here"),
+        None
+    );
+}
+
+#[tokio::test]
+async fn invalid_output_is_retried_with_feedback_then_rejected() {
+    let schema = json!({
+        "type": "object",
+        "properties": { "ok": { "type": "boolean" } },
+        "required": ["ok"]
+    });
+    let provider = ScriptedProvider::new(vec!["{\"done\": true}", "{\"ok\": true}"]);
+    let response = complete_structured(&provider, &structured_request(schema.clone()))
+        .await
+        .unwrap();
+
+    assert_eq!(content(&response), "{\"ok\": true}");
+    // The retry carries the rejected reply and the validation feedback
+    assert_eq!(*provider.prompts.lock().unwrap(), [1, 3]);
+    assert_eq!(response.usage.total_tokens, 30);
+
+    let provider = ScriptedProvider::new(Vec::new());
+    let error = complete_structured(&provider, &structured_request(schema))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ProviderError::InvalidResponse(_)));
+    assert_eq!(*provider.prompts.lock().unwrap(), [1, 3, 5]);
+}
+
+#[tokio::test]
+async fn router_validates_formats_and_checks_streamed_replies() {
+    let state = AppState::new(
+        AppConfig::default(),
+        Arc::new(ScriptedProvider::new(vec!["not json"])),
+        Default::default(),
+    );
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/chat/completions", addr);
+
+    // JSON mode needs the word "json" somewhere in the conversation
+    let body = json!({
+        "model": "gpt-4o",
+        "messages": [{ "role": "user", "content": "Describe a person" }],
+        "response_format": { "type": "json_object" }
+    });
+    let response = client.post(&url).json(&body).send().await.unwrap();
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["param"], "messages");
+
+    let mut body = serde_json::to_value(structured_request(json!({ "type": "nope" }))).unwrap();
+    let response = client.post(&url).json(&body).send().await.unwrap();
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(
+        error["error"]["param"],
+        "response_format.json_schema.schema"
+    );
+
+    body["response_format"]["json_schema"]["schema"] = json!({ "type": "object" });
+    body["stream"] = json!(true);
+    let text = client
+        .post(&url)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(text.contains("does not match response_format"), "{}", text);
+    assert!(text.ends_with("data: [DONE]\n\n"));
+}
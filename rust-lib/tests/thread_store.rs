@@ -0,0 +1,140 @@
+use redb::{Database, ReadableTableMetadata, TableDefinition};
+use serde_json::{json, Value};
+use shared_handlers::ai::{ChatMessage, Role};
+use shared_handlers::threads::{Parent, ThreadStore};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const THREADS: TableDefinition<&str, &[u8]> = TableDefinition::new("threads");
+const MESSAGES: TableDefinition<(&str, u64), &[u8]> = TableDefinition::new("thread_messages");
+
+fn store_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("thread-store-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir.join("threads.redb")
+}
+
+fn user(content: &str) -> ChatMessage {
+    ChatMessage {
+        role: Role::User,
+        content: Some(content.into()),
+        tool_calls: None,
+        tool_call_id: None,
+        name: None,
+    }
+}
+
+#[tokio::test]
+async fn concurrent_appends_are_all_persisted() {
+    let path = store_path("concurrent");
+    let id = {
+        let store = Arc::new(ThreadStore::open(&path).unwrap());
+        let thread = store.create(Default::default(), Vec::new()).await.unwrap();
+        let appends: Vec<_> = (0..20)
+            .map(|i| {
+                let store = store.clone();
+                let id = thread.id.clone();
+                tokio::spawn(async move {
+                    store
+                        .append(&id, &Parent::Root, vec![user(&format!("message {}", i))])
+                        .await
+                })
+            })
+            .collect();
+        for append in appends {
+            append.await.unwrap().unwrap().unwrap();
+        }
+        thread.id
+    };
+
+    let store = ThreadStore::open(&path).unwrap();
+    let first = store.branch(&id, None).unwrap().unwrap();
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].branch_count, 20);
+    let siblings = store.siblings(&id, &first[0].message.id).unwrap().unwrap();
+    let mut contents: Vec<String> = siblings
+        .iter()
+        .map(|message| message.message.content.as_ref().unwrap().to_text())
+        .collect();
+    contents.sort_by_key(|content| content[8..].parse::<u32>().unwrap());
+    assert_eq!(contents.first().unwrap(), "message 0");
+    assert_eq!(contents.last().unwrap(), "message 19");
+
+    drop(store);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn threads_stored_inline_are_migrated_to_message_records() {
+    let path = store_path("legacy");
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    {
+        // The layout before messages got their own table: one record per thread, no parents
+        let legacy = json!({
+            "thread": {
+                "id": "thread_legacy",
+                "object": "thread",
+                "created_at": 1,
+                "updated_at": 1
+            },
+            "messages": [
+                { "id": "msg_1", "object": "thread.message", "created_at": 1, "role": "user", "content": "Hi" },
+                { "id": "msg_2", "object": "thread.message", "created_at": 2, "role": "assistant", "content": "Hello" }
+            ]
+        });
+        let db = Database::create(&path).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(THREADS).unwrap();
+            let bytes = serde_json::to_vec(&legacy).unwrap();
+            table.insert("thread_legacy", bytes.as_slice()).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    let appended = {
+        let store = ThreadStore::open(&path).unwrap();
+        let branch = store.branch("thread_legacy", None).unwrap().unwrap();
+        let ids: Vec<&str> = branch.iter().map(|m| m.message.id.as_str()).collect();
+        assert_eq!(ids, ["msg_1", "msg_2"]);
+        assert_eq!(branch[1].message.parent_id.as_deref(), Some("msg_1"));
+        store
+            .append("thread_legacy", &Parent::Head, vec![user("Still here?")])
+            .await
+            .unwrap()
+            .unwrap()
+            .remove(0)
+    };
+
+    // The record keeps only the thread; every message is its own entry
+    {
+        let db = Database::create(&path).unwrap();
+        let txn = db.begin_read().unwrap();
+        let record: Value = serde_json::from_slice(
+            txn.open_table(THREADS)
+                .unwrap()
+                .get("thread_legacy")
+                .unwrap()
+                .unwrap()
+                .value(),
+        )
+        .unwrap();
+        assert!(record.get("messages").is_none());
+        assert_eq!(record["thread"]["head_id"], appended.id.as_str());
+        assert_eq!(txn.open_table(MESSAGES).unwrap().len().unwrap(), 3);
+    }
+
+    let store = ThreadStore::open(&path).unwrap();
+    let branch = store.branch("thread_legacy", None).unwrap().unwrap();
+    assert_eq!(branch.len(), 3);
+    assert_eq!(branch[2].message.parent_id.as_deref(), Some("msg_2"));
+    assert!(store.delete("thread_legacy").await.unwrap());
+    drop(store);
+
+    let db = Database::create(&path).unwrap();
+    let txn = db.begin_read().unwrap();
+    assert_eq!(txn.open_table(MESSAGES).unwrap().len().unwrap(), 0);
+    drop(txn);
+    drop(db);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,321 @@
+use serde_json::{json, Value};
+use shared_handlers::ai::{ChatMessage, Role};
+use shared_handlers::provider::MockProvider;
+use shared_handlers::threads::{Parent, ThreadStore, THREAD_MESSAGE_HEADER};
+use shared_handlers::{config::AppConfig, state::AppState};
+use std::sync::Arc;
+
+async fn spawn_app() -> String {
+    let state = AppState::new(
+        AppConfig::default(),
+        Arc::new(MockProvider::new()),
+        Default::default(),
+    );
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/threads", addr)
+}
+
+async fn send(request: reqwest::RequestBuilder) -> (u16, Value) {
+    let response = request.send().await.unwrap();
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+fn reply(completion: &Value) -> &str {
+    completion["choices"][0]["message"]["content"]
+        .as_str()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn threads_can_be_created_listed_appended_to_and_deleted() {
+    let url = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let (status, thread) = send(client.post(&url).json(&json!({
+        "metadata": { "title": "Trip planning" },
+        "messages": [{ "role": "system", "content": "You are a travel agent" }]
+    })))
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(thread["object"], "thread");
+    assert_eq!(thread["metadata"]["title"], "Trip planning");
+    let thread_url = format!("{}/{}", url, thread["id"].as_str().unwrap());
+
+    let (_, second) = send(client.post(&url).json(&json!({}))).await;
+    let (_, list) = send(client.get(&url)).await;
+    assert_eq!(list["object"], "list");
+    assert_eq!(list["data"].as_array().unwrap().len(), 2);
+    let (_, list) = send(client.get(format!("{}?limit=1", url))).await;
+    assert_eq!(list["data"].as_array().unwrap().len(), 1);
+    let (status, error) = send(client.get(format!("{}?limit=0", url))).await;
+    assert_eq!(status, 400);
+    assert_eq!(error["error"]["param"], "limit");
+
+    let (status, message) = send(
+        client
+            .post(format!("{}/messages", thread_url))
+            .json(&json!({ "role": "user", "content": "Find me a flight to Oslo" })),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(message["object"], "thread.message");
+    assert_eq!(message["role"], "user");
+    assert!(message["id"].as_str().unwrap().starts_with("msg_"));
+
+    let (_, messages) = send(client.get(format!("{}/messages", thread_url))).await;
+    let roles: Vec<&str> = messages["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["role"].as_str().unwrap())
+        .collect();
+    assert_eq!(roles, ["system", "user"]);
+
+    let (status, deleted) = send(client.delete(&thread_url)).await;
+    assert_eq!(status, 200);
+    assert_eq!(deleted["deleted"], true);
+    let (status, error) = send(client.get(&thread_url)).await;
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["code"], "thread_not_found");
+    let (_, list) = send(client.get(&url)).await;
+    assert_eq!(list["data"][0]["id"], second["id"]);
+}
+
+#[tokio::test]
+async fn runs_complete_against_the_stored_history() {
+    let url = spawn_app().await;
+    let client = reqwest::Client::new();
+    let (_, thread) = send(client.post(&url).json(&json!({
+        "messages": [{ "role": "user", "content": "Hello there" }]
+    })))
+    .await;
+    let thread_url = format!("{}/{}", url, thread["id"].as_str().unwrap());
+
+    let (status, completion) = send(
+        client
+            .post(format!("{}/runs", thread_url))
+            .json(&json!({ "model": "gpt-4" })),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(reply(&completion).contains("You sent 1 messages"));
+
+    // New messages are appended before the run; the history now holds the first reply too
+    let (_, completion) = send(client.post(format!("{}/runs", thread_url)).json(&json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": "And again" }]
+    })))
+    .await;
+    assert!(reply(&completion).contains("You sent 3 messages"));
+
+    // Failed runs leave the thread untouched
+    let (status, _) = send(client.post(format!("{}/runs", thread_url)).json(
+        &json!({ "model": "no-such-model", "messages": [{ "role": "user", "content": "x" }] }),
+    ))
+    .await;
+    assert_eq!(status, 404);
+
+    let (_, messages) = send(client.get(format!("{}/messages", thread_url))).await;
+    let roles: Vec<&str> = messages["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["role"].as_str().unwrap())
+        .collect();
+    assert_eq!(roles, ["user", "assistant", "user", "assistant"]);
+
+    let (status, error) = send(
+        client
+            .post(format!("{}/thread_missing/runs", url))
+            .json(&json!({ "model": "gpt-4" })),
+    )
+    .await;
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["code"], "thread_not_found");
+}
+
+fn ids(messages: &Value) -> Vec<&str> {
+    messages["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["id"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn edits_and_regenerations_branch_the_conversation() {
+    let url = spawn_app().await;
+    let client = reqwest::Client::new();
+    let (_, thread) = send(client.post(&url).json(&json!({}))).await;
+    let thread_url = format!("{}/{}", url, thread["id"].as_str().unwrap());
+    let run = |body: Value| {
+        client
+            .post(format!("{}/runs", thread_url))
+            .json(&body)
+            .send()
+    };
+
+    let first = run(json!({ "model": "gpt-4", "messages": [{ "role": "user", "content": "Hi" }] }))
+        .await
+        .unwrap();
+    let first_reply = first.headers()["x-thread-message-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let (_, branch) = send(client.get(format!("{}/messages", thread_url))).await;
+    let question = ids(&branch)[0].to_string();
+    assert_eq!(ids(&branch), [question.as_str(), first_reply.as_str()]);
+    assert_eq!(branch["data"][1]["parent_id"], question);
+
+    // Regenerate: run again from the user message, giving the reply a sibling
+    let second = run(json!({ "model": "gpt-4", "parent_id": question }))
+        .await
+        .unwrap();
+    let second_reply = second.headers()["x-thread-message-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let (_, branch) = send(client.get(format!("{}/messages", thread_url))).await;
+    assert_eq!(ids(&branch), [question.as_str(), second_reply.as_str()]);
+    assert_eq!(branch["data"][1]["branch_index"], 1);
+    assert_eq!(branch["data"][1]["branch_count"], 2);
+
+    let (_, branches) =
+        send(client.get(format!("{}/messages/{}/branches", thread_url, first_reply))).await;
+    assert_eq!(
+        ids(&branches),
+        [first_reply.as_str(), second_reply.as_str()]
+    );
+
+    // Edit the first user message: a new root with its own reply
+    let edited = run(json!({
+        "model": "gpt-4",
+        "parent_id": null,
+        "messages": [{ "role": "user", "content": "Hello, edited" }]
+    }))
+    .await
+    .unwrap();
+    assert_eq!(edited.status(), 200);
+    let (_, branch) = send(client.get(format!("{}/messages", thread_url))).await;
+    assert_eq!(branch["data"][0]["content"], "Hello, edited");
+    assert_eq!(branch["data"][0]["branch_count"], 2);
+    assert!(branch["data"][1]["content"]
+        .as_str()
+        .unwrap()
+        .contains("You sent 1 messages"));
+
+    // The original branch is still there, followed to its newest reply
+    let (_, original) =
+        send(client.get(format!("{}/messages?message_id={}", thread_url, question))).await;
+    assert_eq!(ids(&original), [question.as_str(), second_reply.as_str()]);
+
+    let (status, error) = send(
+        client
+            .post(format!("{}/runs", thread_url))
+            .json(&json!({ "model": "gpt-4", "parent_id": "msg_missing" })),
+    )
+    .await;
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["code"], "message_not_found");
+}
+
+#[tokio::test]
+async fn streamed_runs_store_the_reply_once_the_stream_finishes() {
+    let url = spawn_app().await;
+    let client = reqwest::Client::new();
+    let (_, thread) = send(client.post(&url).json(&json!({
+        "messages": [{ "role": "user", "content": "Hello there" }]
+    })))
+    .await;
+    let thread_url = format!("{}/{}", url, thread["id"].as_str().unwrap());
+
+    let response = client
+        .post(format!("{}/runs", thread_url))
+        .json(&json!({
+            "model": "gpt-4",
+            "stream": true,
+            "messages": [{ "role": "user", "content": "Stream this" }]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/event-stream"));
+    let reply_id = response.headers()[THREAD_MESSAGE_HEADER]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let body = response.text().await.unwrap();
+    assert!(body.trim_end().ends_with("data: [DONE]"));
+    let streamed: String = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|data| *data != "[DONE]")
+        .map(|data| serde_json::from_str::<Value>(data).unwrap())
+        .filter_map(|chunk| {
+            chunk["choices"][0]["delta"]["content"]
+                .as_str()
+                .map(str::to_string)
+        })
+        .collect();
+    assert!(streamed.contains("You sent 2 messages"));
+
+    let (_, messages) = send(client.get(format!("{}/messages", thread_url))).await;
+    let data = messages["data"].as_array().unwrap();
+    assert_eq!(data.len(), 3);
+    assert_eq!(data[1]["content"], "Stream this");
+    assert_eq!(data[2]["id"], reply_id.as_str());
+    assert_eq!(data[2]["role"], "assistant");
+    assert_eq!(data[2]["content"], streamed.as_str());
+    assert_eq!(data[2]["parent_id"], data[1]["id"]);
+}
+
+#[tokio::test]
+async fn threads_survive_reopening_the_store() {
+    let path = std::env::temp_dir()
+        .join(format!("threads-test-{}", std::process::id()))
+        .join("threads.redb");
+    let message = ChatMessage {
+        role: Role::User,
+        content: Some("Remember me".into()),
+        tool_calls: None,
+        tool_call_id: None,
+        name: None,
+    };
+
+    let id = {
+        let store = ThreadStore::open(&path).unwrap();
+        let thread = store.create(Default::default(), Vec::new()).await.unwrap();
+        store
+            .append(&thread.id, &Parent::Head, vec![message])
+            .await
+            .unwrap()
+            .unwrap();
+        store.create(Default::default(), Vec::new()).await.unwrap();
+        thread.id
+    };
+
+    let store = ThreadStore::open(&path).unwrap();
+    assert_eq!(store.list(10).len(), 2);
+    let messages = store.branch(&id, None).unwrap().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].message.message.role, Role::User);
+    assert_eq!(
+        store.get(&id).unwrap().head_id,
+        Some(messages[0].message.id.clone())
+    );
+    assert!(store.delete(&id).await.unwrap());
+    assert!(store.get(&id).is_none());
+
+    drop(store);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,394 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use shared_handlers::ai::{ChatCompletionRequest, ChatCompletionResponse, FinishReason};
+use shared_handlers::provider::{
+    ChatProvider, MockProvider, OpenAiConfig, OpenAiProvider, ProviderError,
+};
+use std::sync::{Arc, Mutex};
+
+fn request(content: &str, tool_choice: Value) -> ChatCompletionRequest {
+    let mut body = json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": content }],
+        "tools": [
+            {
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "description": "Current weather for a city",
+                    "parameters": {
+                        "type": "object",
+                        "properties": { "city": { "type": "string" } },
+                        "required": ["city"]
+                    }
+                }
+            },
+            {
+                "type": "function",
+                "function": {
+                    "name": "search_web",
+                    "description": "Search the web",
+                    "parameters": {
+                        "type": "object",
+                        "properties": { "query": { "type": "string" } },
+                        "required": ["query"]
+                    }
+                }
+            }
+        ]
+    });
+    if !tool_choice.is_null() {
+        body["tool_choice"] = tool_choice;
+    }
+    serde_json::from_value(body).unwrap()
+}
+
+fn named(name: &str) -> Value {
+    json!({ "type": "function", "function": { "name": name } })
+}
+
+fn called_tools(response: &ChatCompletionResponse) -> Vec<String> {
+    response.choices[0]
+        .message
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|call| call.function.name.clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn mock_auto_uses_keyword_heuristic() {
+    let response = MockProvider::new()
+        .complete(&request("search for rust news", Value::Null))
+        .await
+        .unwrap();
+    assert_eq!(called_tools(&response), vec!["search_web"]);
+    assert_eq!(response.choices[0].finish_reason, FinishReason::ToolCalls);
+}
+
+#[tokio::test]
+async fn mock_none_suppresses_tool_calls() {
+    let response = MockProvider::new()
+        .complete(&request("search for rust news", json!("none")))
+        .await
+        .unwrap();
+    assert!(called_tools(&response).is_empty());
+    assert!(response.choices[0].message.content.is_some());
+    assert_eq!(response.choices[0].finish_reason, FinishReason::Stop);
+}
+
+#[tokio::test]
+async fn mock_required_forces_a_tool_call() {
+    let response = MockProvider::new()
+        .complete(&request("Paris", json!("required")))
+        .await
+        .unwrap();
+    assert_eq!(called_tools(&response), vec!["get_weather"]);
+    let arguments: Value = serde_json::from_str(
+        &response.choices[0].message.tool_calls.as_ref().unwrap()[0]
+            .function
+            .arguments,
+    )
+    .unwrap();
+    assert_eq!(arguments, json!({ "city": "Paris" }));
+    assert_eq!(response.choices[0].finish_reason, FinishReason::ToolCalls);
+}
+
+#[tokio::test]
+async fn mock_named_function_forces_that_tool() {
+    let response = MockProvider::new()
+        .complete(&request("search for the weather", named("get_weather")))
+        .await
+        .unwrap();
+    assert_eq!(called_tools(&response), vec!["get_weather"]);
+}
+
+#[tokio::test]
+async fn mock_emits_parallel_tool_calls_unless_disabled() {
+    let mut request = request("search the docs and read the file", Value::Null);
+    request.tools.as_mut().unwrap()[0].function.name = "read_file".to_string();
+
+    let response = MockProvider::new().complete(&request).await.unwrap();
+    assert_eq!(called_tools(&response), vec!["search_web", "read_file"]);
+    let ids: Vec<_> = response.choices[0]
+        .message
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|call| call.id.clone())
+        .collect();
+    assert_ne!(ids[0], ids[1]);
+
+    request.parallel_tool_calls = Some(false);
+    let response = MockProvider::new().complete(&request).await.unwrap();
+    assert_eq!(called_tools(&response), vec!["search_web"]);
+}
+
+// Upstream stub that ignores tool_choice and always answers with `message`
+type Captured = Arc<Mutex<Option<Value>>>;
+
+async fn spawn_upstream(message: Value, captured: Captured) -> OpenAiProvider {
+    async fn completions(
+        State((message, captured)): State<(Value, Captured)>,
+        Json(body): Json<Value>,
+    ) -> Response {
+        let streaming = body["stream"] == json!(true);
+        *captured.lock().unwrap() = Some(body);
+        let finish = if message["tool_calls"].is_array() {
+            "tool_calls"
+        } else {
+            "stop"
+        };
+
+        if !streaming {
+            return Json(json!({
+                "id": "chatcmpl-upstream",
+                "object": "chat.completion",
+                "created": 1700000000,
+                "model": "gpt-4",
+                "choices": [{ "index": 0, "message": message, "finish_reason": finish }],
+                "usage": { "prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8 }
+            }))
+            .into_response();
+        }
+
+        let chunk = |delta: Value, finish: Value| {
+            json!({
+                "id": "chatcmpl-upstream",
+                "object": "chat.completion.chunk",
+                "created": 1700000000,
+                "model": "gpt-4",
+                "choices": [{ "index": 0, "delta": delta, "finish_reason": finish }]
+            })
+        };
+        let mut events = vec![chunk(json!({ "role": "assistant" }), Value::Null)];
+        for (index, call) in message["tool_calls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            events.push(chunk(
+                json!({ "tool_calls": [{
+                    "index": index,
+                    "id": call["id"],
+                    "type": "function",
+                    "function": { "name": call["function"]["name"], "arguments": "" }
+                }]}),
+                Value::Null,
+            ));
+            events.push(chunk(
+                json!({ "tool_calls": [{
+                    "index": index,
+                    "function": { "arguments": call["function"]["arguments"] }
+                }]}),
+                Value::Null,
+            ));
+        }
+        events.push(chunk(json!({}), json!(finish)));
+
+        let mut body: String = events
+            .iter()
+            .map(|event| format!("data: {}\n\n", event))
+            .collect();
+        body.push_str("data: [DONE]\n\n");
+        ([("content-type", "text/event-stream")], body).into_response()
+    }
+
+    let app = Router::new()
+        .route("/v1/chat/completions", post(completions))
+        .with_state((message, captured));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    OpenAiProvider::new(OpenAiConfig::new(format!("http://{}/v1", addr)))
+}
+
+fn two_tool_calls() -> Value {
+    json!({
+        "role": "assistant",
+        "content": null,
+        "tool_calls": [
+            {
+                "id": "call_search",
+                "type": "function",
+                "function": { "name": "search_web", "arguments": "{\"query\":\"rust\"}" }
+            },
+            {
+                "id": "call_weather",
+                "type": "function",
+                "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+            }
+        ]
+    })
+}
+
+#[tokio::test]
+async fn upstream_none_drops_tool_calls() {
+    let captured: Captured = Arc::default();
+    let provider = spawn_upstream(two_tool_calls(), captured.clone()).await;
+
+    let response = provider
+        .complete(&request("search for rust news", json!("none")))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        captured.lock().unwrap().as_ref().unwrap()["tool_choice"],
+        "none"
+    );
+    assert!(called_tools(&response).is_empty());
+    assert!(response.choices[0]
+        .message
+        .content
+        .as_ref()
+        .unwrap()
+        .is_empty());
+    assert_eq!(response.choices[0].finish_reason, FinishReason::Stop);
+}
+
+#[tokio::test]
+async fn upstream_required_without_tool_call_is_an_error() {
+    let text = json!({ "role": "assistant", "content": "No tools needed" });
+    let provider = spawn_upstream(text, Arc::default()).await;
+
+    let error = provider
+        .complete(&request("Paris", json!("required")))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ProviderError::ToolChoice(_)));
+}
+
+#[tokio::test]
+async fn upstream_named_function_keeps_only_that_tool() {
+    let captured: Captured = Arc::default();
+    let provider = spawn_upstream(two_tool_calls(), captured.clone()).await;
+
+    let response = provider
+        .complete(&request("weather?", named("get_weather")))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        captured.lock().unwrap().as_ref().unwrap()["tool_choice"],
+        named("get_weather")
+    );
+    assert_eq!(called_tools(&response), vec!["get_weather"]);
+    assert_eq!(response.choices[0].finish_reason, FinishReason::ToolCalls);
+}
+
+#[tokio::test]
+async fn upstream_parallel_tool_calls_false_keeps_first_call() {
+    let captured: Captured = Arc::default();
+    let provider = spawn_upstream(two_tool_calls(), captured.clone()).await;
+
+    let mut request = request("search for rust news", Value::Null);
+    request.parallel_tool_calls = Some(false);
+    let response = provider.complete(&request).await.unwrap();
+
+    assert_eq!(
+        captured.lock().unwrap().as_ref().unwrap()["parallel_tool_calls"],
+        false
+    );
+    assert_eq!(called_tools(&response), vec!["search_web"]);
+
+    request.stream = Some(true);
+    let chunks: Vec<_> = provider
+        .complete_stream(&request)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    let deltas: Vec<_> = chunks
+        .iter()
+        .flat_map(|chunk| chunk.choices[0].delta.tool_calls.iter().flatten())
+        .collect();
+    assert!(deltas.iter().all(|delta| delta.index == 0));
+    assert_eq!(
+        deltas[0].function.as_ref().unwrap().name.as_deref(),
+        Some("search_web")
+    );
+    assert_eq!(
+        chunks.last().unwrap().choices[0]
+            .finish_reason
+            .as_ref()
+            .map(FinishReason::as_str),
+        Some("tool_calls")
+    );
+}
+
+#[tokio::test]
+async fn upstream_stream_honors_each_mode() {
+    let provider = spawn_upstream(two_tool_calls(), Arc::default()).await;
+    let collect = |tool_choice: Value| {
+        let mut request = request("weather?", tool_choice);
+        request.stream = Some(true);
+        let provider = &provider;
+        async move {
+            provider
+                .complete_stream(&request)
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+        }
+    };
+
+    // Named: only get_weather survives, re-indexed to 0
+    let chunks: Vec<_> = collect(named("get_weather"))
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+    let deltas: Vec<_> = chunks
+        .iter()
+        .flat_map(|chunk| chunk.choices[0].delta.tool_calls.iter().flatten())
+        .collect();
+    assert_eq!(deltas.len(), 2);
+    assert!(deltas.iter().all(|delta| delta.index == 0));
+    assert_eq!(
+        deltas[0].function.as_ref().unwrap().name.as_deref(),
+        Some("get_weather")
+    );
+    assert_eq!(
+        chunks.last().unwrap().choices[0]
+            .finish_reason
+            .as_ref()
+            .map(FinishReason::as_str),
+        Some("tool_calls")
+    );
+
+    // None: every tool call delta is dropped and the stream finishes with "stop"
+    let chunks: Vec<_> = collect(json!("none"))
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+    assert!(chunks
+        .iter()
+        .all(|chunk| chunk.choices[0].delta.tool_calls.is_none()));
+    assert_eq!(
+        chunks.last().unwrap().choices[0]
+            .finish_reason
+            .as_ref()
+            .map(FinishReason::as_str),
+        Some("stop")
+    );
+
+    // Named tool the upstream never calls: the stream ends with an error
+    let items = collect(named("unknown_tool")).await;
+    assert!(matches!(
+        items.last().unwrap(),
+        Err(ProviderError::ToolChoice(_))
+    ));
+}
@@ -0,0 +1,376 @@
+use axum::{
+    body::Body, extract::State, response::IntoResponse, response::Response, routing::post, Json,
+    Router,
+};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use shared_handlers::ai::ChatCompletionRequest;
+use shared_handlers::config::{AppConfig, ConfigError};
+use shared_handlers::provider::{ChatProvider, OpenAiConfig, OpenAiProvider, ProviderError};
+use shared_handlers::state::AppState;
+use shared_handlers::upstream::{FallbackProvider, ResilientProvider, RetryPolicy, UpstreamConfig};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// What the stub answers on each hit; the last entry repeats
+#[derive(Clone, Copy)]
+enum Reply {
+    Ok,
+    Fail(u16),
+    RetryAfter(u64),
+    Slow(u64),
+    // Streams open and send one chunk, then stall for this many ms
+    StallStream(u64),
+}
+
+struct Stub {
+    base_url: String,
+    hits: Arc<AtomicUsize>,
+}
+
+impl Stub {
+    fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    fn provider(&self, name: &str) -> Arc<dyn ChatProvider> {
+        Arc::new(OpenAiProvider::new(
+            OpenAiConfig::new(self.base_url.clone()).with_name(name),
+        ))
+    }
+}
+
+async fn spawn_stub(script: Vec<Reply>) -> Stub {
+    type StubState = (Arc<AtomicUsize>, Arc<Vec<Reply>>);
+
+    async fn completions(
+        State((hits, script)): State<StubState>,
+        Json(body): Json<Value>,
+    ) -> Response {
+        let hit = hits.fetch_add(1, Ordering::SeqCst);
+        let reply = script
+            .get(hit)
+            .or(script.last())
+            .copied()
+            .unwrap_or(Reply::Ok);
+        let mut stall = None;
+        match reply {
+            Reply::Fail(status) => {
+                let status = axum::http::StatusCode::from_u16(status).unwrap();
+                return (status, "injected failure").into_response();
+            }
+            Reply::RetryAfter(seconds) => {
+                let headers = [("retry-after", seconds.to_string())];
+                return (
+                    axum::http::StatusCode::TOO_MANY_REQUESTS,
+                    headers,
+                    "slow down",
+                )
+                    .into_response();
+            }
+            Reply::Slow(ms) => tokio::time::sleep(Duration::from_millis(ms)).await,
+            Reply::StallStream(ms) => stall = Some(Duration::from_millis(ms)),
+            Reply::Ok => {}
+        }
+
+        if body["stream"] == true {
+            let chunk = json!({
+                "id": "chatcmpl-stub",
+                "object": "chat.completion.chunk",
+                "created": 1700000000,
+                "model": body["model"],
+                "choices": [{ "index": 0, "delta": { "role": "assistant", "content": "Hello from stub" }, "finish_reason": "stop" }]
+            });
+            let first = format!("data: {}\n\n", chunk);
+            let Some(stall) = stall else {
+                let body = format!("{}data: [DONE]\n\n", first);
+                return ([("content-type", "text/event-stream")], body).into_response();
+            };
+            let parts = futures::stream::once(async move { Ok::<_, Infallible>(first) }).chain(
+                futures::stream::once(async move {
+                    tokio::time::sleep(stall).await;
+                    Ok("data: [DONE]\n\n".to_string())
+                }),
+            );
+            return (
+                [("content-type", "text/event-stream")],
+                Body::from_stream(parts),
+            )
+                .into_response();
+        }
+        Json(json!({
+            "id": "chatcmpl-stub",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": body["model"],
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hello from stub" },
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8 }
+        }))
+        .into_response()
+    }
+
+    let hits = Arc::new(AtomicUsize::new(0));
+    let app = Router::new()
+        .route("/v1/chat/completions", post(completions))
+        .with_state((hits.clone(), Arc::new(script)));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    Stub {
+        base_url: format!("http://{}/v1", addr),
+        hits,
+    }
+}
+
+fn request() -> ChatCompletionRequest {
+    serde_json::from_value(json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": "Hi" }]
+    }))
+    .unwrap()
+}
+
+fn quick_retries(max_retries: u32) -> RetryPolicy {
+    RetryPolicy {
+        max_retries,
+        initial_backoff_ms: 10,
+        max_backoff_ms: 5_000,
+    }
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried_honoring_retry_after() {
+    let flaky = spawn_stub(vec![Reply::Fail(503), Reply::RetryAfter(1), Reply::Ok]).await;
+    let provider = ResilientProvider::new(flaky.provider("flaky")).with_retry(quick_retries(2));
+
+    let started = Instant::now();
+    let response = provider.complete(&request()).await.unwrap();
+    assert_eq!(response.choices.len(), 1);
+    assert_eq!(flaky.hits(), 3);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+
+    // Caller errors are not retried
+    let rejecting = spawn_stub(vec![Reply::Fail(400)]).await;
+    let provider =
+        ResilientProvider::new(rejecting.provider("rejecting")).with_retry(quick_retries(2));
+    let error = provider.complete(&request()).await.unwrap_err();
+    assert!(matches!(error, ProviderError::Status { status: 400, .. }));
+    assert_eq!(rejecting.hits(), 1);
+
+    // A Retry-After beyond the backoff cap gives up instead of stalling
+    let limited = spawn_stub(vec![Reply::RetryAfter(60)]).await;
+    let provider = ResilientProvider::new(limited.provider("limited")).with_retry(quick_retries(2));
+    let error = provider.complete(&request()).await.unwrap_err();
+    assert_eq!(error.retry_after(), Some(Duration::from_secs(60)));
+    assert_eq!(limited.hits(), 1);
+}
+
+#[tokio::test]
+async fn timeouts_fall_back_to_the_next_provider() {
+    let slow = spawn_stub(vec![Reply::Slow(2_000)]).await;
+    let backup = spawn_stub(vec![Reply::Ok]).await;
+    let primary = ResilientProvider::new(slow.provider("primary"))
+        .with_timeout(Duration::from_millis(200))
+        .with_retry(quick_retries(0));
+    let fallback = FallbackProvider::new(vec![Arc::new(primary), backup.provider("backup")]);
+
+    let started = Instant::now();
+    let response = fallback.complete(&request()).await.unwrap();
+    assert_eq!(response.served_by.as_deref(), Some("backup"));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!((slow.hits(), backup.hits()), (1, 1));
+
+    let empty = FallbackProvider::new(Vec::new());
+    assert!(matches!(
+        empty.complete(&request()).await,
+        Err(ProviderError::Unavailable(_))
+    ));
+}
+
+#[tokio::test]
+async fn streams_that_stall_midway_end_with_a_timeout() {
+    let stalling = spawn_stub(vec![Reply::StallStream(5_000)]).await;
+    let provider = ResilientProvider::new(stalling.provider("stalling"))
+        .with_timeout(Duration::from_millis(200))
+        .with_retry(quick_retries(0));
+    let mut request = request();
+    request.stream = Some(true);
+
+    let started = Instant::now();
+    let mut chunks = provider.complete_stream(&request).await.unwrap();
+    assert!(chunks.next().await.unwrap().is_ok());
+    let error = chunks.next().await.unwrap().unwrap_err();
+    assert!(matches!(error, ProviderError::Timeout(_)), "{}", error);
+    assert!(chunks.next().await.is_none());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn open_circuits_skip_the_provider_until_a_probe_succeeds() {
+    let failing = spawn_stub(vec![Reply::Fail(500), Reply::Fail(500), Reply::Ok]).await;
+    let backup = spawn_stub(vec![Reply::Ok]).await;
+    let primary = Arc::new(
+        ResilientProvider::new(failing.provider("primary"))
+            .with_retry(quick_retries(0))
+            .with_circuit_breaker(2, Duration::from_millis(300)),
+    );
+    let fallback = FallbackProvider::new(vec![primary.clone(), backup.provider("backup")]);
+
+    for _ in 0..3 {
+        let response = fallback.complete(&request()).await.unwrap();
+        assert_eq!(response.served_by.as_deref(), Some("backup"));
+    }
+    // The third request never reached the primary
+    assert_eq!(failing.hits(), 2);
+    assert!(primary.circuit_open());
+
+    tokio::time::sleep(Duration::from_millis(350)).await;
+    let response = fallback.complete(&request()).await.unwrap();
+    assert_eq!(response.served_by.as_deref(), Some("primary"));
+    assert_eq!(failing.hits(), 3);
+    assert!(!primary.circuit_open());
+}
+
+#[tokio::test]
+async fn configured_chains_are_picked_by_alias_and_reported() {
+    let hosted = spawn_stub(vec![Reply::Fail(503)]).await;
+    let local = spawn_stub(vec![Reply::Ok]).await;
+    let dir = std::env::temp_dir().join(format!("upstream-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("providers.yaml");
+    std::fs::write(
+        &path,
+        format!(
+            "providers:\n  hosted:\n    base_url: {}\n    api_key: ${{UPSTREAM_TEST_UNSET_KEY}}\n    retry:\n      max_retries: 0\n  local:\n    base_url: {}\nchains:\n  default: [hosted, local]\ndefault_chain: [hosted]\n",
+            hosted.base_url, local.base_url
+        ),
+    )
+    .unwrap();
+    let provider = UpstreamConfig::load(&path).unwrap().build();
+
+    let state = AppState::new(AppConfig::default(), provider, Default::default());
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/chat/completions", addr);
+
+    // "default" resolves to another model id, but its alias picks the chain
+    let body = json!({ "model": "default", "messages": [{ "role": "user", "content": "Hi" }] });
+    let response = client.post(&url).json(&body).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["x-served-by"], "local");
+    let completion: Value = response.json().await.unwrap();
+    assert_eq!(completion["served_by"], "local");
+
+    let mut body = body;
+    body["stream"] = json!(true);
+    let response = client.post(&url).json(&body).send().await.unwrap();
+    assert_eq!(response.headers()["x-served-by"], "local");
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("\"served_by\":\"local\""));
+    assert_eq!(hosted.hits(), 2);
+
+    // Other models use the default chain, whose only provider is down
+    let body = json!({ "model": "gpt-4", "messages": [{ "role": "user", "content": "Hi" }] });
+    let response = client.post(&url).json(&body).send().await.unwrap();
+    assert_eq!(response.status(), 502);
+
+    std::fs::write(
+        &path,
+        "providers:\n  local:\n    base_url: http://localhost:11434/v1\nchains:\n  default: [local, missing]\ndefault_chain: []\n",
+    )
+    .unwrap();
+    let ConfigError::Validation(issues) = UpstreamConfig::load(&path).unwrap_err() else {
+        panic!("expected validation issues");
+    };
+    let paths: Vec<&str> = issues.iter().map(|issue| issue.path.as_str()).collect();
+    assert_eq!(paths, ["chains.default[1]", "default_chain"]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn agent_endpoints_report_the_fallback_that_served_each_step() {
+    let hosted = spawn_stub(vec![Reply::Fail(503)]).await;
+    let local = spawn_stub(vec![Reply::Ok]).await;
+    let fallback = FallbackProvider::new(vec![hosted.provider("hosted"), local.provider("local")]);
+    let state = AppState::new(AppConfig::default(), Arc::new(fallback), Default::default());
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+
+    let body = json!({ "messages": [{ "role": "user", "content": "Hi" }] });
+    let text = client
+        .post(format!("http://{}/chat/data-stream", addr))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        text.lines()
+            .any(|line| line == r#"8:[{"served_by":"local"}]"#),
+        "{}",
+        text
+    );
+
+    let input = json!({
+        "threadId": "thread-1",
+        "runId": "run-1",
+        "messages": [{ "id": "m1", "role": "user", "content": "Hi" }]
+    });
+    let text = client
+        .post(format!("http://{}/agui", addr))
+        .json(&input)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let deltas: Vec<Value> = text
+        .split("\n\n")
+        .filter_map(|event| event.strip_prefix("data: "))
+        .map(|data| serde_json::from_str::<Value>(data).unwrap())
+        .filter(|event| event["type"] == "STATE_DELTA")
+        .collect();
+    let served_by = &deltas.last().unwrap()["delta"][1];
+    assert_eq!(served_by["path"], "/agent/served_by");
+    assert_eq!(served_by["value"], "local");
+    assert_eq!(hosted.hits(), 2);
+}
+
+#[test]
+fn the_example_providers_file_falls_back_from_openai_to_ollama() {
+    let path = std::path::Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../config/providers.example.yaml"
+    ));
+    let config = UpstreamConfig::load(path).unwrap();
+
+    assert_eq!(config.default_chain, ["openai", "ollama"]);
+    let ollama = &config.providers["ollama"];
+    assert_eq!(
+        ollama.base_url.as_deref(),
+        Some("http://localhost:11434/v1")
+    );
+    assert_eq!(ollama.models["gpt-4o-mini"], "llama3.1:8b");
+    assert_eq!(
+        config.providers["openai"].base_url.as_deref(),
+        Some("https://api.openai.com/v1")
+    );
+}
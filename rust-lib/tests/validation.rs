@@ -0,0 +1,204 @@
+use serde_json::{json, Value};
+use shared_handlers::ai::ChatCompletionRequest;
+use shared_handlers::error::AiError;
+use shared_handlers::mcp::{McpRegistry, McpServer, McpServerStatus, McpTool};
+use shared_handlers::provider::MockProvider;
+use shared_handlers::{config::AppConfig, state::AppState};
+use std::sync::Arc;
+
+fn request(body: Value) -> ChatCompletionRequest {
+    let mut request = json!({
+        "model": "gpt-4",
+        "messages": [{ "role": "user", "content": "Hello there" }]
+    });
+    request
+        .as_object_mut()
+        .unwrap()
+        .extend(body.as_object().unwrap().clone());
+    serde_json::from_value(request).unwrap()
+}
+
+// The `param` an invalid request is blamed on
+fn param(body: Value) -> Option<String> {
+    match request(body).validate() {
+        Err(AiError::InvalidRequest { param, .. }) | Err(AiError::UnknownTool { param, .. }) => {
+            param
+        }
+        other => panic!("expected an invalid request, got {:?}", other.map(|_| ())),
+    }
+}
+
+fn tool(name: &str) -> Value {
+    json!({
+        "type": "function",
+        "function": { "name": name, "description": "", "parameters": { "type": "object" } }
+    })
+}
+
+#[test]
+fn messages_are_checked_in_order_with_their_index() {
+    assert_eq!(
+        param(json!({ "messages": [] })).as_deref(),
+        Some("messages")
+    );
+    assert_eq!(
+        param(json!({ "messages": [{ "role": "user", "content": "" }] })).as_deref(),
+        Some("messages[0].content")
+    );
+    assert_eq!(
+        param(json!({ "messages": [
+            { "role": "user", "content": "Hi" },
+            { "role": "assistant" }
+        ] }))
+        .as_deref(),
+        Some("messages[1].content")
+    );
+    assert_eq!(
+        param(json!({ "messages": [
+            { "role": "user", "content": "Hi" },
+            { "role": "tool", "tool_call_id": "call_1", "content": "42" }
+        ] }))
+        .as_deref(),
+        Some("messages[1].tool_call_id")
+    );
+    assert_eq!(
+        param(json!({ "messages": [{ "role": "system", "content": [
+            { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } }
+        ] }] }))
+        .as_deref(),
+        Some("messages[0].content[0].type")
+    );
+    assert_eq!(
+        param(json!({ "messages": [{ "role": "user", "content": [
+            { "type": "image_url", "image_url": { "url": "data:image/png;base64,???" } }
+        ] }] }))
+        .as_deref(),
+        Some("messages[0].content[0].image_url.url")
+    );
+
+    // Tool results answering an earlier assistant call are fine
+    assert!(request(json!({ "messages": [
+        { "role": "user", "content": "Hi" },
+        { "role": "assistant", "tool_calls": [
+            { "id": "call_1", "type": "function", "function": { "name": "echo", "arguments": "{}" } }
+        ] },
+        { "role": "tool", "tool_call_id": "call_1", "content": "42" }
+    ] }))
+    .validate()
+    .is_ok());
+}
+
+#[test]
+fn tools_and_tool_choice_must_agree() {
+    assert_eq!(
+        param(json!({ "tools": [tool("ok"), tool("lookup.v2")] })).as_deref(),
+        Some("tools[1].function.name")
+    );
+    assert_eq!(
+        param(json!({ "tools": [tool("echo"), tool("echo")] })).as_deref(),
+        Some("tools[1].function.name")
+    );
+    assert_eq!(
+        param(json!({ "tool_choice": "required" })).as_deref(),
+        Some("tool_choice")
+    );
+    assert_eq!(
+        param(json!({ "tool_choice": "sometimes", "tools": [tool("echo")] })).as_deref(),
+        Some("tool_choice")
+    );
+    assert_eq!(
+        param(json!({
+            "tools": [tool("echo")],
+            "tool_choice": { "type": "function", "function": { "name": "search" } }
+        }))
+        .as_deref(),
+        Some("tool_choice.function.name")
+    );
+    assert!(request(json!({
+        "tools": [tool("echo")],
+        "tool_choice": { "type": "function", "function": { "name": "echo" } }
+    }))
+    .validate()
+    .is_ok());
+}
+
+#[test]
+fn response_formats_are_checked() {
+    assert_eq!(
+        param(json!({ "response_format": { "type": "json_object" } })).as_deref(),
+        Some("messages")
+    );
+    assert_eq!(
+        param(
+            json!({ "response_format": { "type": "json_schema", "json_schema": {
+            "name": "reply", "schema": { "type": 12 }
+        } } })
+        )
+        .as_deref(),
+        Some("response_format.json_schema.schema")
+    );
+    assert!(request(json!({
+        "messages": [{ "role": "user", "content": "Answer in JSON" }],
+        "response_format": { "type": "json_object" }
+    }))
+    .validate()
+    .is_ok());
+}
+
+#[tokio::test]
+async fn injected_mcp_tools_are_not_validated_as_client_tools() {
+    // Live servers may report names clients could not send themselves
+    let mut registry = McpRegistry::new();
+    registry.register_server(McpServer {
+        name: "legacy".to_string(),
+        description: "Legacy tools".to_string(),
+        version: "1.0.0".to_string(),
+        tools: vec![McpTool {
+            name: "lookup.v2".to_string(),
+            description: "Lookup".to_string(),
+            schema: json!({ "type": "object" }),
+            server: "legacy".to_string(),
+        }],
+        status: McpServerStatus::Active,
+    });
+    let state = AppState::new(
+        AppConfig::default(),
+        Arc::new(MockProvider::new()),
+        registry,
+    );
+    let app = shared_handlers::openai_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let url = format!("http://{}/chat/completions", addr);
+    let send = |body: Value| {
+        let url = url.clone();
+        async move {
+            let response = reqwest::Client::new()
+                .post(url)
+                .json(&body)
+                .send()
+                .await
+                .unwrap();
+            let status = response.status().as_u16();
+            (status, response.json::<Value>().await.unwrap())
+        }
+    };
+
+    let hello = json!({ "model": "gpt-4", "messages": [{ "role": "user", "content": "Hi" }] });
+    let (status, _) = send(hello.clone()).await;
+    assert_eq!(status, 200);
+
+    // tool_choice may still name an injected tool
+    let mut named = hello.clone();
+    named["tool_choice"] = json!({ "type": "function", "function": { "name": "lookup.v2" } });
+    let (status, _) = send(named).await;
+    assert_eq!(status, 200);
+
+    // Client tools are still validated
+    let mut invalid = hello;
+    invalid["tools"] = json!([tool("lookup.v2")]);
+    let (status, error) = send(invalid).await;
+    assert_eq!(status, 400);
+    assert_eq!(error["error"]["param"], "tools[0].function.name");
+}
@@ -8,6 +8,7 @@ pub struct AIProxyServer {
     port: u16,
     is_running: bool,
     server_handle: Option<tokio::task::JoinHandle<()>>,
+    data_dir: Option<std::path::PathBuf>,
 }
 
 impl AIProxyServer {
@@ -16,9 +17,16 @@ impl AIProxyServer {
             port: 8080, // Different port from main app
             is_running: false,
             server_handle: None,
+            data_dir: None,
         }
     }
 
+    // Keep persistent AI state (RAG index, threads) in the app's data directory
+    pub fn with_data_dir(mut self, data_dir: std::path::PathBuf) -> Self {
+        self.data_dir = Some(data_dir);
+        self
+    }
+
     pub async fn start(&mut self) -> Result<u16, Box<dyn std::error::Error + Send + Sync>> {
         log::info!("🤖 Starting AI Proxy server on port {}", self.port);
         
@@ -62,13 +70,21 @@ impl AIProxyServer {
     async fn create_ai_router(&self) -> Result<Router, Box<dyn std::error::Error + Send + Sync>> {
         log::info!("🏗️ Building AI Proxy router...");
         
+        // Shared state (MCP registry, RAG, provider) is built once per server
+        let mut config = shared_handlers::config::AppConfig::from_env();
+        if let Some(data_dir) = &self.data_dir {
+            config = config.with_data_dir(data_dir.clone());
+        }
+        let state = shared_handlers::state::AppState::from_config(config).await;
+        
         let app = Router::new()
-            // OpenAI-compatible endpoints for assistant-ui/ag-ui
-            .route("/v1/chat/completions", axum::routing::post(shared_handlers::ai::chat_completions_handler))
+            // OpenAI-compatible endpoints for assistant-ui/ag-ui, plus persistent threads
+            .nest_service("/v1", shared_handlers::openai_router(state.clone()))
             // Legacy endpoints
             .route("/ai/chat", axum::routing::post(shared_handlers::ai::ai_chat_handler))
             .route("/ai/stream", axum::routing::post(shared_handlers::ai::ai_stream_handler))
             .route("/ai/health", axum::routing::get(shared_handlers::ai::ai_health_handler))
+            .with_state(state)
             .layer(ServiceBuilder::new().layer(CorsLayer::permissive()));
         
         log::info!("✅ AI Proxy router created");
@@ -67,10 +67,14 @@ pub fn run() {
 
       // Start the AI proxy server in a background task
       let ai_server_state_clone = ai_server_state.clone();
+      let ai_data_dir = app.path().app_data_dir().ok();
       tauri::async_runtime::spawn(async move {
         log::info!("🤖 Initializing AI Proxy server...");
         
         let mut server = AIProxyServer::new();
+        if let Some(data_dir) = ai_data_dir {
+          server = server.with_data_dir(data_dir);
+        }
         match server.start().await {
           Ok(port) => {
             log::info!("✅ AI Proxy server started successfully on port {}", port);
@@ -21,9 +21,14 @@ mod api_health_check;
 async fn main() {
     println!("🚀 SPA Server starting...");
 
+    // Shared AI state (MCP registry, RAG, provider) is built once at startup
+    let ai_state = shared_handlers::state::AppState::from_env().await;
+
     let router = Router::new()
         // API routes
         .route("/api/health_check", get(api_health_check::get_tuono_internal_api))
+        // OpenAI-compatible endpoints (and persistent threads) served by the shared handlers
+        .nest_service("/v1", shared_handlers::openai_router(ai_state))
         // Serve static assets from dist directory
         .nest_service("/assets", ServeDir::new("dist/assets"))
         .nest_service("/favicon.ico", ServeDir::new("dist/favicon.ico"))